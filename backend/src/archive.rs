@@ -0,0 +1,232 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+/// Archive formats the scanner looks inside
+pub fn is_archive_extension(extension: &str) -> bool {
+    matches!(extension, "zip" | "7z")
+}
+
+/// Archive formats an upload is unpacked from
+pub fn is_upload_archive_extension(extension: &str) -> bool {
+    is_archive_extension(extension) || extension == "tar"
+}
+
+/// A file stored in an archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveListing {
+    pub name: String,
+    pub size: u64,
+}
+
+fn zip_error(e: zip::result::ZipError) -> io::Error {
+    io::Error::other(e)
+}
+
+fn sevenz_error(e: sevenz_rust::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// List the files in an archive without decompressing anything
+pub fn list_archive(path: &Path, extension: &str) -> io::Result<Vec<ArchiveListing>> {
+    match extension {
+        "zip" => {
+            let mut archive = ZipArchive::new(BufReader::new(File::open(path)?)).map_err(zip_error)?;
+            let mut entries = Vec::with_capacity(archive.len());
+            for index in 0..archive.len() {
+                let entry = archive.by_index_raw(index).map_err(zip_error)?;
+                if entry.is_file() {
+                    entries.push(ArchiveListing {
+                        name: entry.name().to_string(),
+                        size: entry.size(),
+                    });
+                }
+            }
+            Ok(entries)
+        }
+        "7z" => {
+            let reader = SevenZReader::open(path, Password::empty()).map_err(sevenz_error)?;
+            Ok(reader
+                .archive()
+                .files
+                .iter()
+                .filter(|entry| !entry.is_directory())
+                .map(|entry| ArchiveListing {
+                    name: entry.name().to_string(),
+                    size: entry.size(),
+                })
+                .collect())
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "not an archive")),
+    }
+}
+
+/// Run `read` on the decompressed contents of one archive entry
+pub fn with_archive_entry<T>(
+    path: &Path,
+    extension: &str,
+    entry_name: &str,
+    read: impl FnOnce(&mut dyn Read) -> io::Result<T>,
+) -> io::Result<T> {
+    match extension {
+        "zip" => {
+            let mut archive = ZipArchive::new(BufReader::new(File::open(path)?)).map_err(zip_error)?;
+            let mut entry = archive.by_name(entry_name).map_err(zip_error)?;
+            read(&mut entry)
+        }
+        "7z" => {
+            let mut reader = SevenZReader::open(path, Password::empty()).map_err(sevenz_error)?;
+            // Solid archives can only be decoded front to back, so walk until the entry turns up
+            let mut read = Some(read);
+            let mut result = None;
+            reader
+                .for_each_entries(|entry, data| {
+                    if entry.name() != entry_name {
+                        return Ok(true);
+                    }
+                    if let Some(read) = read.take() {
+                        result = Some(read(data));
+                    }
+                    Ok(false)
+                })
+                .map_err(sevenz_error)?;
+            result.unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::NotFound, "entry not in archive")))
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "not an archive")),
+    }
+}
+
+/// Run `visit` on every file in an archive, in the order they are stored, with its
+/// name and decompressed contents. Stops at the first error `visit` returns.
+pub fn for_each_archive_file(
+    path: &Path,
+    extension: &str,
+    mut visit: impl FnMut(&str, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    match extension {
+        "zip" => {
+            let mut archive = ZipArchive::new(BufReader::new(File::open(path)?)).map_err(zip_error)?;
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index).map_err(zip_error)?;
+                if entry.is_file() {
+                    let name = entry.name().to_string();
+                    visit(&name, &mut entry)?;
+                }
+            }
+            Ok(())
+        }
+        "7z" => {
+            let mut reader = SevenZReader::open(path, Password::empty()).map_err(sevenz_error)?;
+            // The callback's error type is sevenz's, so keep visit's own error to hand back
+            let mut failed = None;
+            reader
+                .for_each_entries(|entry, data| {
+                    if entry.is_directory() {
+                        return Ok(true);
+                    }
+                    match visit(entry.name(), data) {
+                        Ok(()) => Ok(true),
+                        Err(e) => {
+                            failed = Some(e);
+                            Ok(false)
+                        }
+                    }
+                })
+                .map_err(sevenz_error)?;
+            failed.map_or(Ok(()), Err)
+        }
+        "tar" => {
+            let mut archive = tar::Archive::new(BufReader::new(File::open(path)?));
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path()?.to_string_lossy().into_owned();
+                visit(&name, &mut entry)?;
+            }
+            Ok(())
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "not an archive")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_zip_listing_and_reading() {
+        let path = std::env::temp_dir().join(format!("archive-test-{}.zip", std::process::id()));
+        {
+            let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            zip.add_directory("docs/", options).unwrap();
+            zip.start_file("docs/readme.txt", options).unwrap();
+            zip.write_all(b"hello").unwrap();
+            zip.start_file("Tetris (World).gb", options).unwrap();
+            zip.write_all(&[1, 2, 3, 4]).unwrap();
+            zip.finish().unwrap();
+        }
+
+        let listing = list_archive(&path, "zip");
+        let contents = with_archive_entry(&path, "zip", "Tetris (World).gb", |r| {
+            let mut data = Vec::new();
+            r.read_to_end(&mut data)?;
+            Ok(data)
+        });
+        let missing = with_archive_entry(&path, "zip", "nope.gb", |_| Ok(()));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            listing.unwrap(),
+            vec![
+                ArchiveListing { name: "docs/readme.txt".to_string(), size: 5 },
+                ArchiveListing { name: "Tetris (World).gb".to_string(), size: 4 },
+            ]
+        );
+        assert_eq!(contents.unwrap(), vec![1, 2, 3, 4]);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_for_each_tar_file() {
+        let path = std::env::temp_dir().join(format!("archive-test-{}.tar", std::process::id()));
+        {
+            let mut tar = tar::Builder::new(File::create(&path).unwrap());
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            tar.append_data(&mut header, "discs/", io::empty()).unwrap();
+            for (name, data) in [("discs/Disc 1.cue", &b"FILE"[..]), ("Tetris (World).gb", &[1, 2, 3, 4][..])] {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                tar.append_data(&mut header, name, data).unwrap();
+            }
+            tar.finish().unwrap();
+        }
+
+        let mut files = Vec::new();
+        let walked = for_each_archive_file(&path, "tar", |name, reader| {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            files.push((name.to_string(), data));
+            Ok(())
+        });
+        let stopped = for_each_archive_file(&path, "tar", |_, _| Err(io::ErrorKind::Other.into()));
+        std::fs::remove_file(&path).unwrap();
+
+        walked.unwrap();
+        assert_eq!(
+            files,
+            vec![
+                ("discs/Disc 1.cue".to_string(), b"FILE".to_vec()),
+                ("Tetris (World).gb".to_string(), vec![1, 2, 3, 4]),
+            ]
+        );
+        assert!(stopped.is_err());
+    }
+}
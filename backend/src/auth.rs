@@ -0,0 +1,311 @@
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts},
+    http::{header, request::Parts, StatusCode},
+    response::Json,
+};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::{Arc, OnceLock};
+use rand::{distributions::Alphanumeric, Rng};
+
+/// Access tokens are short lived; clients renew them with the refresh token
+pub const ACCESS_TOKEN_TTL_SECS: i64 = 60 * 60;
+pub const REFRESH_TOKEN_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// The placeholder older .env files and docker-compose setups shipped with. It is public,
+/// so it is treated like no secret at all.
+const PLACEHOLDER_JWT_SECRET: &str = "your-secret-key-change-this";
+
+const GENERATED_SECRET_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
+}
+
+/// Account roles, from most to least privileged
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can scan, edit the library and manage users
+    Admin,
+    /// Can add ROMs on top of what players can do
+    Uploader,
+    /// Can browse and play
+    Player,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Uploader => "uploader",
+            Role::Player => "player",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        match value {
+            "admin" => Some(Role::Admin),
+            "uploader" => Some(Role::Uploader),
+            "player" => Some(Role::Player),
+            _ => None,
+        }
+    }
+
+    pub fn can_upload(self) -> bool {
+        matches!(self, Role::Admin | Role::Uploader)
+    }
+
+    pub fn can_manage_library(self) -> bool {
+        self == Role::Admin
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    pub sub: i32,
+    pub username: String,
+    pub role: Role,
+    pub token_type: TokenType,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// The user behind a valid access token. Use as an extractor on any handler
+/// that must only be reachable by logged-in users.
+///
+/// The role comes from the token, so a role change takes effect once the
+/// user's access token is refreshed.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: i32,
+    pub username: String,
+    pub role: Role,
+}
+
+impl AuthUser {
+    /// Reject users that are not allowed to add ROMs
+    pub fn require_uploader(&self) -> Result<(), StatusCode> {
+        if self.role.can_upload() {
+            Ok(())
+        } else {
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+
+    /// Reject users that are not allowed to scan or edit the library
+    pub fn require_admin(&self) -> Result<(), StatusCode> {
+        if self.role.can_manage_library() {
+            Ok(())
+        } else {
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+}
+
+/// The configured JWT_SECRET, unless it is unset, empty or the public placeholder
+fn configured_secret() -> Option<String> {
+    std::env::var("JWT_SECRET")
+        .ok()
+        .filter(|secret| !secret.trim().is_empty() && secret != PLACEHOLDER_JWT_SECRET)
+}
+
+/// The signing secret: JWT_SECRET, or without a usable one a random secret made once per
+/// process, so tokens can't be forged but everyone is logged out when the server restarts
+pub fn jwt_secret() -> String {
+    static SECRET: OnceLock<String> = OnceLock::new();
+    SECRET
+        .get_or_init(|| {
+            configured_secret().unwrap_or_else(|| {
+                rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(GENERATED_SECRET_LEN)
+                    .map(char::from)
+                    .collect()
+            })
+        })
+        .clone()
+}
+
+/// Whether tokens are signed with a generated secret because JWT_SECRET isn't usable
+pub fn using_generated_secret() -> bool {
+    configured_secret().is_none()
+}
+
+fn encode_token(
+    secret: &str,
+    user_id: i32,
+    username: &str,
+    role: Role,
+    token_type: TokenType,
+    ttl_secs: i64,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let claims = Claims {
+        sub: user_id,
+        username: username.to_string(),
+        role,
+        token_type,
+        iat: now,
+        exp: now + ttl_secs,
+    };
+
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes()))
+}
+
+/// Issue a fresh access/refresh token pair for a user
+pub fn issue_tokens(user_id: i32, username: &str, role: Role) -> Result<TokenPair, StatusCode> {
+    let secret = jwt_secret();
+    let issue = |token_type, ttl| {
+        encode_token(&secret, user_id, username, role, token_type, ttl).map_err(|e| {
+            eprintln!("Error signing token: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+    };
+
+    Ok(TokenPair {
+        access_token: issue(TokenType::Access, ACCESS_TOKEN_TTL_SECS)?,
+        refresh_token: issue(TokenType::Refresh, REFRESH_TOKEN_TTL_SECS)?,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_TTL_SECS,
+    })
+}
+
+/// Verify a token's signature and expiry and make sure it is of the expected type
+fn verify_token(secret: &str, token: &str, expected: TokenType) -> Option<Claims> {
+    let claims = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .ok()?
+    .claims;
+
+    (claims.token_type == expected).then_some(claims)
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        let claims = verify_token(&jwt_secret(), token, TokenType::Access)
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        Ok(AuthUser {
+            user_id: claims.sub,
+            username: claims.username,
+            role: claims.role,
+        })
+    }
+}
+
+/// Exchange a refresh token for a new token pair
+pub async fn refresh(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Json(req): Json<RefreshRequest>,
+) -> Result<Json<TokenPair>, StatusCode> {
+    let claims = verify_token(&jwt_secret(), &req.refresh_token, TokenType::Refresh)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    // The account may have been deleted, renamed or had its role changed since the token was issued
+    let user = sqlx::query_as::<_, (i32, String, String)>(
+        "SELECT id, username, role FROM users WHERE id = $1"
+    )
+    .bind(claims.sub)
+    .fetch_optional(pool.as_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Database error during token refresh: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if user.1 != claims.username {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let role = Role::parse(&user.2).ok_or(StatusCode::UNAUTHORIZED)?;
+    issue_tokens(user.0, &user.1, role).map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+
+    #[test]
+    fn test_access_token_round_trip() {
+        let token = encode_token(SECRET, 7, "alice", Role::Player, TokenType::Access, 60).unwrap();
+        let claims = verify_token(SECRET, &token, TokenType::Access).unwrap();
+        assert_eq!(claims.sub, 7);
+        assert_eq!(claims.username, "alice");
+    }
+
+    #[test]
+    fn test_token_type_is_enforced() {
+        let refresh = encode_token(SECRET, 7, "alice", Role::Player, TokenType::Refresh, 60).unwrap();
+        assert!(verify_token(SECRET, &refresh, TokenType::Access).is_none());
+        assert!(verify_token(SECRET, &refresh, TokenType::Refresh).is_some());
+    }
+
+    #[test]
+    fn test_rejects_wrong_secret_and_expired_tokens() {
+        let token = encode_token(SECRET, 7, "alice", Role::Player, TokenType::Access, 60).unwrap();
+        assert!(verify_token("other-secret", &token, TokenType::Access).is_none());
+
+        // Well past the default 60s leeway
+        let expired = encode_token(SECRET, 7, "alice", Role::Player, TokenType::Access, -3600).unwrap();
+        assert!(verify_token(SECRET, &expired, TokenType::Access).is_none());
+    }
+
+    #[test]
+    fn test_placeholder_secret_is_never_used() {
+        let secret = jwt_secret();
+        assert!(!secret.is_empty());
+        assert_ne!(secret, PLACEHOLDER_JWT_SECRET);
+        assert_eq!(jwt_secret(), secret);
+
+        let forged = encode_token(PLACEHOLDER_JWT_SECRET, 1, "mallory", Role::Admin, TokenType::Access, 60).unwrap();
+        assert!(verify_token(&secret, &forged, TokenType::Access).is_none());
+    }
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(Role::Admin.can_upload() && Role::Admin.can_manage_library());
+        assert!(Role::Uploader.can_upload() && !Role::Uploader.can_manage_library());
+        assert!(!Role::Player.can_upload() && !Role::Player.can_manage_library());
+        assert_eq!(Role::parse(Role::Uploader.as_str()), Some(Role::Uploader));
+        assert_eq!(Role::parse("root"), None);
+    }
+}
@@ -0,0 +1,283 @@
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::Json,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+use crate::auth::AuthUser;
+use crate::emulators::get_emulator_by_id;
+use crate::rom_hash::hash_reader;
+
+/// Name of the firmware directory inside ROMS_PATH; the scanner leaves it alone
+pub const BIOS_DIR_NAME: &str = "bios";
+
+/// A firmware file an emulator looks for, with the MD5s of known good dumps.
+/// An empty list means any file of that name is accepted (keys, console-specific dumps).
+pub struct BiosFile {
+    pub name: &'static str,
+    pub md5: &'static [&'static str],
+}
+
+/// One thing an emulator needs; any of the listed files satisfies it
+pub struct BiosRequirement {
+    pub description: &'static str,
+    /// Games won't boot without it (as opposed to a few features or games needing it)
+    pub required: bool,
+    pub any_of: &'static [BiosFile],
+}
+
+const PS1_BIOS: &[BiosRequirement] = &[BiosRequirement {
+    description: "PlayStation BIOS (any region)",
+    required: true,
+    any_of: &[
+        BiosFile { name: "scph5501.bin", md5: &["490f666e1afb15b7362b406ed1cea246"] },
+        BiosFile { name: "scph5500.bin", md5: &["8dd7d5296a650fac7319bce665a6a53c"] },
+        BiosFile { name: "scph5502.bin", md5: &["32736f17079d0b2b7024407c39bd3050"] },
+        BiosFile { name: "scph1001.bin", md5: &["924e392ed05558ffdb115408c263dccf"] },
+        BiosFile { name: "scph7001.bin", md5: &["1e68c231d0896b7eadcad1d7d8e76129"] },
+        BiosFile { name: "scph101.bin", md5: &["6e3735ff4c7dc899ee98981385f6f3d0"] },
+    ],
+}];
+
+const PS2_BIOS: &[BiosRequirement] = &[BiosRequirement {
+    description: "PlayStation 2 BIOS (any region)",
+    required: true,
+    any_of: &[
+        BiosFile { name: "ps2-0230a-20080220.bin", md5: &["21038400dc633070a78ad53090c53017"] },
+        BiosFile { name: "ps2-0230e-20080220.bin", md5: &["dc69f0643a3030aaa4797501b483d6c4"] },
+        BiosFile { name: "ps2-0230j-20080220.bin", md5: &["80ac46fa7e77b8ab4366e86948e54f83"] },
+        BiosFile { name: "ps2-0250e-20100415.bin", md5: &["91c87cb2f2eb6ce529a2360f80ce2457"] },
+    ],
+}];
+
+const DREAMCAST_BIOS: &[BiosRequirement] = &[
+    BiosRequirement {
+        description: "Dreamcast boot ROM",
+        required: true,
+        any_of: &[BiosFile { name: "dc_boot.bin", md5: &["e10c53c2f8b90bab96ead2d368858623"] }],
+    },
+    BiosRequirement {
+        description: "Dreamcast flash ROM (region and settings; created if absent)",
+        required: false,
+        any_of: &[BiosFile { name: "dc_flash.bin", md5: &[] }],
+    },
+];
+
+const DS_BIOS: &[BiosRequirement] = &[
+    BiosRequirement {
+        description: "Nintendo DS ARM7 BIOS",
+        required: true,
+        any_of: &[BiosFile { name: "bios7.bin", md5: &["df692a80a5b1bc90728bc3dfc76cd948"] }],
+    },
+    BiosRequirement {
+        description: "Nintendo DS ARM9 BIOS",
+        required: true,
+        any_of: &[BiosFile { name: "bios9.bin", md5: &["a392174eb3e572fed6447e956bde4b25"] }],
+    },
+    BiosRequirement {
+        description: "Nintendo DS firmware (user settings differ per console)",
+        required: true,
+        any_of: &[BiosFile { name: "firmware.bin", md5: &[] }],
+    },
+];
+
+const N3DS_BIOS: &[BiosRequirement] = &[
+    BiosRequirement {
+        description: "3DS AES keys (needed for encrypted games)",
+        required: false,
+        any_of: &[BiosFile { name: "aes_keys.txt", md5: &[] }],
+    },
+    BiosRequirement {
+        description: "3DS shared system font",
+        required: false,
+        any_of: &[BiosFile { name: "shared_font.bin", md5: &[] }],
+    },
+];
+
+/// Firmware each emulator looks for; empty for emulators that need none
+pub fn bios_requirements(emulator_id: &str) -> &'static [BiosRequirement] {
+    match emulator_id {
+        "duckstation" => PS1_BIOS,
+        "pcsx2" => PS2_BIOS,
+        "flycast" | "reicast" => DREAMCAST_BIOS,
+        "melonds" => DS_BIOS,
+        "citra" => N3DS_BIOS,
+        _ => &[],
+    }
+}
+
+/// Directory holding BIOS and firmware files, set with BIOS_PATH; `bios/` in the ROMs directory by default
+pub fn bios_path() -> PathBuf {
+    std::env::var("BIOS_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| crate::roms::roms_path().join(BIOS_DIR_NAME))
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BiosStatus {
+    Present,
+    Missing,
+    /// A file with the right name whose contents match no known good dump
+    WrongHash,
+}
+
+#[derive(Serialize)]
+pub struct BiosFileStatus {
+    pub file_name: String,
+    pub status: BiosStatus,
+    /// Where it was found, relative to the BIOS directory
+    pub path: Option<String>,
+    pub md5: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RequirementStatus {
+    pub description: String,
+    pub required: bool,
+    pub status: BiosStatus,
+    pub files: Vec<BiosFileStatus>,
+}
+
+#[derive(Serialize)]
+pub struct EmulatorRequirements {
+    pub emulator_id: String,
+    /// Every required file is present with a known good hash
+    pub ready: bool,
+    pub requirements: Vec<RequirementStatus>,
+}
+
+/// Check requirements against the files in `bios_dir`. Files are matched by name
+/// (case-insensitively) anywhere below it, so `bios/dc/dc_boot.bin` works as well.
+pub fn check_requirements(bios_dir: &std::path::Path, requirements: &[BiosRequirement]) -> Vec<RequirementStatus> {
+    let mut found: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for entry in WalkDir::new(bios_dir)
+        .max_depth(3)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        found.entry(name).or_default().push(entry.into_path());
+    }
+
+    requirements
+        .iter()
+        .map(|requirement| {
+            let files: Vec<BiosFileStatus> = requirement
+                .any_of
+                .iter()
+                .map(|file| check_file(bios_dir, file, found.get(&file.name.to_lowercase())))
+                .collect();
+            let status = if files.iter().any(|f| f.status == BiosStatus::Present) {
+                BiosStatus::Present
+            } else if files.iter().any(|f| f.status == BiosStatus::WrongHash) {
+                BiosStatus::WrongHash
+            } else {
+                BiosStatus::Missing
+            };
+            RequirementStatus {
+                description: requirement.description.to_string(),
+                required: requirement.required,
+                status,
+                files,
+            }
+        })
+        .collect()
+}
+
+fn check_file(bios_dir: &std::path::Path, file: &BiosFile, candidates: Option<&Vec<PathBuf>>) -> BiosFileStatus {
+    let mut status = BiosFileStatus {
+        file_name: file.name.to_string(),
+        status: BiosStatus::Missing,
+        path: None,
+        md5: None,
+    };
+    // With several copies of a name, a good one wins
+    for path in candidates.into_iter().flatten() {
+        let md5 = std::fs::File::open(path).and_then(hash_reader).map(|h| h.md5).ok();
+        let good = file.md5.is_empty() || md5.as_deref().is_some_and(|md5| file.md5.contains(&md5));
+        status.path = Some(
+            path.strip_prefix(bios_dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace('\\', "/"),
+        );
+        status.md5 = md5;
+        status.status = if good { BiosStatus::Present } else { BiosStatus::WrongHash };
+        if good {
+            break;
+        }
+    }
+    status
+}
+
+/// BIOS and firmware an emulator needs, and whether the BIOS directory has them
+pub async fn get_emulator_requirements(
+    _auth: AuthUser,
+    Path(emulator_id): Path<String>,
+) -> Result<Json<EmulatorRequirements>, StatusCode> {
+    let emulator = get_emulator_by_id(&emulator_id).ok_or(StatusCode::NOT_FOUND)?;
+    let requirements = bios_requirements(&emulator.id);
+
+    // Hashing a few firmware files is quick, but still blocking IO
+    let bios_dir = bios_path();
+    let requirements = tokio::task::spawn_blocking(move || check_requirements(&bios_dir, requirements))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(EmulatorRequirements {
+        emulator_id: emulator.id,
+        ready: requirements
+            .iter()
+            .all(|r| !r.required || r.status == BiosStatus::Present),
+        requirements,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_REQUIREMENTS: &[BiosRequirement] = &[
+        BiosRequirement {
+            description: "boot ROM",
+            required: true,
+            any_of: &[
+                BiosFile { name: "usa.bin", md5: &["5d41402abc4b2a76b9719d911017c592"] },
+                BiosFile { name: "jpn.bin", md5: &["5d41402abc4b2a76b9719d911017c592"] },
+            ],
+        },
+        BiosRequirement {
+            description: "keys",
+            required: false,
+            any_of: &[BiosFile { name: "keys.txt", md5: &[] }],
+        },
+        BiosRequirement {
+            description: "flash",
+            required: true,
+            any_of: &[BiosFile { name: "flash.bin", md5: &["5d41402abc4b2a76b9719d911017c592"] }],
+        },
+    ];
+
+    #[test]
+    fn test_check_requirements() {
+        let dir = std::env::temp_dir().join(format!("bios-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("USA.BIN"), b"not it").unwrap();
+        std::fs::write(dir.join("sub/jpn.bin"), b"hello").unwrap();
+        std::fs::write(dir.join("flash.bin"), b"corrupt").unwrap();
+
+        let statuses = check_requirements(&dir, TEST_REQUIREMENTS);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(statuses[0].status, BiosStatus::Present);
+        assert_eq!(statuses[0].files[0].status, BiosStatus::WrongHash);
+        assert_eq!(statuses[0].files[1].path.as_deref(), Some("sub/jpn.bin"));
+        assert_eq!(statuses[1].status, BiosStatus::Missing);
+        assert_eq!(statuses[2].status, BiosStatus::WrongHash);
+    }
+}
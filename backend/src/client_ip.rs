@@ -0,0 +1,204 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap, StatusCode},
+};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Used when TRUSTED_PROXIES is not set: only a proxy on the same host is trusted
+const DEFAULT_TRUSTED_PROXIES: &str = "127.0.0.1/32,::1/128";
+
+/// Networks whose forwarding headers (`cf-connecting-ip`, `x-forwarded-for`,
+/// `x-real-ip`) are believed. Requests from anywhere else are identified by
+/// their socket address only.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Parse a comma-separated list of CIDRs or bare addresses, skipping invalid entries
+    pub fn parse(list: &str) -> Self {
+        let networks = list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let parsed = entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from));
+                if parsed.is_err() {
+                    eprintln!("Warning: ignoring invalid trusted proxy '{}'", entry);
+                }
+                parsed.ok()
+            })
+            .collect();
+
+        TrustedProxies { networks }
+    }
+
+    /// Read TRUSTED_PROXIES, falling back to loopback only
+    pub fn from_env() -> Self {
+        Self::parse(
+            &std::env::var("TRUSTED_PROXIES").unwrap_or_else(|_| DEFAULT_TRUSTED_PROXIES.to_string()),
+        )
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        self.networks.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Work out the real client address for a request that arrived from `peer`
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = canonical(peer);
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        // Cloudflare Tunnel sets this to the visitor's address
+        if let Some(ip) = header_ip(headers, "cf-connecting-ip") {
+            return ip;
+        }
+
+        // Each proxy appends the address it received the request from, so walk
+        // from the right and stop at the first hop we don't trust
+        if let Some(xff) = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+            let hops: Vec<IpAddr> = xff
+                .split(',')
+                .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+                .map(canonical)
+                .collect();
+            if let Some(ip) = hops.iter().rev().find(|ip| !self.is_trusted(**ip)) {
+                return *ip;
+            }
+            if let Some(first) = hops.first() {
+                return *first;
+            }
+        }
+
+        if let Some(ip) = header_ip(headers, "x-real-ip") {
+            return ip;
+        }
+
+        peer
+    }
+}
+
+fn header_ip(headers: &HeaderMap, name: &str) -> Option<IpAddr> {
+    headers
+        .get(name)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<IpAddr>()
+        .ok()
+        .map(canonical)
+}
+
+/// Treat IPv4-mapped IPv6 addresses (::ffff:a.b.c.d) as plain IPv4
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+/// The caller's address, resolved through trusted proxies. Needs the server to
+/// be started with `into_make_service_with_connect_info::<SocketAddr>()` and an
+/// `Extension<Arc<TrustedProxies>>` layer.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+            .ok_or_else(|| {
+                eprintln!("ClientIp used without connect info");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        let ip = match parts.extensions.get::<Arc<TrustedProxies>>() {
+            Some(proxies) => proxies.client_ip(peer, &parts.headers),
+            None => canonical(peer),
+        };
+
+        Ok(ClientIp(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_untrusted_peer_headers_are_ignored() {
+        let proxies = TrustedProxies::parse("172.16.0.0/12");
+        let spoofed = headers(&[
+            ("cf-connecting-ip", "1.1.1.1"),
+            ("x-forwarded-for", "2.2.2.2"),
+            ("x-real-ip", "3.3.3.3"),
+        ]);
+        assert_eq!(proxies.client_ip(ip("203.0.113.9"), &spoofed), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn test_trusted_peer_headers_are_used() {
+        let proxies = TrustedProxies::parse("172.16.0.0/12, 10.0.0.1");
+        let peer = ip("172.18.0.5");
+
+        assert_eq!(
+            proxies.client_ip(peer, &headers(&[("cf-connecting-ip", "198.51.100.7")])),
+            ip("198.51.100.7")
+        );
+        assert_eq!(
+            proxies.client_ip(peer, &headers(&[("x-real-ip", "198.51.100.8")])),
+            ip("198.51.100.8")
+        );
+        assert_eq!(proxies.client_ip(peer, &HeaderMap::new()), peer);
+    }
+
+    #[test]
+    fn test_forwarded_for_skips_trusted_hops_from_the_right() {
+        let proxies = TrustedProxies::parse("172.16.0.0/12,10.0.0.1/32");
+        let peer = ip("172.18.0.5");
+
+        // The left-most entry is client supplied and must not win
+        let xff = headers(&[("x-forwarded-for", "6.6.6.6, 198.51.100.7, 10.0.0.1")]);
+        assert_eq!(proxies.client_ip(peer, &xff), ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn test_ipv4_mapped_addresses_and_invalid_entries() {
+        let proxies = TrustedProxies::parse("127.0.0.1/32, not-a-network");
+        assert!(proxies.is_trusted(ip("::ffff:127.0.0.1")));
+        assert!(!proxies.is_trusted(ip("127.0.0.2")));
+        assert_eq!(
+            proxies.client_ip(ip("::ffff:127.0.0.1"), &headers(&[("x-real-ip", "garbage")])),
+            ip("127.0.0.1")
+        );
+    }
+}
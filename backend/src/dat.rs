@@ -0,0 +1,486 @@
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    response::Json,
+};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::auth::AuthUser;
+use crate::rom_hash::RomHashes;
+use crate::rom_tags::{parse_tags, RomTags};
+
+/// Rows per INSERT when loading DAT entries
+const INSERT_CHUNK_SIZE: usize = 5000;
+
+/// Games looked up (and updated) per statement when identifying in bulk
+const IDENTIFY_CHUNK_SIZE: usize = 5000;
+
+/// Header of a Logiqx XML DAT (No-Intro, Redump, TOSEC, MAME all use this layout)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DatHeader {
+    pub name: String,
+    pub description: Option<String>,
+    pub version: Option<String>,
+}
+
+/// One `<rom>` of a `<game>`, flattened with the game it belongs to
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DatEntry {
+    pub game_name: String,
+    pub rom_name: String,
+    pub size: Option<i64>,
+    pub crc32: Option<String>,
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+    /// `verified`, `good`, `baddump` or `nodump` when the DAT says so
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct DatFile {
+    pub header: DatHeader,
+    pub entries: Vec<DatEntry>,
+}
+
+/// What a matched DAT entry tells us about a ROM
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct DatMatch {
+    pub dat_name: String,
+    pub game_name: String,
+    pub title: String,
+    pub region: Option<String>,
+    pub languages: Option<String>,
+    pub revision: Option<String>,
+    pub status: Option<String>,
+}
+
+impl DatMatch {
+    pub fn is_bad_dump(&self) -> bool {
+        matches!(self.status.as_deref(), Some("baddump") | Some("nodump"))
+    }
+
+    /// Tags from the canonical DAT name, which are more reliable than the file name's
+    pub fn tags(&self) -> RomTags {
+        RomTags {
+            verified: self.status.as_deref() == Some("verified"),
+            bad_dump: self.is_bad_dump(),
+            ..parse_tags(&self.game_name)
+        }
+    }
+}
+
+/// A DAT match together with the game it was found for
+#[derive(sqlx::FromRow)]
+struct GameMatch {
+    game_id: i32,
+    #[sqlx(flatten)]
+    dat_match: DatMatch,
+}
+
+#[derive(Serialize)]
+pub struct DatImportResult {
+    pub files_imported: usize,
+    pub entries_imported: usize,
+    pub games_identified: usize,
+    pub errors: Vec<String>,
+}
+
+/// Canonical title, region, languages and revision as encoded in a No-Intro/Redump game name,
+/// e.g. "Legend of Zelda, The (USA) (Rev 1)" or "Tetris (Japan) (En,Ja)"
+pub fn split_game_name(name: &str) -> (String, Option<String>, Option<String>, Option<String>) {
+    let title = name.split(" (").next().unwrap_or(name).trim().to_string();
+    let tags = parse_tags(name);
+    let region = (!tags.regions.is_empty()).then(|| tags.regions.join(", "));
+    let languages = (!tags.languages.is_empty()).then(|| tags.languages.join(","));
+
+    (title, region, languages, tags.revision)
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attr| attr.key.as_ref() == name)
+        .and_then(|attr| attr.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+fn lowercase_hash(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty() && v != "-")
+}
+
+/// Parse a Logiqx XML DAT, streaming so large Redump DATs don't need to be held as a DOM
+pub fn parse_dat<R: BufRead>(source: R) -> Result<DatFile, String> {
+    let mut reader = Reader::from_reader(source);
+    reader.trim_text(true);
+
+    let mut dat = DatFile::default();
+    let mut buf = Vec::new();
+    let mut in_header = false;
+    let mut current_game: Option<String> = None;
+    let mut text_target: Option<&'static str> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => match e.name().as_ref() {
+                b"header" => in_header = true,
+                b"game" | b"machine" => current_game = attribute(&e, b"name"),
+                b"name" if in_header => text_target = Some("name"),
+                b"description" if in_header => text_target = Some("description"),
+                b"version" if in_header => text_target = Some("version"),
+                b"rom" => {
+                    if let Some(game) = &current_game {
+                        dat.entries.push(rom_entry(&e, game));
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"rom" => {
+                if let Some(game) = &current_game {
+                    dat.entries.push(rom_entry(&e, game));
+                }
+            }
+            Ok(Event::Text(t)) => {
+                if let Some(target) = text_target {
+                    let text = t.unescape().map_err(|e| e.to_string())?.into_owned();
+                    match target {
+                        "name" => dat.header.name = text,
+                        "description" => dat.header.description = Some(text),
+                        _ => dat.header.version = Some(text),
+                    }
+                }
+            }
+            Ok(Event::End(e)) => match e.name().as_ref() {
+                b"header" => in_header = false,
+                b"game" | b"machine" => current_game = None,
+                _ => text_target = None,
+            },
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                return Err(format!("XML error at byte {}: {}", reader.buffer_position(), e));
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    if dat.header.name.is_empty() {
+        return Err("DAT has no <header><name>".to_string());
+    }
+    Ok(dat)
+}
+
+fn rom_entry(element: &BytesStart, game_name: &str) -> DatEntry {
+    DatEntry {
+        game_name: game_name.to_string(),
+        rom_name: attribute(element, b"name").unwrap_or_default(),
+        size: attribute(element, b"size").and_then(|s| s.parse().ok()),
+        crc32: lowercase_hash(attribute(element, b"crc")),
+        md5: lowercase_hash(attribute(element, b"md5")),
+        sha1: lowercase_hash(attribute(element, b"sha1")),
+        status: attribute(element, b"status").map(|s| s.to_lowercase()),
+    }
+}
+
+/// Directory holding the DAT files, set with DATS_PATH
+pub fn dats_path() -> PathBuf {
+    PathBuf::from(std::env::var("DATS_PATH").unwrap_or_else(|_| "/dats".to_string()))
+}
+
+/// Store a parsed DAT, replacing any earlier import of a DAT with the same name
+pub async fn store_dat(pool: &PgPool, file_name: &str, dat: &DatFile) -> Result<usize, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let (dat_id,) = sqlx::query_as::<_, (i32,)>(
+        "INSERT INTO dat_files (name, description, version, file_name, entry_count, imported_at)
+         VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP)
+         ON CONFLICT (name) DO UPDATE
+         SET description = EXCLUDED.description, version = EXCLUDED.version,
+             file_name = EXCLUDED.file_name, entry_count = EXCLUDED.entry_count,
+             imported_at = CURRENT_TIMESTAMP
+         RETURNING id"
+    )
+    .bind(&dat.header.name)
+    .bind(&dat.header.description)
+    .bind(&dat.header.version)
+    .bind(file_name)
+    .bind(dat.entries.len() as i32)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM dat_entries WHERE dat_id = $1")
+        .bind(dat_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for chunk in dat.entries.chunks(INSERT_CHUNK_SIZE) {
+        let mut game_names = Vec::with_capacity(chunk.len());
+        let mut titles = Vec::with_capacity(chunk.len());
+        let mut regions = Vec::with_capacity(chunk.len());
+        let mut languages = Vec::with_capacity(chunk.len());
+        let mut revisions = Vec::with_capacity(chunk.len());
+        for entry in chunk {
+            let (title, region, langs, revision) = split_game_name(&entry.game_name);
+            game_names.push(entry.game_name.clone());
+            titles.push(title);
+            regions.push(region);
+            languages.push(langs);
+            revisions.push(revision);
+        }
+
+        sqlx::query(
+            "INSERT INTO dat_entries
+                 (dat_id, game_name, title, region, languages, revision, rom_name, size, crc32, md5, sha1, status)
+             SELECT $1, * FROM UNNEST(
+                 $2::text[], $3::text[], $4::text[], $5::text[], $6::text[],
+                 $7::text[], $8::bigint[], $9::text[], $10::text[], $11::text[], $12::text[])"
+        )
+        .bind(dat_id)
+        .bind(&game_names)
+        .bind(&titles)
+        .bind(&regions)
+        .bind(&languages)
+        .bind(&revisions)
+        .bind(chunk.iter().map(|e| e.rom_name.clone()).collect::<Vec<_>>())
+        .bind(chunk.iter().map(|e| e.size).collect::<Vec<_>>())
+        .bind(chunk.iter().map(|e| e.crc32.clone()).collect::<Vec<_>>())
+        .bind(chunk.iter().map(|e| e.md5.clone()).collect::<Vec<_>>())
+        .bind(chunk.iter().map(|e| e.sha1.clone()).collect::<Vec<_>>())
+        .bind(chunk.iter().map(|e| e.status.clone()).collect::<Vec<_>>())
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(dat.entries.len())
+}
+
+/// Look games up in the imported DATs, given as (game id, hashes, size), and store
+/// the matches. Each chunk of games is matched with one join against dat_entries,
+/// preferring the strongest hash that matches, and updated with one statement.
+/// Returns the games that matched.
+pub async fn identify_games(pool: &PgPool, games: &[(i32, &RomHashes, i64)]) -> Result<Vec<(i32, DatMatch)>, sqlx::Error> {
+    let mut matches = Vec::new();
+    for chunk in games.chunks(IDENTIFY_CHUNK_SIZE) {
+        let found = find_matches(pool, chunk).await?;
+        apply_matches(pool, &found).await?;
+        matches.extend(found);
+    }
+    Ok(matches)
+}
+
+async fn find_matches(pool: &PgPool, games: &[(i32, &RomHashes, i64)]) -> Result<Vec<(i32, DatMatch)>, sqlx::Error> {
+    let found = sqlx::query_as::<_, GameMatch>(
+        "SELECT DISTINCT ON (g.id)
+                g.id AS game_id, d.name AS dat_name, e.game_name, e.title, e.region, e.languages, e.revision, e.status
+         FROM UNNEST($1::int[], $2::text[], $3::text[], $4::text[], $5::bigint[]) AS g(id, sha1, md5, crc32, size)
+         JOIN dat_entries e ON e.sha1 = g.sha1 OR e.md5 = g.md5 OR (e.crc32 = g.crc32 AND e.size = g.size)
+         JOIN dat_files d ON d.id = e.dat_id
+         ORDER BY g.id, (e.sha1 = g.sha1) IS TRUE DESC, (e.md5 = g.md5) IS TRUE DESC"
+    )
+    .bind(games.iter().map(|(id, _, _)| *id).collect::<Vec<_>>())
+    .bind(games.iter().map(|(_, hashes, _)| hashes.sha1.clone()).collect::<Vec<_>>())
+    .bind(games.iter().map(|(_, hashes, _)| hashes.md5.clone()).collect::<Vec<_>>())
+    .bind(games.iter().map(|(_, hashes, _)| hashes.crc32.clone()).collect::<Vec<_>>())
+    .bind(games.iter().map(|(_, _, size)| *size).collect::<Vec<_>>())
+    .fetch_all(pool)
+    .await?;
+    Ok(found.into_iter().map(|m| (m.game_id, m.dat_match)).collect())
+}
+
+/// Store DAT matches on their games: canonical title, the DAT details under
+/// `metadata.dat` and the tags of the DAT name under `metadata.tags`
+async fn apply_matches(pool: &PgPool, matches: &[(i32, DatMatch)]) -> Result<(), sqlx::Error> {
+    if matches.is_empty() {
+        return Ok(());
+    }
+    sqlx::query(
+        "UPDATE games g
+         SET title = m.title,
+             metadata = COALESCE(g.metadata, '{}'::jsonb) || jsonb_build_object('dat', m.dat, 'tags', m.tags)
+         FROM UNNEST($1::int[], $2::text[], $3::jsonb[], $4::jsonb[]) AS m(id, title, dat, tags)
+         WHERE g.id = m.id"
+    )
+    .bind(matches.iter().map(|(id, _)| *id).collect::<Vec<_>>())
+    .bind(matches.iter().map(|(_, m)| m.title.clone()).collect::<Vec<_>>())
+    .bind(matches.iter().map(|(_, m)| serde_json::to_value(m).unwrap_or_default()).collect::<Vec<_>>())
+    .bind(matches.iter().map(|(_, m)| serde_json::to_value(m.tags()).unwrap_or_default()).collect::<Vec<_>>())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Whether any DAT has been imported; without one every ROM would look unmatched
+pub async fn has_dats(pool: &PgPool) -> bool {
+    sqlx::query_as::<_, (bool,)>("SELECT EXISTS (SELECT 1 FROM dat_files)")
+        .fetch_one(pool)
+        .await
+        .map(|(exists,)| exists)
+        .unwrap_or(false)
+}
+
+/// Re-run identification for every hashed game, e.g. after importing new DATs
+async fn identify_all_games(pool: &PgPool, errors: &mut Vec<String>) -> usize {
+    let games = match sqlx::query_as::<_, (i32, String, String, String, Option<i64>)>(
+        "SELECT id, crc32, md5, sha1, file_size FROM games WHERE sha1 IS NOT NULL"
+    )
+    .fetch_all(pool)
+    .await
+    {
+        Ok(games) => games,
+        Err(e) => {
+            errors.push(format!("Failed to load games: {}", e));
+            return 0;
+        }
+    };
+
+    let hashes: Vec<(i32, RomHashes, i64)> = games
+        .into_iter()
+        .map(|(id, crc32, md5, sha1, size)| (id, RomHashes { crc32, md5, sha1 }, size.unwrap_or(-1)))
+        .collect();
+    let lookups: Vec<(i32, &RomHashes, i64)> = hashes.iter().map(|(id, hashes, size)| (*id, hashes, *size)).collect();
+    match identify_games(pool, &lookups).await {
+        Ok(matches) => matches.len(),
+        Err(e) => {
+            errors.push(format!("Failed to identify games: {}", e));
+            0
+        }
+    }
+}
+
+fn list_dat_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| e.eq_ignore_ascii_case("dat") || e.eq_ignore_ascii_case("xml"))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Import every DAT in DATS_PATH and re-identify the library (admin only)
+pub async fn import_dats(
+    Extension(pool): Extension<Arc<PgPool>>,
+    auth: AuthUser,
+) -> Result<Json<DatImportResult>, StatusCode> {
+    auth.require_admin()?;
+
+    let dir = dats_path();
+    if !dir.is_dir() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let mut result = DatImportResult {
+        files_imported: 0,
+        entries_imported: 0,
+        games_identified: 0,
+        errors: Vec::new(),
+    };
+
+    for path in list_dat_files(&dir) {
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let parse_path = path.clone();
+        let parsed = tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(&parse_path).map_err(|e| e.to_string())?;
+            parse_dat(std::io::BufReader::new(file))
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|parsed| parsed);
+
+        let dat = match parsed {
+            Ok(dat) => dat,
+            Err(e) => {
+                result.errors.push(format!("{}: {}", file_name, e));
+                continue;
+            }
+        };
+
+        match store_dat(pool.as_ref(), &file_name, &dat).await {
+            Ok(count) => {
+                result.files_imported += 1;
+                result.entries_imported += count;
+            }
+            Err(e) => result.errors.push(format!("{}: {}", file_name, e)),
+        }
+    }
+
+    result.games_identified = identify_all_games(pool.as_ref(), &mut result.errors).await;
+
+    Ok(Json(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_DAT: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE datafile PUBLIC "-//Logiqx//DTD ROM Management Datafile//EN" "http://www.logiqx.com/dats/datafile.dtd">
+<datafile>
+    <header>
+        <name>Nintendo - Nintendo Entertainment System</name>
+        <description>Nintendo - Nintendo Entertainment System (Headered)</description>
+        <version>20240101-000000</version>
+    </header>
+    <game name="Legend of Zelda, The (USA) (Rev 1)">
+        <description>Legend of Zelda, The (USA) (Rev 1)</description>
+        <rom name="Legend of Zelda, The (USA) (Rev 1).nes" size="131088" crc="A12D74C1" md5="D7A1C0A5B4C1E0F2A3B4C5D6E7F8A9B0" sha1="F4095791987351BE68674A9355B266BC276688D6" status="verified"/>
+    </game>
+    <game name="Broken Game (Europe) (En,Fr,De)">
+        <rom name="Broken Game (Europe) (En,Fr,De).nes" size="40976" crc="00000001" status="baddump"/>
+    </game>
+</datafile>"#;
+
+    #[test]
+    fn test_parse_dat() {
+        let dat = parse_dat(SAMPLE_DAT.as_bytes()).unwrap();
+        assert_eq!(dat.header.name, "Nintendo - Nintendo Entertainment System");
+        assert_eq!(dat.header.version.as_deref(), Some("20240101-000000"));
+        assert_eq!(dat.entries.len(), 2);
+
+        let zelda = &dat.entries[0];
+        assert_eq!(zelda.game_name, "Legend of Zelda, The (USA) (Rev 1)");
+        assert_eq!(zelda.size, Some(131088));
+        assert_eq!(zelda.crc32.as_deref(), Some("a12d74c1"));
+        assert_eq!(zelda.sha1.as_deref(), Some("f4095791987351be68674a9355b266bc276688d6"));
+        assert_eq!(dat.entries[1].status.as_deref(), Some("baddump"));
+        assert_eq!(dat.entries[1].md5, None);
+    }
+
+    #[test]
+    fn test_parse_dat_requires_header() {
+        assert!(parse_dat("<datafile><game name=\"x\"/></datafile>".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_split_game_name() {
+        let (title, region, languages, revision) = split_game_name("Legend of Zelda, The (USA) (Rev 1)");
+        assert_eq!(title, "Legend of Zelda, The");
+        assert_eq!(region.as_deref(), Some("USA"));
+        assert_eq!(languages, None);
+        assert_eq!(revision.as_deref(), Some("1"));
+
+        let (title, region, languages, _) = split_game_name("Tetris (Japan) (En,Ja)");
+        assert_eq!(title, "Tetris");
+        assert_eq!(region.as_deref(), Some("Japan"));
+        assert_eq!(languages.as_deref(), Some("En,Ja"));
+    }
+}
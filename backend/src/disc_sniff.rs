@@ -0,0 +1,318 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Extensions shared by several disc-based systems, where the file has to be inspected
+pub fn is_ambiguous_disc_extension(extension: &str) -> bool {
+    matches!(extension, "iso" | "bin" | "img" | "chd" | "cue")
+}
+
+const GAMECUBE_MAGIC: u32 = 0xc233_9f3d;
+const WII_MAGIC: u32 = 0x5d1c_9ea3;
+const RAW_SECTOR_SYNC: [u8; 12] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+/// Largest directory or SYSTEM.CNF we are willing to read while sniffing
+const MAX_SNIFF_READ: u32 = 64 * 1024;
+
+/// How logical 2048-byte sectors are laid out in the image
+#[derive(Debug, Clone, Copy)]
+struct SectorLayout {
+    sector_size: u64,
+    data_offset: u64,
+}
+
+const COOKED: SectorLayout = SectorLayout { sector_size: 2048, data_offset: 0 };
+
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_sectors<R: Read + Seek>(reader: &mut R, layout: SectorLayout, lba: u32, len: u32) -> io::Result<Vec<u8>> {
+    let len = len.min(MAX_SNIFF_READ) as usize;
+    let mut data = Vec::with_capacity(len);
+    let mut lba = u64::from(lba);
+    while data.len() < len {
+        let chunk = (len - data.len()).min(2048);
+        data.extend(read_at(reader, lba * layout.sector_size + layout.data_offset, chunk)?);
+        lba += 1;
+    }
+    Ok(data)
+}
+
+/// Raw BIN images keep the 2352-byte CD sectors; the mode byte says where user data starts
+fn detect_layout<R: Read + Seek>(reader: &mut R) -> io::Result<SectorLayout> {
+    let start = read_at(reader, 0, 16)?;
+    if start[..12] != RAW_SECTOR_SYNC {
+        return Ok(COOKED);
+    }
+    let mode = read_at(reader, 16 * 2352 + 15, 1)?[0];
+    Ok(SectorLayout {
+        sector_size: 2352,
+        data_offset: if mode == 2 { 24 } else { 16 },
+    })
+}
+
+struct DirEntry {
+    name: String,
+    lba: u32,
+    size: u32,
+}
+
+fn le32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Entries of an ISO 9660 directory, names upper-cased and without the ";1" version
+fn parse_directory(data: &[u8]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let len = data[pos] as usize;
+        if len == 0 {
+            // Records never cross a sector boundary; the rest of this sector is padding
+            pos = (pos / 2048 + 1) * 2048;
+            continue;
+        }
+        if pos + len > data.len() || len < 34 {
+            break;
+        }
+        let record = &data[pos..pos + len];
+        let name_len = record[32] as usize;
+        if 33 + name_len <= len {
+            let name = String::from_utf8_lossy(&record[33..33 + name_len]);
+            entries.push(DirEntry {
+                name: name.split(';').next().unwrap_or_default().to_uppercase(),
+                lba: le32(record, 2),
+                size: le32(record, 10),
+            });
+        }
+        pos += len;
+    }
+    entries
+}
+
+fn sniff_iso9660<R: Read + Seek>(reader: &mut R, layout: SectorLayout) -> io::Result<Option<&'static str>> {
+    let pvd = read_sectors(reader, layout, 16, 2048)?;
+    if pvd[0] != 1 || &pvd[1..6] != b"CD001" {
+        return Ok(None);
+    }
+    let system_id = String::from_utf8_lossy(&pvd[8..40]).trim().to_string();
+    if system_id == "PSP GAME" {
+        return Ok(Some("psp"));
+    }
+
+    let root = read_sectors(reader, layout, le32(&pvd, 156 + 2), le32(&pvd, 156 + 10))?;
+    let entries = parse_directory(&root);
+    if entries.iter().any(|e| e.name == "UMD_DATA.BIN" || e.name == "PSP_GAME") {
+        return Ok(Some("psp"));
+    }
+    if let Some(cnf) = entries.iter().find(|e| e.name == "SYSTEM.CNF") {
+        let text = read_sectors(reader, layout, cnf.lba, cnf.size)?;
+        let text = String::from_utf8_lossy(&text).to_uppercase();
+        // PS2 discs boot through BOOT2, PS1 discs through BOOT
+        if text.contains("BOOT2") {
+            return Ok(Some("ps2"));
+        }
+        if text.contains("BOOT") {
+            return Ok(Some("ps1"));
+        }
+    }
+    if system_id.starts_with("PLAYSTATION") {
+        return Ok(Some("ps1"));
+    }
+    Ok(None)
+}
+
+/// Detect the platform of a disc image (ISO or raw BIN), as a ROM directory name
+pub fn sniff_disc<R: Read + Seek>(reader: &mut R) -> Option<&'static str> {
+    let start = read_at(reader, 0, 0x20).ok()?;
+    if &start[..4] == b"WBFS" {
+        return Some("wii");
+    }
+    let be32 = |offset: usize| u32::from_be_bytes([start[offset], start[offset + 1], start[offset + 2], start[offset + 3]]);
+    if be32(0x18) == WII_MAGIC {
+        return Some("wii");
+    }
+    if be32(0x1c) == GAMECUBE_MAGIC {
+        return Some("gamecube");
+    }
+
+    let layout = detect_layout(reader).ok()?;
+    // Dreamcast IP.BIN sits in the first sector of the data track
+    if let Ok(ip_bin) = read_sectors(reader, layout, 0, 16) {
+        if ip_bin.starts_with(b"SEGA SEGAKATANA") {
+            return Some("dreamcast");
+        }
+    }
+
+    sniff_iso9660(reader, layout).ok().flatten()
+}
+
+/// CHD hunks are compressed, but the track metadata is stored plain and says what kind of disc it is
+fn sniff_chd<R: Read + Seek>(reader: &mut R) -> Option<&'static str> {
+    let header = read_at(reader, 0, 56).ok()?;
+    if &header[..8] != b"MComprHD" {
+        return None;
+    }
+    let be64 = |data: &[u8], offset: usize| {
+        u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap_or_default())
+    };
+    let version = u32::from_be_bytes([header[12], header[13], header[14], header[15]]);
+    let mut offset = match version {
+        5 => be64(&header, 48),
+        3 | 4 => be64(&header, 36),
+        _ => return None,
+    };
+
+    for _ in 0..64 {
+        if offset == 0 {
+            break;
+        }
+        let entry = read_at(reader, offset, 16).ok()?;
+        match &entry[..4] {
+            b"CHGD" | b"CHGT" => return Some("dreamcast"),
+            b"DVD " => return Some("ps2"),
+            _ => {}
+        }
+        offset = be64(&entry, 8);
+    }
+    None
+}
+
+/// The first data file a .cue sheet points at
+fn cue_data_file(cue: &Path) -> Option<std::path::PathBuf> {
+    let text = std::fs::read_to_string(cue).ok()?;
+    let line = text.lines().map(str::trim).find(|l| l.get(..5).is_some_and(|prefix| prefix.eq_ignore_ascii_case("FILE ")))?;
+    let name = match line[5..].trim_start().strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next()?,
+        None => line[5..].split_whitespace().next()?,
+    };
+    Some(cue.with_file_name(name))
+}
+
+/// Detect the platform of a disc image on disk. Returns `None` for anything that
+/// isn't recognisably one of GameCube, Wii, PS1, PS2, PSP or Dreamcast.
+pub fn sniff_disc_file(path: &Path, extension: &str) -> Option<&'static str> {
+    let path = if extension == "cue" { cue_data_file(path)? } else { path.to_path_buf() };
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    if extension == "chd" {
+        sniff_chd(&mut reader)
+    } else {
+        sniff_disc(&mut reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn dir_record(name: &str, lba: u32, size: u32) -> Vec<u8> {
+        let len = 33 + name.len() + (name.len() + 1) % 2;
+        let mut record = vec![0u8; len];
+        record[0] = len as u8;
+        record[2..6].copy_from_slice(&lba.to_le_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[32] = name.len() as u8;
+        record[33..33 + name.len()].copy_from_slice(name.as_bytes());
+        record
+    }
+
+    /// A minimal cooked ISO with the given root entries; SYSTEM.CNF content goes in sector 20
+    fn build_iso(system_id: &str, files: &[&str], system_cnf: &str) -> Vec<u8> {
+        let mut iso = vec![0u8; 2048 * 24];
+        let pvd = 16 * 2048;
+        iso[pvd] = 1;
+        iso[pvd + 1..pvd + 6].copy_from_slice(b"CD001");
+        iso[pvd + 8..pvd + 8 + system_id.len()].copy_from_slice(system_id.as_bytes());
+        let root = dir_record("\0", 18, 2048);
+        iso[pvd + 156..pvd + 156 + root.len()].copy_from_slice(&root);
+
+        let mut pos = 18 * 2048;
+        for name in files {
+            let record = dir_record(name, 20, system_cnf.len() as u32);
+            iso[pos..pos + record.len()].copy_from_slice(&record);
+            pos += record.len();
+        }
+        iso[20 * 2048..20 * 2048 + system_cnf.len()].copy_from_slice(system_cnf.as_bytes());
+        iso
+    }
+
+    #[test]
+    fn test_gamecube_and_wii_magic() {
+        let mut disc = vec![0u8; 0x40];
+        disc[0x1c..0x20].copy_from_slice(&GAMECUBE_MAGIC.to_be_bytes());
+        assert_eq!(sniff_disc(&mut Cursor::new(&disc)), Some("gamecube"));
+
+        let mut disc = vec![0u8; 0x40];
+        disc[0x18..0x1c].copy_from_slice(&WII_MAGIC.to_be_bytes());
+        assert_eq!(sniff_disc(&mut Cursor::new(&disc)), Some("wii"));
+    }
+
+    #[test]
+    fn test_playstation_system_cnf() {
+        let ps2 = build_iso("PLAYSTATION", &["SYSTEM.CNF;1"], "BOOT2 = cdrom0:\\SLUS_203.12;1\r\n");
+        assert_eq!(sniff_disc(&mut Cursor::new(&ps2)), Some("ps2"));
+
+        let ps1 = build_iso("PLAYSTATION", &["SYSTEM.CNF;1"], "BOOT = cdrom:\\SCUS_944.55;1\r\n");
+        assert_eq!(sniff_disc(&mut Cursor::new(&ps1)), Some("ps1"));
+
+        let psp = build_iso("", &["PSP_GAME", "UMD_DATA.BIN;1"], "");
+        assert_eq!(sniff_disc(&mut Cursor::new(&psp)), Some("psp"));
+
+        let other = build_iso("LINUX", &["README.TXT;1"], "");
+        assert_eq!(sniff_disc(&mut Cursor::new(&other)), None);
+    }
+
+    #[test]
+    fn test_raw_bin_sectors() {
+        // Re-pack a cooked PS1 image into raw Mode 2 sectors
+        let cooked = build_iso("PLAYSTATION", &["SYSTEM.CNF;1"], "BOOT = cdrom:\\SLUS_000.67;1\r\n");
+        let mut raw = Vec::new();
+        for sector in cooked.chunks(2048) {
+            raw.extend_from_slice(&RAW_SECTOR_SYNC);
+            raw.extend_from_slice(&[0, 0, 0, 2]);
+            raw.extend_from_slice(&[0; 8]);
+            raw.extend_from_slice(sector);
+            raw.extend_from_slice(&[0; 280]);
+        }
+        assert_eq!(sniff_disc(&mut Cursor::new(&raw)), Some("ps1"));
+    }
+
+    #[test]
+    fn test_dreamcast_ip_bin() {
+        let mut disc = vec![0u8; 2048 * 20];
+        disc[..16].copy_from_slice(b"SEGA SEGAKATANA ");
+        assert_eq!(sniff_disc(&mut Cursor::new(&disc)), Some("dreamcast"));
+    }
+
+    #[test]
+    fn test_cue_data_file() {
+        let dir = std::env::temp_dir().join(format!("cue-data-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cue = dir.join("Game.cue");
+        // "ﬁ" uppercases to "FI", so the line has to be matched as written
+        std::fs::write(&cue, "REM é\nﬁle \"wrong.bin\" BINARY\nTITLE \"Straße\"\nfile \"Game (Track 1).bin\" BINARY\n").unwrap();
+        let data = cue_data_file(&cue);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(data, Some(dir.join("Game (Track 1).bin")));
+    }
+
+    #[test]
+    fn test_chd_metadata() {
+        let mut chd = vec![0u8; 128];
+        chd[..8].copy_from_slice(b"MComprHD");
+        chd[12..16].copy_from_slice(&5u32.to_be_bytes());
+        chd[48..56].copy_from_slice(&64u64.to_be_bytes());
+        chd[64..68].copy_from_slice(b"CHT2");
+        chd[72..80].copy_from_slice(&96u64.to_be_bytes());
+        chd[96..100].copy_from_slice(b"CHGD");
+        assert_eq!(sniff_chd(&mut Cursor::new(&chd)), Some("dreamcast"));
+
+        chd[96..100].copy_from_slice(b"CHT2");
+        assert_eq!(sniff_chd(&mut Cursor::new(&chd)), None);
+    }
+}
@@ -15,6 +15,8 @@ pub struct EmulatorInfo {
 
 #[derive(Serialize, Clone)]
 #[serde(rename_all = "PascalCase")]
+// No built-in emulator is an EmulatorJS or BrowserWASM one yet, but the names are part of the API
+#[allow(dead_code)]
 pub enum EmulatorType {
     RetroArchCore,
     EmulatorJS,
@@ -208,7 +210,7 @@ pub fn get_all_emulators() -> Vec<EmulatorInfo> {
             name: "PCSX2".to_string(),
             system: "PlayStation 2".to_string(),
             core: "pcsx2".to_string(),
-            supported_formats: vec!["iso", "bin", "mdf", "nrg", "gz", "cso"].into_iter().map(String::from).collect(),
+            supported_formats: vec!["iso", "bin", "chd", "mdf", "nrg", "gz", "cso"].into_iter().map(String::from).collect(),
             emulator_type: EmulatorType::NativeService,
             service_port: Some(8084),
             github_url: "https://github.com/PCSX2/pcsx2".to_string(),
@@ -312,6 +314,8 @@ pub fn get_emulator_by_id(id: &str) -> Option<EmulatorInfo> {
     get_all_emulators().into_iter().find(|e| e.id == id)
 }
 
+// Not called at the moment, but kept next to get_emulator_by_id as a lookup for handlers
+#[allow(dead_code)]
 pub fn get_emulators_by_system(system: &str) -> Vec<EmulatorInfo> {
     get_all_emulators()
         .into_iter()
@@ -6,6 +6,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use std::sync::Arc;
 use crate::emulators::{get_emulator_by_id, EmulatorInfo};
+use crate::archive::is_archive_extension;
+use crate::auth::AuthUser;
 
 #[derive(Serialize, sqlx::FromRow)]
 pub struct Game {
@@ -22,6 +24,15 @@ pub struct Game {
     pub file_size: Option<i64>,
     #[sqlx(default)]
     pub metadata: Option<serde_json::Value>,
+    #[sqlx(default)]
+    pub crc32: Option<String>,
+    #[sqlx(default)]
+    pub md5: Option<String>,
+    #[sqlx(default)]
+    pub sha1: Option<String>,
+    /// When a scan last failed to find the file; `None` while it is present
+    #[sqlx(default)]
+    pub missing_since: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Serialize)]
@@ -32,39 +43,86 @@ pub struct GameWithEmulator {
     pub launch_url: String,
 }
 
+/// Split the `<archive>#<entry>` path of a ROM stored in a .zip/.7z into the archive
+/// and the entry. None for a plain file, even if its name contains a `#`.
+pub fn split_archive_path(file_path: &str) -> Option<(&str, &str)> {
+    file_path.match_indices('#').find_map(|(index, _)| {
+        let (archive, entry) = (&file_path[..index], &file_path[index + 1..]);
+        let extension = std::path::Path::new(archive).extension()?.to_str()?.to_lowercase();
+        (is_archive_extension(&extension) && !entry.is_empty()).then_some((archive, entry))
+    })
+}
+
+/// Where the emulator service for a game is opened. The ROM is passed as `rom`, its
+/// path in the ROMs directory; for a ROM inside an archive that is the archive, and
+/// `entry` names the file in it (served unpacked by `GET /api/roms/archive-entry`).
+pub fn launch_url(game: &Game, emulator: &EmulatorInfo) -> String {
+    let rom = match split_archive_path(&game.file_path) {
+        Some((archive, entry)) => format!("{}&entry={}", urlencoding::encode(archive), urlencoding::encode(entry)),
+        None => urlencoding::encode(&game.file_path).into_owned(),
+    };
+    match emulator.emulator_type {
+        crate::emulators::EmulatorType::RetroArchCore => {
+            format!("http://localhost:8081/play?core={}&rom={}", emulator.core, rom)
+        }
+        crate::emulators::EmulatorType::EmulatorJS |
+        crate::emulators::EmulatorType::BrowserWASM => {
+            format!("http://localhost:8082/?rom={}&core={}", rom, emulator.core)
+        }
+        crate::emulators::EmulatorType::NativeService => {
+            format!("http://localhost:{}/launch?rom={}", emulator.service_port.unwrap_or(8080), rom)
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct GameQuery {
     system: Option<String>,
     emulator: Option<String>,
+    /// Filters on the tags stored in `metadata.tags`
+    region: Option<String>,
+    language: Option<String>,
+    revision: Option<String>,
+    verified: Option<bool>,
+    bad_dump: Option<bool>,
+    hack: Option<bool>,
+    translation: Option<bool>,
+    /// Games whose files have disappeared are hidden unless this is set
+    include_missing: Option<bool>,
 }
 
 pub async fn get_games(
     Extension(pool): Extension<Arc<PgPool>>,
     Query(query): Query<GameQuery>,
 ) -> Result<Json<Vec<GameWithEmulator>>, axum::http::StatusCode> {
-    let games: Vec<Game> = if let Some(system) = &query.system {
-        if let Some(emulator) = &query.emulator {
-            sqlx::query_as("SELECT * FROM games WHERE system = $1 AND emulator_id = $2 ORDER BY title")
-                .bind(system)
-                .bind(emulator)
-                .fetch_all(pool.as_ref())
-                .await
-        } else {
-            sqlx::query_as("SELECT * FROM games WHERE system = $1 ORDER BY title")
-                .bind(system)
-                .fetch_all(pool.as_ref())
-                .await
-        }
-    } else if let Some(emulator) = &query.emulator {
-        sqlx::query_as("SELECT * FROM games WHERE emulator_id = $1 ORDER BY title")
-            .bind(emulator)
-            .fetch_all(pool.as_ref())
-            .await
-    } else {
-        sqlx::query_as("SELECT id, title, system, file_path, emulator_id, emulator_type, added_at, user_id, file_size, metadata FROM games ORDER BY title")
-            .fetch_all(pool.as_ref())
-            .await
-    }
+    // Every filter is optional; a NULL parameter leaves that condition out
+    let games: Vec<Game> = sqlx::query_as(
+        "SELECT id, title, system, file_path, emulator_id, emulator_type, added_at, user_id, file_size, metadata, crc32, md5, sha1, missing_since
+         FROM games
+         WHERE ($1::text IS NULL OR system = $1)
+           AND ($2::text IS NULL OR emulator_id = $2)
+           AND ($3::text IS NULL OR metadata->'tags'->'regions' ? $3)
+           AND ($4::text IS NULL OR metadata->'tags'->'languages' ? $4)
+           AND ($5::text IS NULL OR metadata->'tags'->>'revision' = $5)
+           AND ($6::bool IS NULL OR COALESCE((metadata->'tags'->>'verified')::bool, false) = $6)
+           AND ($7::bool IS NULL OR COALESCE((metadata->'tags'->>'bad_dump')::bool, false) = $7)
+           AND ($8::bool IS NULL OR COALESCE((metadata->'tags'->>'hack')::bool, false) = $8)
+           AND ($9::bool IS NULL OR COALESCE((metadata->'tags'->>'translation')::bool, false) = $9)
+           AND ($10::bool IS TRUE OR missing_since IS NULL)
+         ORDER BY title"
+    )
+    .bind(&query.system)
+    .bind(&query.emulator)
+    .bind(&query.region)
+    .bind(&query.language)
+    .bind(&query.revision)
+    .bind(query.verified)
+    .bind(query.bad_dump)
+    .bind(query.hack)
+    .bind(query.translation)
+    .bind(query.include_missing)
+    .fetch_all(pool.as_ref())
+    .await
     .map_err(|e| {
         eprintln!("Database error: {:?}", e);
         axum::http::StatusCode::INTERNAL_SERVER_ERROR
@@ -74,24 +132,7 @@ pub async fn get_games(
         .into_iter()
         .filter_map(|game| {
             get_emulator_by_id(&game.emulator_id).map(|emulator| {
-                let launch_url = match emulator.emulator_type {
-                    crate::emulators::EmulatorType::RetroArchCore => {
-                        format!("http://localhost:8081/play?core={}&rom={}", 
-                            emulator.core, 
-                            urlencoding::encode(&game.file_path))
-                    }
-                    crate::emulators::EmulatorType::EmulatorJS | 
-                    crate::emulators::EmulatorType::BrowserWASM => {
-                        format!("http://localhost:8082/?rom={}&core={}", 
-                            urlencoding::encode(&game.file_path),
-                            emulator.core)
-                    }
-                    crate::emulators::EmulatorType::NativeService => {
-                        format!("http://localhost:{}/launch?rom={}", 
-                            emulator.service_port.unwrap_or(8080),
-                            urlencoding::encode(&game.file_path))
-                    }
-                };
+                let launch_url = launch_url(&game, &emulator);
                 
                 GameWithEmulator {
                     game,
@@ -119,24 +160,7 @@ pub async fn get_game_by_id(
     let emulator = get_emulator_by_id(&game.emulator_id)
         .ok_or(axum::http::StatusCode::NOT_FOUND)?;
     
-    let launch_url = match emulator.emulator_type {
-        crate::emulators::EmulatorType::RetroArchCore => {
-            format!("http://localhost:8081/play?core={}&rom={}", 
-                emulator.core, 
-                urlencoding::encode(&game.file_path))
-        }
-        crate::emulators::EmulatorType::EmulatorJS | 
-        crate::emulators::EmulatorType::BrowserWASM => {
-            format!("http://localhost:8082/?rom={}&core={}", 
-                urlencoding::encode(&game.file_path),
-                emulator.core)
-        }
-        crate::emulators::EmulatorType::NativeService => {
-            format!("http://localhost:{}/launch?rom={}", 
-                emulator.service_port.unwrap_or(8080),
-                urlencoding::encode(&game.file_path))
-        }
-    };
+    let launch_url = launch_url(&game, &emulator);
     
     Ok(Json(GameWithEmulator {
         game,
@@ -155,8 +179,11 @@ pub struct AddGameRequest {
 
 pub async fn add_game(
     Extension(pool): Extension<Arc<PgPool>>,
+    auth: AuthUser,
     Json(payload): Json<AddGameRequest>,
 ) -> Result<Json<Game>, axum::http::StatusCode> {
+    auth.require_admin()?;
+    
     // Validate emulator exists and get emulator type
     let emulator = get_emulator_by_id(&payload.emulator_id)
         .ok_or(axum::http::StatusCode::BAD_REQUEST)?;
@@ -169,8 +196,8 @@ pub async fn add_game(
     };
     
     let game = sqlx::query_as::<_, Game>(
-        "INSERT INTO games (title, system, file_path, emulator_id, emulator_type) 
-         VALUES ($1, $2, $3, $4, $5)
+        "INSERT INTO games (title, system, file_path, emulator_id, emulator_type, user_id) 
+         VALUES ($1, $2, $3, $4, $5, $6)
          RETURNING *"
     )
     .bind(&payload.title)
@@ -178,6 +205,7 @@ pub async fn add_game(
     .bind(&payload.file_path)
     .bind(&payload.emulator_id)
     .bind(emulator_type_str)
+    .bind(auth.user_id)
     .fetch_one(pool.as_ref())
     .await
     .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
@@ -185,3 +213,54 @@ pub async fn add_game(
     Ok(Json(game))
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(file_path: &str) -> Game {
+        Game {
+            id: 1,
+            title: "Tetris".to_string(),
+            system: "Game Boy".to_string(),
+            file_path: file_path.to_string(),
+            emulator_id: "mgba".to_string(),
+            emulator_type: "RetroArchCore".to_string(),
+            added_at: chrono::NaiveDateTime::default(),
+            user_id: None,
+            file_size: None,
+            metadata: None,
+            crc32: None,
+            md5: None,
+            sha1: None,
+            missing_since: None,
+        }
+    }
+
+    #[test]
+    fn test_split_archive_path() {
+        assert_eq!(split_archive_path("gb/set.zip#Tetris (World).gb"), Some(("gb/set.zip", "Tetris (World).gb")));
+        assert_eq!(split_archive_path("gb/#1 Hits.7z#disk/#1.gb"), Some(("gb/#1 Hits.7z", "disk/#1.gb")));
+        assert_eq!(split_archive_path("nes/Track #1.nes"), None);
+        assert_eq!(split_archive_path("nes/set.zip#"), None);
+    }
+
+    #[test]
+    fn test_launch_url_for_archived_game() {
+        let mgba = get_emulator_by_id("mgba").unwrap();
+        assert_eq!(
+            launch_url(&game("gb/Tetris (World).gb"), &mgba),
+            "http://localhost:8081/play?core=mgba_libretro&rom=gb%2FTetris%20%28World%29.gb"
+        );
+        assert_eq!(
+            launch_url(&game("gb/set.zip#Tetris (World).gb"), &mgba),
+            "http://localhost:8081/play?core=mgba_libretro&rom=gb%2Fset.zip&entry=Tetris%20%28World%29.gb"
+        );
+
+        let pcsx2 = get_emulator_by_id("pcsx2").unwrap();
+        assert_eq!(
+            launch_url(&game("ps2/discs.7z#Game.iso"), &pcsx2),
+            "http://localhost:8084/launch?rom=ps2%2Fdiscs.7z&entry=Game.iso"
+        );
+    }
+}
@@ -1,18 +1,39 @@
 use axum::{
-    extract::Extension,
-    routing::{get, post},
+    extract::{DefaultBodyLimit, Extension},
+    routing::{get, post, put},
     Router,
     response::Json,
 };
 use sqlx::PgPool;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 
+mod archive;
+mod auth;
+mod bios;
+mod client_ip;
+mod dat;
+mod disc_sniff;
 mod emulators;
 mod games;
+mod multi_disc;
+mod play_history;
+mod rate_limit;
+mod registration;
+mod resumable_uploads;
+mod rom_hash;
+mod rom_header;
 mod rom_scanner;
+mod rom_tags;
 mod roms;
+mod saves;
+mod scan_config;
+mod scan_jobs;
 mod sessions;
+mod upload_limits;
+mod users;
+mod watcher;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -22,112 +43,115 @@ async fn main() -> anyhow::Result<()> {
     let pool = PgPool::connect(&database_url).await?;
     let pool = Arc::new(pool);
     
-    // Perform initial ROM scan on startup and add to database
-    println!("Performing initial ROM scan...");
-    let roms_path = std::env::var("ROMS_PATH").unwrap_or_else(|_| "/roms".to_string());
-    if std::path::Path::new(&roms_path).exists() {
-        let discovered = rom_scanner::scan_roms_directory(std::path::Path::new(&roms_path));
-        println!("Found {} ROM files", discovered.len());
-        
-        // Add discovered ROMs to database
-        let mut added = 0;
-        let mut skipped = 0;
-        for rom in discovered {
-            // Check if already exists
-            let exists: Option<(i64,)> = sqlx::query_as(
-                "SELECT id FROM games WHERE file_path = $1"
-            )
-            .bind(&rom.file_path)
-            .fetch_optional(pool.as_ref())
-            .await
-            .ok()
-            .flatten();
-            
-            if exists.is_some() {
-                skipped += 1;
-                continue;
+    if auth::using_generated_secret() {
+        println!("Warning: JWT_SECRET is not set, tokens are signed with a random secret that changes on every restart");
+    }
+    
+    // Scan the ROMs directory on startup, then keep following it. The scan runs as a
+    // background job, so the server is up while a large library is still being read.
+    let scan_jobs = Arc::new(scan_jobs::ScanJobs::default());
+    let roms_path = roms::roms_path();
+    if roms_path.exists() {
+        let (pool, jobs) = (pool.clone(), scan_jobs.clone());
+        tokio::spawn(async move {
+            println!("Performing initial ROM scan...");
+            let result = jobs.run(pool.clone(), roms_path.clone(), roms::ScanOptions::default()).await;
+            println!("Found {} ROM files", result.total_found);
+            for error in &result.errors {
+                println!("Warning: {}", error);
             }
-            
-            // Get emulator info
-            let emulator_id = match &rom.suggested_emulator {
-                Some(id) => id,
-                None => {
-                    println!("Warning: No emulator found for {}", rom.file_name);
-                    continue;
-                }
-            };
-            
-            let emulator = match emulators::get_emulator_by_id(emulator_id) {
-                Some(emu) => emu,
-                None => {
-                    println!("Warning: Invalid emulator ID for {}", rom.file_name);
-                    continue;
-                }
-            };
-            
-            let emulator_type_str = match emulator.emulator_type {
-                emulators::EmulatorType::RetroArchCore => "RetroArchCore",
-                emulators::EmulatorType::EmulatorJS => "EmulatorJS",
-                emulators::EmulatorType::NativeService => "NativeService",
-                emulators::EmulatorType::BrowserWASM => "BrowserWASM",
-            };
-            
-            // Insert into database
-            let result = sqlx::query(
-                "INSERT INTO games (title, system, file_path, emulator_id, emulator_type, file_size) 
-                 VALUES ($1, $2, $3, $4, $5, $6)"
-            )
-            .bind(&rom.file_name)
-            .bind(&rom.system)
-            .bind(&rom.file_path)
-            .bind(emulator_id)
-            .bind(emulator_type_str)
-            .bind(rom.size as i64)
-            .execute(pool.as_ref())
-            .await;
-            
-            match result {
-                Ok(_) => {
-                    added += 1;
-                    println!("Added: {} ({})", rom.file_name, rom.system);
-                }
-                Err(e) => {
-                    println!("Error adding {}: {}", rom.file_name, e);
-                }
+            for dir in &result.unknown_directories {
+                println!("Warning: skipped directory {}, it doesn't match any system (add an alias in scan.toml)", dir);
             }
-        }
-        println!("Initial scan complete: {} added, {} already in database", added, skipped);
+            println!(
+                "Initial scan complete: {} added, {} already in database, {} hashed, {} identified, {} moved, {} missing",
+                result.newly_added, result.already_exists, result.hashed, result.identified, result.moved, result.missing.len()
+            );
+            watcher::spawn(pool, jobs, roms_path);
+        });
     }
     
-    // Start background task to clean up old sessions
+    let resumable_uploads = Arc::new(resumable_uploads::ResumableUploads::default());
+    let login_limiter = Arc::new(rate_limit::LoginLimiter::new());
+    let trusted_proxies = Arc::new(client_ip::TrustedProxies::from_env());
+    
+    // Start background task to clean up old sessions, stale login counters and abandoned uploads
     let pool_cleanup = pool.clone();
+    let limiter_cleanup = login_limiter.clone();
+    let uploads_cleanup = resumable_uploads.clone();
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(300)); // Every 5 minutes
         loop {
             interval.tick().await;
             sessions::cleanup_old_sessions(pool_cleanup.clone()).await;
+            limiter_cleanup.cleanup();
+            uploads_cleanup.expire().await;
         }
     });
     
     let app = Router::new()
         .route("/api/emulators", get(get_emulators))
+        .route("/api/emulators/:id/requirements", get(bios::get_emulator_requirements))
         .route("/api/games", get(games::get_games).post(games::add_game))
         .route("/api/games/:id", get(games::get_game_by_id))
+        .route("/api/games/:id/saves", get(saves::list_saves))
+        .route(
+            "/api/games/:id/saves/:slot",
+            get(saves::get_save)
+                .put(saves::put_save)
+                .delete(saves::delete_save)
+                .layer(DefaultBodyLimit::max(saves::MAX_SAVE_SIZE)),
+        )
+        .route("/api/games/:id/play/start", post(play_history::start_session))
+        .route("/api/play/:session_id/heartbeat", post(play_history::heartbeat_session))
+        .route("/api/play/:session_id/stop", post(play_history::stop_session))
+        .route("/api/play/recent", get(play_history::get_recently_played))
+        .route("/api/play/most-played", get(play_history::get_most_played))
         .route("/api/roms/scan", post(roms::scan_roms))
-        .route("/api/roms/upload", post(roms::upload_rom))
+        .route("/api/roms/scan/:job_id", get(scan_jobs::get_scan_job))
+        .route("/api/roms/scan/:job_id/events", get(scan_jobs::scan_job_events))
+        .route(
+            "/api/roms/upload",
+            // Uploads are streamed to disk and checked against UPLOAD_MAX_SIZE as they arrive
+            post(roms::upload_rom).layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/api/roms/uploads",
+            post(resumable_uploads::create_upload).options(resumable_uploads::upload_options),
+        )
+        .route(
+            "/api/roms/uploads/:upload_id",
+            get(resumable_uploads::get_upload)
+                .head(resumable_uploads::head_upload)
+                .patch(resumable_uploads::patch_upload)
+                .delete(resumable_uploads::delete_upload),
+        )
         .route("/api/roms/consoles", get(roms::get_consoles))
+        .route("/api/roms/archive-entry", get(roms::get_archive_entry))
+        .route("/api/dats/import", post(dat::import_dats))
         .route("/api/auth/login", post(sessions::login))
+        .route("/api/auth/refresh", post(auth::refresh))
+        .route("/api/auth/register", post(registration::register))
+        .route("/api/auth/registration", get(registration::get_registration_info))
+        .route("/api/auth/invites", post(registration::create_invite))
+        .route("/api/users", get(users::list_users))
+        .route("/api/users/:id/role", put(users::set_user_role))
         .route("/api/auth/previous-usernames", get(sessions::get_previous_usernames))
         .route("/api/auth/delete-account", post(sessions::delete_account))
         .route("/api/sessions/register", post(sessions::register_session))
         .route("/api/sessions/connected", get(sessions::get_connected_users))
         .layer(Extension(pool))
+        .layer(Extension(login_limiter))
+        .layer(Extension(trusted_proxies))
+        .layer(Extension(scan_jobs))
+        .layer(Extension(resumable_uploads))
         .layer(CorsLayer::permissive());
     
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
     println!("Server running on http://0.0.0.0:8080");
     
-    axum::serve(listener, app).await?;
+    // Connect info gives handlers the peer address for client IP resolution
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
     
     Ok(())
 }
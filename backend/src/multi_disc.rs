@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use regex::Regex;
+use crate::emulators::EmulatorInfo;
+use crate::rom_scanner::{clean_filename, file_size_and_mtime, find_compatible_emulator, RomFile};
+use crate::rom_tags::parse_tags;
+
+/// Extensions that hold a whole disc (or describe one) and can be listed in a playlist
+const DISC_EXTENSIONS: &[&str] = &["cue", "gdi", "chd", "iso", "cdi", "pbp", "cso"];
+
+/// Files referenced by a .cue sheet (`FILE "Track 01.bin" BINARY`)
+pub fn parse_cue(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| line.get(..5).is_some_and(|prefix| prefix.eq_ignore_ascii_case("FILE ")))
+        .filter_map(|line| {
+            let rest = line[5..].trim_start();
+            match rest.strip_prefix('"') {
+                Some(quoted) => quoted.split('"').next(),
+                // Unquoted names can't contain spaces; the file type follows
+                None => rest.split_whitespace().next(),
+            }
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Track files of a Dreamcast .gdi: a track count, then `number lba type size file offset` lines
+pub fn parse_gdi(text: &str) -> Vec<String> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.trim();
+            for _ in 0..4 {
+                fields = fields.split_once(char::is_whitespace)?.1.trim_start();
+            }
+            match fields.strip_prefix('"') {
+                Some(quoted) => quoted.split('"').next(),
+                None => fields.split_whitespace().next(),
+            }
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Entries of an .m3u playlist, skipping comments and blank lines
+pub fn parse_m3u(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+fn disc_tag() -> Regex {
+    Regex::new(r"(?i)\s*[\(\[]\s*(?:disc|disk|cd)\s*(\d+)(?:\s*of\s*\d+)?\s*[\)\]]").unwrap()
+}
+
+/// Disc number and the name with the disc tag removed, for names like "Final Fantasy VII (USA) (Disc 2)"
+pub fn split_disc_number(stem: &str) -> Option<(u32, String)> {
+    let re = disc_tag();
+    let captures = re.captures(stem)?;
+    let number = captures[1].parse().ok()?;
+    let base = re.replace(stem, "").trim().to_string();
+    Some((number, base))
+}
+
+fn parent_dir(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+fn join(dir: &str, name: &str) -> String {
+    let name = name.replace('\\', "/");
+    if dir.is_empty() {
+        name
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn stem(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name)
+}
+
+/// Files a cue/gdi/m3u refers to, as paths relative to the ROMs directory
+fn referenced_files(base_path: &Path, rom: &RomFile) -> Vec<String> {
+    let parse = match rom.extension.as_str() {
+        "cue" => parse_cue,
+        "gdi" => parse_gdi,
+        "m3u" => parse_m3u,
+        _ => return Vec::new(),
+    };
+    let Ok(text) = std::fs::read_to_string(base_path.join(&rom.file_path)) else {
+        return Vec::new();
+    };
+    let dir = parent_dir(&rom.file_path);
+    parse(&text).iter().map(|name| join(dir, name)).collect()
+}
+
+/// Fold track files into their cue/gdi sheet and discs into one game per title.
+///
+/// - Files named by a .cue or .gdi become `tracks` of that sheet instead of games of their own.
+/// - Files named by an .m3u become `discs` of the playlist.
+/// - Remaining discs tagged "(Disc N)" that share a name are grouped, and an .m3u is
+///   written for them. If no emulator for the system takes .m3u, disc 1 stands for the set.
+pub fn group_multi_file_games(base_path: &Path, roms: Vec<RomFile>, emulators: &[EmulatorInfo]) -> Vec<RomFile> {
+    let mut roms = roms;
+
+    // Sheets first, so a playlist can point at a cue that already carries its tracks
+    let mut consumed = HashSet::new();
+    for rom in roms.iter_mut().filter(|r| r.extension == "cue" || r.extension == "gdi") {
+        rom.tracks = referenced_files(base_path, rom);
+        consumed.extend(rom.tracks.iter().cloned());
+    }
+    roms.retain(|r| !consumed.contains(&r.file_path));
+
+    let mut consumed = HashSet::new();
+    for rom in roms.iter_mut().filter(|r| r.extension == "m3u") {
+        rom.discs = referenced_files(base_path, rom);
+        consumed.extend(rom.discs.iter().cloned());
+    }
+    let (disc_files, mut roms): (Vec<RomFile>, Vec<RomFile>) =
+        roms.into_iter().partition(|r| consumed.contains(&r.file_path));
+    for playlist in roms.iter_mut().filter(|r| r.extension == "m3u") {
+        for disc in disc_files.iter().filter(|d| playlist.discs.contains(&d.file_path)) {
+            playlist.tracks.extend(disc.tracks.iter().cloned());
+        }
+    }
+
+    // Remaining "(Disc N)" files, keyed by directory and name without the disc tag
+    let mut sets: BTreeMap<(String, String), Vec<(u32, usize)>> = BTreeMap::new();
+    for (index, rom) in roms.iter().enumerate() {
+        if rom.archive.is_some() || !DISC_EXTENSIONS.contains(&rom.extension.as_str()) {
+            continue;
+        }
+        if let Some((number, base)) = split_disc_number(stem(&rom.file_path)) {
+            let key = (parent_dir(&rom.file_path).to_string(), base);
+            sets.entry(key).or_default().push((number, index));
+        }
+    }
+
+    let mut grouped = HashSet::new();
+    let mut playlists = Vec::new();
+    for ((dir, base), mut discs) in sets {
+        if discs.len() < 2 {
+            continue;
+        }
+        discs.sort();
+        let disc_roms: Vec<&RomFile> = discs.iter().map(|&(_, index)| &roms[index]).collect();
+        let first = disc_roms[0];
+        let disc_paths: Vec<String> = disc_roms.iter().map(|r| r.file_path.clone()).collect();
+        let tracks: Vec<String> = disc_roms.iter().flat_map(|r| r.tracks.iter().cloned()).collect();
+
+        let playlist_emulator = find_compatible_emulator(emulators, &first.system, "m3u");
+        let playlist_path = join(&dir, &format!("{}.m3u", base));
+        let written = playlist_emulator.is_some() && {
+            let contents: String = disc_paths
+                .iter()
+                .map(|p| format!("{}\n", p.rsplit('/').next().unwrap_or(p)))
+                .collect();
+            write_new_file(&base_path.join(&playlist_path), &contents)
+        };
+
+        grouped.extend(discs.iter().map(|&(_, index)| index));
+        if written {
+            let (size, mtime) = file_size_and_mtime(&base_path.join(&playlist_path));
+            playlists.push(RomFile {
+                file_path: playlist_path.clone(),
+                file_name: clean_filename(&base),
+                system: first.system.clone(),
+                extension: "m3u".to_string(),
+                size,
+                mtime,
+                suggested_emulator: playlist_emulator,
+                tags: parse_tags(&base),
+                hashes: None,
+                header: None,
+                disc_platform: first.disc_platform,
+                tracks,
+                discs: disc_paths,
+                archive: None,
+            });
+        } else {
+            playlists.push(RomFile {
+                file_name: clean_filename(&base),
+                tags: parse_tags(&base),
+                tracks,
+                discs: disc_paths,
+                ..first.clone()
+            });
+        }
+    }
+
+    let mut roms: Vec<RomFile> = roms
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !grouped.contains(index))
+        .map(|(_, rom)| rom)
+        .collect();
+    roms.extend(playlists);
+    roms
+}
+
+/// Write a file that must not exist yet, so a playlist the user placed is never replaced
+fn write_new_file(path: &Path, contents: &str) -> bool {
+    use std::io::Write;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| eprintln!("Warning: could not write playlist {}: {}", path.display(), e))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulators::get_all_emulators;
+
+    fn rom(path: &str, system: &str) -> RomFile {
+        let extension = path.rsplit('.').next().unwrap().to_string();
+        RomFile {
+            file_path: path.to_string(),
+            file_name: clean_filename(stem(path)),
+            system: system.to_string(),
+            suggested_emulator: find_compatible_emulator(&get_all_emulators(), system, &extension),
+            extension,
+            size: 0,
+            mtime: 0,
+            tags: parse_tags(stem(path)),
+            hashes: None,
+            header: None,
+            disc_platform: None,
+            tracks: Vec::new(),
+            discs: Vec::new(),
+            archive: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_sheets() {
+        let cue = "FILE \"Game (Track 1).bin\" BINARY\n  TRACK 01 MODE2/2352\nfile track2.bin BINARY\n";
+        assert_eq!(parse_cue(cue), vec!["Game (Track 1).bin", "track2.bin"]);
+        // Lines are matched by characters, so non-ASCII text near the start is fine
+        let cue = "REM é\nTITLE \"Pokémon Stadium\"\nPERFORMER ポケモン\nFILE \"Pokémon.bin\" BINARY\n";
+        assert_eq!(parse_cue(cue), vec!["Pokémon.bin"]);
+
+        let gdi = "3\n1 0 4 2352 track01.bin 0\n2 450 0 2352 \"Track 02.raw\" 0\n3 45000 4 2352 track03.bin 0\n";
+        assert_eq!(parse_gdi(gdi), vec!["track01.bin", "Track 02.raw", "track03.bin"]);
+
+        assert_eq!(parse_m3u("#EXTM3U\nGame (Disc 1).cue\n\nGame (Disc 2).cue\n"), vec!["Game (Disc 1).cue", "Game (Disc 2).cue"]);
+    }
+
+    #[test]
+    fn test_split_disc_number() {
+        assert_eq!(
+            split_disc_number("Final Fantasy VII (USA) (Disc 2)"),
+            Some((2, "Final Fantasy VII (USA)".to_string()))
+        );
+        assert_eq!(split_disc_number("Metal Gear Solid [CD1 of 2] (E)"), Some((1, "Metal Gear Solid (E)".to_string())));
+        assert_eq!(split_disc_number("Crash Bandicoot (USA)"), None);
+    }
+
+    #[test]
+    fn test_group_cue_tracks_and_discs() {
+        let base = std::env::temp_dir().join(format!("multi-disc-test-{}", std::process::id()));
+        let dir = base.join("ps1");
+        std::fs::create_dir_all(&dir).unwrap();
+        for disc in 1..=2 {
+            std::fs::write(
+                dir.join(format!("Game (USA) (Disc {}).cue", disc)),
+                format!("FILE \"Game (USA) (Disc {}).bin\" BINARY\n", disc),
+            )
+            .unwrap();
+        }
+
+        let roms = vec![
+            rom("ps1/Game (USA) (Disc 1).cue", "PlayStation 1"),
+            rom("ps1/Game (USA) (Disc 1).bin", "PlayStation 1"),
+            rom("ps1/Game (USA) (Disc 2).cue", "PlayStation 1"),
+            rom("ps1/Game (USA) (Disc 2).bin", "PlayStation 1"),
+            rom("ps1/Other (USA).chd", "PlayStation 1"),
+        ];
+        let grouped = group_multi_file_games(&base, roms, &get_all_emulators());
+        let written = std::fs::read_to_string(dir.join("Game (USA).m3u"));
+        std::fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(grouped.len(), 2);
+        assert_eq!(grouped[0].file_path, "ps1/Other (USA).chd");
+        let set = &grouped[1];
+        assert_eq!(set.file_path, "ps1/Game (USA).m3u");
+        assert_eq!(set.file_name, "Game");
+        assert_eq!(set.discs, vec!["ps1/Game (USA) (Disc 1).cue", "ps1/Game (USA) (Disc 2).cue"]);
+        assert_eq!(set.tracks, vec!["ps1/Game (USA) (Disc 1).bin", "ps1/Game (USA) (Disc 2).bin"]);
+        assert_eq!(written.unwrap(), "Game (USA) (Disc 1).cue\nGame (USA) (Disc 2).cue\n");
+    }
+}
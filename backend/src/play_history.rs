@@ -0,0 +1,194 @@
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use crate::auth::AuthUser;
+
+/// Longest gap between heartbeats that still counts as play time.
+/// Anything longer is treated as the player having walked away.
+const MAX_HEARTBEAT_GAP_SECS: i32 = 120;
+
+const DEFAULT_LIST_LIMIT: i64 = 20;
+
+#[derive(Deserialize)]
+pub struct PlayHistoryQuery {
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct PlaySessionResponse {
+    pub session_id: i32,
+    pub game_id: i32,
+    pub play_time_seconds: i32,
+    pub active: bool,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct PlayedGame {
+    pub game_id: i32,
+    pub title: String,
+    pub system: String,
+    pub play_time_seconds: i64,
+    pub sessions: i64,
+    pub last_played: chrono::NaiveDateTime,
+}
+
+/// Start a play session for a game, called when the launcher opens `launch_url`
+pub async fn start_session(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Path(game_id): Path<i32>,
+    auth: AuthUser,
+) -> Result<Json<PlaySessionResponse>, StatusCode> {
+    let game_exists = sqlx::query_as::<_, (i32,)>("SELECT id FROM games WHERE id = $1")
+        .bind(game_id)
+        .fetch_optional(pool.as_ref())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error looking up game: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if game_exists.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let (session_id,) = sqlx::query_as::<_, (i32,)>(
+        "INSERT INTO play_history (game_id, user_id, play_time_seconds, last_played)
+         VALUES ($1, $2, 0, CURRENT_TIMESTAMP)
+         RETURNING id"
+    )
+    .bind(game_id)
+    .bind(auth.user_id)
+    .fetch_one(pool.as_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Error starting play session: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(PlaySessionResponse {
+        session_id,
+        game_id,
+        play_time_seconds: 0,
+        active: true,
+    }))
+}
+
+/// Add the time since the last heartbeat to a session, optionally ending it
+async fn accumulate_play_time(
+    pool: &PgPool,
+    session_id: i32,
+    user_id: i32,
+    finish: bool,
+) -> Result<PlaySessionResponse, StatusCode> {
+    let updated = sqlx::query_as::<_, (i32, i32)>(
+        "UPDATE play_history
+         SET play_time_seconds = play_time_seconds
+                 + LEAST(GREATEST(EXTRACT(EPOCH FROM (CURRENT_TIMESTAMP - last_played))::INTEGER, 0), $3),
+             last_played = CURRENT_TIMESTAMP,
+             ended_at = CASE WHEN $4 THEN CURRENT_TIMESTAMP ELSE NULL END
+         WHERE id = $1 AND user_id = $2 AND ended_at IS NULL
+         RETURNING game_id, play_time_seconds"
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .bind(MAX_HEARTBEAT_GAP_SECS)
+    .bind(finish)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error updating play session: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // Unknown, foreign or already finished sessions are all reported as missing
+    let (game_id, play_time_seconds) = updated.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(PlaySessionResponse {
+        session_id,
+        game_id,
+        play_time_seconds,
+        active: !finish,
+    })
+}
+
+/// Keep a play session alive and add the elapsed time to it
+pub async fn heartbeat_session(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Path(session_id): Path<i32>,
+    auth: AuthUser,
+) -> Result<Json<PlaySessionResponse>, StatusCode> {
+    accumulate_play_time(pool.as_ref(), session_id, auth.user_id, false)
+        .await
+        .map(Json)
+}
+
+/// Finish a play session
+pub async fn stop_session(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Path(session_id): Path<i32>,
+    auth: AuthUser,
+) -> Result<Json<PlaySessionResponse>, StatusCode> {
+    accumulate_play_time(pool.as_ref(), session_id, auth.user_id, true)
+        .await
+        .map(Json)
+}
+
+async fn played_games(
+    pool: &PgPool,
+    user_id: i32,
+    query: &PlayHistoryQuery,
+    order_by: &str,
+) -> Result<Vec<PlayedGame>, StatusCode> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, 100);
+
+    // `order_by` only ever comes from the two handlers below, never from the client
+    let sql = format!(
+        "SELECT g.id AS game_id, g.title, g.system,
+                SUM(h.play_time_seconds)::BIGINT AS play_time_seconds,
+                COUNT(*) AS sessions,
+                MAX(h.last_played) AS last_played
+         FROM play_history h
+         JOIN games g ON g.id = h.game_id
+         WHERE h.user_id = $1
+         GROUP BY g.id, g.title, g.system
+         ORDER BY {}
+         LIMIT $2",
+        order_by
+    );
+
+    sqlx::query_as::<_, PlayedGame>(&sql)
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error fetching play history: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Games the user played most recently
+pub async fn get_recently_played(
+    Extension(pool): Extension<Arc<PgPool>>,
+    auth: AuthUser,
+    Query(query): Query<PlayHistoryQuery>,
+) -> Result<Json<Vec<PlayedGame>>, StatusCode> {
+    played_games(pool.as_ref(), auth.user_id, &query, "last_played DESC")
+        .await
+        .map(Json)
+}
+
+/// Games the user has spent the most time in
+pub async fn get_most_played(
+    Extension(pool): Extension<Arc<PgPool>>,
+    auth: AuthUser,
+    Query(query): Query<PlayHistoryQuery>,
+) -> Result<Json<Vec<PlayedGame>>, StatusCode> {
+    played_games(pool.as_ref(), auth.user_id, &query, "play_time_seconds DESC, last_played DESC")
+        .await
+        .map(Json)
+}
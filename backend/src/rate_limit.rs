@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How aggressively one kind of key (client IP or username) is throttled
+#[derive(Debug, Clone, Copy)]
+struct Policy {
+    /// Failures allowed before any delay kicks in
+    free_attempts: u32,
+    /// Failures after which the key is locked out entirely
+    lockout_after: u32,
+    lockout_duration: Duration,
+}
+
+/// A single IP may be trying many usernames, so it gets more room than one account
+const IP_POLICY: Policy = Policy {
+    free_attempts: 5,
+    lockout_after: 30,
+    lockout_duration: Duration::from_secs(15 * 60),
+};
+
+const USERNAME_POLICY: Policy = Policy {
+    free_attempts: 3,
+    lockout_after: 10,
+    lockout_duration: Duration::from_secs(15 * 60),
+};
+
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Failure counters are dropped after this long without a new failure
+const FORGET_AFTER: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Clone, Copy)]
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+}
+
+impl Policy {
+    /// How long after the last failure the next attempt has to wait
+    fn delay(&self, failures: u32) -> Duration {
+        if failures >= self.lockout_after {
+            return self.lockout_duration;
+        }
+        if failures < self.free_attempts {
+            return Duration::ZERO;
+        }
+        // 1s, 2s, 4s, ... capped at MAX_DELAY
+        let exponent = (failures - self.free_attempts).min(16);
+        BASE_DELAY.saturating_mul(1 << exponent).min(MAX_DELAY)
+    }
+}
+
+/// Tracks failed logins per client IP and per username, with exponential
+/// backoff and a temporary lockout after repeated failures. State is kept in
+/// memory, so a restart clears all counters.
+#[derive(Default)]
+pub struct LoginLimiter {
+    entries: Mutex<HashMap<String, Attempts>>,
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+fn username_key(username: &str) -> String {
+    format!("user:{}", username.to_lowercase())
+}
+
+impl LoginLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn keys(ip: &str, username: &str) -> Vec<(String, Policy)> {
+        vec![(username_key(username), USERNAME_POLICY), (ip_key(ip), IP_POLICY)]
+    }
+
+    /// Returns how long the caller must wait before another attempt is allowed. An
+    /// attempt that may go ahead is counted as a failure under the same lock, before the
+    /// password is checked, so a burst of concurrent guesses can't all get through while
+    /// the first ones are still being verified. `record_success` or `release` take it back.
+    pub fn check(&self, ip: &str, username: &str) -> Option<Duration> {
+        self.check_at(ip, username, Instant::now())
+    }
+
+    fn check_at(&self, ip: &str, username: &str, now: Instant) -> Option<Duration> {
+        let mut entries = self.entries.lock().unwrap();
+        let wait = Self::keys(ip, username)
+            .into_iter()
+            .filter_map(|(key, policy)| {
+                let attempts = entries.get(&key)?;
+                let allowed_at = attempts.last_failure + policy.delay(attempts.failures);
+                allowed_at.checked_duration_since(now).filter(|wait| !wait.is_zero())
+            })
+            .max();
+        if wait.is_none() {
+            Self::count_failure(&mut entries, ip, username, now);
+        }
+        wait
+    }
+
+    fn count_failure(entries: &mut HashMap<String, Attempts>, ip: &str, username: &str, now: Instant) {
+        for (key, _) in Self::keys(ip, username) {
+            let attempts = entries.entry(key).or_insert(Attempts {
+                failures: 0,
+                last_failure: now,
+            });
+            attempts.failures = attempts.failures.saturating_add(1);
+            attempts.last_failure = now;
+        }
+    }
+
+    /// Take back an attempt counted by `check` that never got an answer, e.g. because
+    /// the database was unreachable
+    pub fn release(&self, ip: &str, username: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        for (key, _) in Self::keys(ip, username) {
+            if let Some(attempts) = entries.get_mut(&key) {
+                attempts.failures = attempts.failures.saturating_sub(1);
+                if attempts.failures == 0 {
+                    entries.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// A successful login clears the username's counter. On the IP counter only this
+    /// attempt is taken back, so an attacker can't reset it by logging into their own account.
+    pub fn record_success(&self, ip: &str, username: &str) {
+        self.release(ip, username);
+        self.entries.lock().unwrap().remove(&username_key(username));
+    }
+
+    /// Drop counters that have been quiet for a while (called periodically)
+    pub fn cleanup(&self) {
+        self.cleanup_at(Instant::now());
+    }
+
+    fn cleanup_at(&self, now: Instant) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|_, attempts| now.duration_since(attempts.last_failure) < FORGET_AFTER);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_exponentially() {
+        assert_eq!(USERNAME_POLICY.delay(0), Duration::ZERO);
+        assert_eq!(USERNAME_POLICY.delay(2), Duration::ZERO);
+        assert_eq!(USERNAME_POLICY.delay(3), Duration::from_secs(1));
+        assert_eq!(USERNAME_POLICY.delay(4), Duration::from_secs(2));
+        assert_eq!(USERNAME_POLICY.delay(6), Duration::from_secs(8));
+        assert_eq!(USERNAME_POLICY.delay(10), USERNAME_POLICY.lockout_duration);
+        assert_eq!(IP_POLICY.delay(29), MAX_DELAY);
+    }
+
+    #[test]
+    fn test_lockout_and_reset() {
+        let limiter = LoginLimiter::new();
+        let start = Instant::now();
+
+        for _ in 0..USERNAME_POLICY.lockout_after {
+            LoginLimiter::count_failure(&mut limiter.entries.lock().unwrap(), "10.0.0.1", "Alice", start);
+        }
+        // Usernames are matched case-insensitively, from any IP
+        let wait = limiter.check_at("10.0.0.2", "alice", start).unwrap();
+        assert_eq!(wait, USERNAME_POLICY.lockout_duration);
+        assert!(limiter.check_at("10.0.0.2", "bob", start).is_none());
+
+        limiter.record_success("10.0.0.2", "alice");
+        assert!(limiter.check_at("10.0.0.2", "alice", start).is_none());
+    }
+
+    #[test]
+    fn test_concurrent_attempts_are_counted_up_front() {
+        let limiter = LoginLimiter::new();
+        let start = Instant::now();
+
+        // A burst arriving before any password has been checked
+        for _ in 0..USERNAME_POLICY.free_attempts {
+            assert!(limiter.check_at("10.0.0.1", "alice", start).is_none());
+        }
+        assert!(limiter.check_at("10.0.0.1", "alice", start).is_some());
+
+        // Attempts that got no answer don't count
+        for _ in 0..USERNAME_POLICY.free_attempts {
+            limiter.release("10.0.0.1", "alice");
+        }
+        assert!(limiter.entries.lock().unwrap().is_empty());
+
+        // A success takes back its own attempt on the IP and nothing more
+        limiter.check_at("10.0.0.1", "mallory", start);
+        limiter.check_at("10.0.0.1", "mallory", start);
+        limiter.record_success("10.0.0.1", "mallory");
+        assert_eq!(limiter.entries.lock().unwrap()[&ip_key("10.0.0.1")].failures, 1);
+        assert!(!limiter.entries.lock().unwrap().contains_key(&username_key("mallory")));
+    }
+
+    #[test]
+    fn test_ip_is_throttled_across_usernames() {
+        let limiter = LoginLimiter::new();
+        let start = Instant::now();
+
+        for i in 0..IP_POLICY.free_attempts {
+            assert!(limiter.check_at("10.0.0.1", &format!("user{}", i), start).is_none());
+        }
+        assert!(limiter.check_at("10.0.0.1", "someone-else", start).is_some());
+        assert!(limiter.check_at("10.0.0.9", "someone-else", start).is_none());
+        assert!(limiter
+            .check_at("10.0.0.1", "someone-else", start + Duration::from_secs(2))
+            .is_none());
+    }
+
+    #[test]
+    fn test_cleanup_forgets_old_failures() {
+        let limiter = LoginLimiter::new();
+        let start = Instant::now();
+        limiter.check_at("10.0.0.1", "alice", start);
+
+        limiter.cleanup_at(start + FORGET_AFTER / 2);
+        assert_eq!(limiter.entries.lock().unwrap().len(), 2);
+
+        limiter.cleanup_at(start + FORGET_AFTER);
+        assert!(limiter.entries.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,324 @@
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    response::Json,
+};
+use bcrypt::{hash, DEFAULT_COST};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use crate::auth::{AuthUser, Role};
+use crate::sessions::LoginResponse;
+
+pub const USERNAME_MIN_LEN: usize = 3;
+pub const USERNAME_MAX_LEN: usize = 32;
+pub const PASSWORD_MIN_LEN: usize = 8;
+/// bcrypt silently ignores everything past 72 bytes
+pub const PASSWORD_MAX_BYTES: usize = 72;
+
+const INVITE_CODE_LEN: usize = 16;
+const INVITE_TTL_DAYS: i32 = 7;
+
+/// Who may create new accounts, set with REGISTRATION_MODE
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    /// Anyone can register
+    Open,
+    /// Registration requires an unused invite code
+    Invite,
+    /// No new accounts, except the very first one
+    Closed,
+}
+
+impl RegistrationMode {
+    pub fn from_env() -> Self {
+        match std::env::var("REGISTRATION_MODE")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "invite" | "invite-only" | "invite_only" => RegistrationMode::Invite,
+            "closed" => RegistrationMode::Closed,
+            _ => RegistrationMode::Open,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub password: String,
+    pub email: Option<String>,
+    pub invite_code: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RegistrationInfo {
+    pub mode: RegistrationMode,
+    pub username_min_length: usize,
+    pub username_max_length: usize,
+    pub password_min_length: usize,
+}
+
+#[derive(Serialize)]
+pub struct InviteResponse {
+    pub code: String,
+    pub expires_at: String,
+}
+
+/// Usernames are 3-32 characters of letters, digits, `_`, `-` and `.`, starting with a letter or digit
+pub fn validate_username(username: &str) -> Result<(), String> {
+    let len = username.chars().count();
+    if !(USERNAME_MIN_LEN..=USERNAME_MAX_LEN).contains(&len) {
+        return Err(format!(
+            "Username must be between {} and {} characters",
+            USERNAME_MIN_LEN, USERNAME_MAX_LEN
+        ));
+    }
+    if !username.chars().next().is_some_and(|c| c.is_ascii_alphanumeric()) {
+        return Err("Username must start with a letter or digit".to_string());
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err("Username may only contain letters, digits, '_', '-' and '.'".to_string());
+    }
+    Ok(())
+}
+
+pub fn validate_password(password: &str, username: &str) -> Result<(), String> {
+    if password.chars().count() < PASSWORD_MIN_LEN {
+        return Err(format!("Password must be at least {} characters", PASSWORD_MIN_LEN));
+    }
+    if password.len() > PASSWORD_MAX_BYTES {
+        return Err(format!("Password must be at most {} bytes", PASSWORD_MAX_BYTES));
+    }
+    if password.eq_ignore_ascii_case(username) {
+        return Err("Password must not match the username".to_string());
+    }
+    Ok(())
+}
+
+/// Light sanity check only; there is no mail delivery to confirm the address
+pub fn validate_email(email: &str) -> Result<(), String> {
+    let valid = email.len() <= 100
+        && !email.contains(char::is_whitespace)
+        && email
+            .split_once('@')
+            .is_some_and(|(local, domain)| {
+                !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.')
+            });
+
+    if valid {
+        Ok(())
+    } else {
+        Err("Invalid email address".to_string())
+    }
+}
+
+/// Report the registration mode so the login screen can show the right form
+pub async fn get_registration_info() -> Json<RegistrationInfo> {
+    Json(RegistrationInfo {
+        mode: RegistrationMode::from_env(),
+        username_min_length: USERNAME_MIN_LEN,
+        username_max_length: USERNAME_MAX_LEN,
+        password_min_length: PASSWORD_MIN_LEN,
+    })
+}
+
+/// Create a new account and log it in
+pub async fn register(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Json(req): Json<RegisterRequest>,
+) -> Result<Json<LoginResponse>, StatusCode> {
+    let username = req.username.trim().to_string();
+    let email = req
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .map(str::to_lowercase);
+
+    let validation = validate_username(&username)
+        .and_then(|_| validate_password(&req.password, &username))
+        .and_then(|_| email.as_deref().map_or(Ok(()), validate_email));
+    if let Err(message) = validation {
+        return Ok(Json(LoginResponse::failure(&message)));
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        eprintln!("Database error during registration: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // Serialize registrations so exactly one account ends up as the first admin
+    sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error during registration: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // The first account can always be created so a fresh install is never locked out
+    let (user_count,) = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM users")
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error during registration: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mode = RegistrationMode::from_env();
+    let mut invite_id: Option<i32> = None;
+    if user_count > 0 {
+        match mode {
+            RegistrationMode::Open => {}
+            RegistrationMode::Closed => {
+                return Ok(Json(LoginResponse::failure("Registration is closed")));
+            }
+            RegistrationMode::Invite => {
+                let code = req.invite_code.as_deref().map(str::trim).unwrap_or("");
+                invite_id = sqlx::query_as::<_, (i32,)>(
+                    "SELECT id FROM invites
+                     WHERE code = $1 AND used_by IS NULL AND expires_at > CURRENT_TIMESTAMP
+                     FOR UPDATE"
+                )
+                .bind(code)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error checking invite: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?
+                .map(|(id,)| id);
+
+                if invite_id.is_none() {
+                    return Ok(Json(LoginResponse::failure("A valid invite code is required")));
+                }
+            }
+        }
+    }
+
+    let password_hash = hash(&req.password, DEFAULT_COST).map_err(|e| {
+        eprintln!("Password hashing error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // The first account becomes the admin
+    let role = if user_count == 0 { Role::Admin } else { Role::Player };
+
+    let result = sqlx::query_as::<_, (i32,)>(
+        "INSERT INTO users (username, email, password_hash, role)
+         VALUES ($1, $2, $3, $4)
+         RETURNING id"
+    )
+    .bind(&username)
+    .bind(&email)
+    .bind(&password_hash)
+    .bind(role.as_str())
+    .fetch_one(&mut *tx)
+    .await;
+
+    let user_id = match result {
+        Ok((id,)) => id,
+        Err(sqlx::Error::Database(db_err)) if db_err.constraint() == Some("users_username_key") => {
+            return Ok(Json(LoginResponse::failure("Username is already taken")));
+        }
+        Err(sqlx::Error::Database(db_err)) if db_err.constraint() == Some("users_email_key") => {
+            return Ok(Json(LoginResponse::failure("Email is already in use")));
+        }
+        Err(e) => {
+            eprintln!("Error creating user: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    if let Some(invite_id) = invite_id {
+        sqlx::query("UPDATE invites SET used_by = $1, used_at = CURRENT_TIMESTAMP WHERE id = $2")
+            .bind(user_id)
+            .bind(invite_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                eprintln!("Error redeeming invite: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    }
+
+    tx.commit().await.map_err(|e| {
+        eprintln!("Database error during registration: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    LoginResponse::success("Account created", username, user_id, role).map(Json)
+}
+
+/// Create a single-use invite code for invite-only registration (admin only)
+pub async fn create_invite(
+    Extension(pool): Extension<Arc<PgPool>>,
+    auth: AuthUser,
+) -> Result<Json<InviteResponse>, StatusCode> {
+    auth.require_admin()?;
+
+    let code: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(INVITE_CODE_LEN)
+        .map(char::from)
+        .collect();
+
+    let (expires_at,) = sqlx::query_as::<_, (chrono::NaiveDateTime,)>(
+        "INSERT INTO invites (code, created_by, expires_at)
+         VALUES ($1, $2, CURRENT_TIMESTAMP + make_interval(days => $3))
+         RETURNING expires_at"
+    )
+    .bind(&code)
+    .bind(auth.user_id)
+    .bind(INVITE_TTL_DAYS)
+    .fetch_one(pool.as_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Error creating invite: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(InviteResponse {
+        code,
+        expires_at: expires_at.and_utc().to_rfc3339(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_username() {
+        assert!(validate_username("player_one").is_ok());
+        assert!(validate_username("a.b-c").is_ok());
+        assert!(validate_username("ab").is_err());
+        assert!(validate_username(&"a".repeat(33)).is_err());
+        assert!(validate_username("_leading").is_err());
+        assert!(validate_username("has space").is_err());
+        assert!(validate_username("ünïcode").is_err());
+    }
+
+    #[test]
+    fn test_validate_password() {
+        assert!(validate_password("correct horse", "alice").is_ok());
+        assert!(validate_password("short", "alice").is_err());
+        assert!(validate_password("AliceAlice", "alicealice").is_err());
+        assert!(validate_password(&"x".repeat(73), "alice").is_err());
+    }
+
+    #[test]
+    fn test_validate_email() {
+        assert!(validate_email("player@example.com").is_ok());
+        assert!(validate_email("player@localhost").is_err());
+        assert!(validate_email("@example.com").is_err());
+        assert!(validate_email("player example@example.com").is_err());
+    }
+}
@@ -0,0 +1,585 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Path},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use crate::auth::AuthUser;
+use crate::disc_sniff::is_ambiguous_disc_extension;
+use crate::rom_hash::{hash_reader, RomHasher};
+use crate::rom_scanner::get_directory_for_extension;
+use crate::roms::{
+    check_upload_target, import_upload, roms_path, staging_dir, DuplicateChoice, StagedFile, StagedUpload, UploadMembers,
+    UploadOptions, UploadResult,
+};
+use crate::scan_config::{scan_config_path, ScanConfig};
+use crate::upload_limits::UploadLimits;
+
+/// The tus protocol version spoken here (https://tus.io/protocols/resumable-upload)
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+const UPLOAD_ID_LEN: usize = 16;
+
+const DEFAULT_EXPIRY_HOURS: i64 = 24;
+
+/// Buffer between the request body and the partial file
+const WRITE_BUFFER: usize = 1024 * 1024;
+
+/// How long an upload may go without receiving data before it is deleted, set with UPLOAD_EXPIRY_HOURS
+fn expiry() -> chrono::Duration {
+    let hours = std::env::var("UPLOAD_EXPIRY_HOURS")
+        .ok()
+        .and_then(|hours| hours.trim().parse::<i64>().ok())
+        .filter(|&hours| hours > 0)
+        .unwrap_or(DEFAULT_EXPIRY_HOURS);
+    chrono::Duration::hours(hours)
+}
+
+fn part_path(id: &str) -> PathBuf {
+    staging_dir().join(format!("{}.part", id))
+}
+
+fn info_path(id: &str) -> PathBuf {
+    staging_dir().join(format!("{}.json", id))
+}
+
+/// What is stored next to the partial file, so uploads carry on after a restart
+#[derive(Serialize, Deserialize, Clone)]
+struct UploadInfo {
+    user_id: i32,
+    file_name: String,
+    #[serde(flatten)]
+    options: UploadOptions,
+    length: u64,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    /// Set once the file is complete and has been imported (or rejected)
+    result: Option<UploadResult>,
+    /// The status the import was rejected with
+    #[serde(default)]
+    error_status: Option<u16>,
+}
+
+/// The hash of the bytes received so far. It only covers data received since the
+/// server started, so uploads resumed after a restart are hashed once complete instead.
+struct WriteState {
+    hasher: Option<RomHasher>,
+    hashed: u64,
+}
+
+struct Upload {
+    id: String,
+    info: Mutex<UploadInfo>,
+    /// Bytes received; ahead of the partial file while a PATCH is still writing
+    offset: AtomicU64,
+    /// Held for the length of a PATCH, so only one client writes at a time
+    writer: tokio::sync::Mutex<WriteState>,
+}
+
+impl Upload {
+    fn info(&self) -> UploadInfo {
+        self.info.lock().unwrap().clone()
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset.load(Ordering::Relaxed)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.info.lock().unwrap().result.is_some()
+    }
+
+    /// Uploads belong to whoever started them; admins may look at any
+    fn is_visible_to(&self, auth: &AuthUser) -> bool {
+        self.info.lock().unwrap().user_id == auth.user_id || auth.require_admin().is_ok()
+    }
+
+    /// The partial file is the record of what has been received: a PATCH cut off
+    /// half way may have counted bytes that never reached the disk. Call with the writer held.
+    async fn sync_offset(&self, state: &mut WriteState) -> u64 {
+        if self.is_finished() {
+            return self.offset();
+        }
+        let on_disk = tokio::fs::metadata(part_path(&self.id))
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        if on_disk != state.hashed {
+            state.hasher = None;
+        }
+        self.offset.store(on_disk, Ordering::Relaxed);
+        on_disk
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        let json = serde_json::to_vec(&*self.info.lock().unwrap())?;
+        tokio::fs::write(info_path(&self.id), json).await
+    }
+}
+
+/// Partially received ROM uploads, resumable with the tus protocol. Each one is a
+/// `.part` file in the staging directory plus a `.json` file describing it.
+#[derive(Default)]
+pub struct ResumableUploads {
+    uploads: Mutex<HashMap<String, Arc<Upload>>>,
+}
+
+impl ResumableUploads {
+    async fn get(&self, id: &str) -> Option<Arc<Upload>> {
+        // Ids end up in file names
+        if id.len() != UPLOAD_ID_LEN || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        if let Some(upload) = self.uploads.lock().unwrap().get(id) {
+            return Some(upload.clone());
+        }
+
+        // Not seen since the server started; pick it up from disk
+        let info: UploadInfo = serde_json::from_slice(&tokio::fs::read(info_path(id)).await.ok()?).ok()?;
+        let offset = if info.result.is_some() {
+            info.length
+        } else {
+            tokio::fs::metadata(part_path(id)).await.map(|m| m.len()).unwrap_or(0)
+        };
+        let upload = Arc::new(Upload {
+            id: id.to_string(),
+            info: Mutex::new(info),
+            offset: AtomicU64::new(offset),
+            writer: tokio::sync::Mutex::new(WriteState {
+                hasher: (offset == 0).then(RomHasher::new),
+                hashed: 0,
+            }),
+        });
+        Some(self.uploads.lock().unwrap().entry(id.to_string()).or_insert(upload).clone())
+    }
+
+    /// Delete uploads that have received nothing for longer than UPLOAD_EXPIRY_HOURS,
+    /// along with files left in the staging directory by interrupted form uploads
+    pub async fn expire(&self) {
+        let Ok(mut entries) = tokio::fs::read_dir(staging_dir()).await else { return };
+        let now = Utc::now();
+        let mut removed = 0;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()).map(str::to_string) else { continue };
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("json") => {
+                    let Some(upload) = self.get(&id).await else { continue };
+                    if upload.info().expires_at > now {
+                        continue;
+                    }
+                    // Still being written to
+                    let Ok(_writing) = upload.writer.try_lock() else { continue };
+                    let _ = tokio::fs::remove_file(part_path(&id)).await;
+                    let _ = tokio::fs::remove_file(info_path(&id)).await;
+                    self.uploads.lock().unwrap().remove(&id);
+                    removed += 1;
+                }
+                Some("part") if !info_path(&id).exists() => {
+                    let modified = entry.metadata().await.and_then(|m| m.modified());
+                    let stale = modified
+                        .map(|modified| DateTime::<Utc>::from(modified) + expiry() < now)
+                        .unwrap_or(false);
+                    if stale && tokio::fs::remove_file(&path).await.is_ok() {
+                        removed += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if removed > 0 {
+            println!("Removed {} abandoned uploads", removed);
+        }
+    }
+}
+
+/// Parse `Upload-Metadata`: comma-separated keys, each followed by a base64 value
+fn parse_metadata(header: &str) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for pair in header.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => (key, String::from_utf8(STANDARD.decode(value.trim()).ok()?).ok()?),
+            None => (pair, String::new()),
+        };
+        metadata.insert(key.to_string(), value);
+    }
+    Some(metadata)
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Requests from a client speaking another version of the protocol are refused
+fn check_version(headers: &HeaderMap) -> Result<(), StatusCode> {
+    match headers.get("Tus-Resumable") {
+        Some(version) if version != TUS_VERSION => Err(StatusCode::PRECONDITION_FAILED),
+        _ => Ok(()),
+    }
+}
+
+/// `Upload-Expires` uses the HTTP date format
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn tus_headers(upload: Option<&Upload>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("Tus-Resumable", HeaderValue::from_static(TUS_VERSION));
+    if let Some(upload) = upload {
+        let info = upload.info();
+        headers.insert("Upload-Offset", upload.offset().into());
+        headers.insert("Upload-Length", info.length.into());
+        if let Ok(expires) = HeaderValue::from_str(&http_date(info.expires_at)) {
+            headers.insert("Upload-Expires", expires);
+        }
+    }
+    headers
+}
+
+/// What the server supports, as tus clients ask for it
+pub async fn upload_options() -> Response {
+    let mut headers = tus_headers(None);
+    headers.insert("Tus-Version", HeaderValue::from_static(TUS_VERSION));
+    headers.insert("Tus-Extension", HeaderValue::from_static(TUS_EXTENSIONS));
+    headers.insert("Tus-Max-Size", UploadLimits::from_env().largest().into());
+    (StatusCode::NO_CONTENT, headers).into_response()
+}
+
+/// Start a resumable upload. The file name, and optionally console, title, whether to
+/// replace an existing file and what to do with a duplicate, are passed in
+/// `Upload-Metadata` as `filename`, `console`, `title`, `replace` and `on_duplicate`.
+pub async fn create_upload(
+    Extension(uploads): Extension<Arc<ResumableUploads>>,
+    auth: AuthUser,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    auth.require_uploader()?;
+    check_version(&headers)?;
+
+    let length = header_u64(&headers, "Upload-Length").ok_or(StatusCode::BAD_REQUEST)?;
+    let mut metadata = parse_metadata(
+        headers
+            .get("Upload-Metadata")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default(),
+    )
+    .ok_or(StatusCode::BAD_REQUEST)?;
+    let file_name = metadata
+        .remove("filename")
+        .or_else(|| metadata.remove("name"))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let options = UploadOptions {
+        console: metadata.remove("console").filter(|c| !c.is_empty()),
+        title: metadata.remove("title").filter(|t| !t.is_empty()),
+        replace: metadata.remove("replace").is_some_and(|r| matches!(r.trim(), "" | "true" | "1")),
+        on_duplicate: match metadata.remove("on_duplicate") {
+            Some(choice) => Some(DuplicateChoice::parse(&choice).ok_or(StatusCode::BAD_REQUEST)?),
+            None => None,
+        },
+    };
+
+    // Turn away what is bound to fail before any of it is sent
+    let (config, _) = ScanConfig::load(&scan_config_path(&roms_path()));
+    let file_name = check_upload_target(&file_name, &options, &config)?;
+    let limits = UploadLimits::from_env();
+    let max_size = match &options.console {
+        Some(console) => limits.for_system(console),
+        None => limits.largest(),
+    };
+    if length > max_size {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    // A name clash is only certain when the system is known without looking inside the file
+    let extension = file_name.rsplit('.').next().unwrap_or_default().to_lowercase();
+    let target_dir = options.console.as_deref().or_else(|| {
+        (!is_ambiguous_disc_extension(&extension))
+            .then(|| get_directory_for_extension(&extension))
+            .flatten()
+    });
+    if let Some(dir) = target_dir {
+        if !options.replace && roms_path().join(dir).join(&file_name).exists() {
+            return Err(StatusCode::CONFLICT);
+        }
+    }
+
+    let id: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(UPLOAD_ID_LEN)
+        .map(char::from)
+        .collect();
+    let now = Utc::now();
+    let upload = Arc::new(Upload {
+        id: id.clone(),
+        info: Mutex::new(UploadInfo {
+            user_id: auth.user_id,
+            file_name,
+            options,
+            length,
+            created_at: now,
+            expires_at: now + expiry(),
+            result: None,
+            error_status: None,
+        }),
+        offset: AtomicU64::new(0),
+        writer: tokio::sync::Mutex::new(WriteState {
+            hasher: Some(RomHasher::new()),
+            hashed: 0,
+        }),
+    });
+
+    tokio::fs::create_dir_all(staging_dir())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tokio::fs::File::create(part_path(&id))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    upload.save().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    uploads.uploads.lock().unwrap().insert(id.clone(), upload.clone());
+
+    let mut headers = tus_headers(Some(&upload));
+    headers.insert(
+        header::LOCATION,
+        HeaderValue::from_str(&format!("/api/roms/uploads/{}", id)).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    );
+    Ok((StatusCode::CREATED, headers).into_response())
+}
+
+/// How much of an upload the server has, for a client about to resume it
+pub async fn head_upload(
+    Extension(uploads): Extension<Arc<ResumableUploads>>,
+    auth: AuthUser,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    check_version(&headers)?;
+    let upload = uploads
+        .get(&upload_id)
+        .await
+        .filter(|u| u.is_visible_to(&auth))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    // While a PATCH is running the count so far is as good as it gets
+    if let Ok(mut state) = upload.writer.try_lock() {
+        upload.sync_offset(&mut state).await;
+    }
+
+    let mut headers = tus_headers(Some(&upload));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok((StatusCode::OK, headers).into_response())
+}
+
+/// Append to an upload, starting at `Upload-Offset`. Whatever arrives is kept even if
+/// the connection drops. The request that completes the file also imports it, and
+/// answers with the same result as `/api/roms/upload`; an empty PATCH at the final
+/// offset answers the same again.
+pub async fn patch_upload(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Extension(uploads): Extension<Arc<ResumableUploads>>,
+    auth: AuthUser,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, StatusCode> {
+    auth.require_uploader()?;
+    check_version(&headers)?;
+    if headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) != Some("application/offset+octet-stream") {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    let offset = header_u64(&headers, "Upload-Offset").ok_or(StatusCode::BAD_REQUEST)?;
+
+    let upload = uploads
+        .get(&upload_id)
+        .await
+        .filter(|u| u.is_visible_to(&auth))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let mut state = upload.writer.try_lock().map_err(|_| StatusCode::LOCKED)?;
+    let info = upload.info();
+    if let Some(result) = info.result {
+        // A client that lost the final response sends an empty PATCH at the end to get it again
+        if offset != info.length {
+            return Err(StatusCode::CONFLICT);
+        }
+        let status = info
+            .error_status
+            .and_then(|s| StatusCode::from_u16(s).ok())
+            .unwrap_or(StatusCode::OK);
+        return Ok((status, tus_headers(Some(&upload)), Json(result)).into_response());
+    }
+    if upload.sync_offset(&mut state).await != offset {
+        return Err(StatusCode::CONFLICT);
+    }
+    let length = info.length;
+
+    let file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(part_path(&upload.id))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut writer = BufWriter::with_capacity(WRITE_BUFFER, file);
+    let mut stream = body.into_data_stream();
+    let mut received = offset;
+    let mut failed = None;
+    while let Some(chunk) = stream.next().await {
+        // A dropped connection ends up here; keep what has arrived
+        let Ok(chunk) = chunk else {
+            failed = Some(StatusCode::BAD_REQUEST);
+            break;
+        };
+        if received + chunk.len() as u64 > length {
+            failed = Some(StatusCode::PAYLOAD_TOO_LARGE);
+            break;
+        }
+        if writer.write_all(&chunk).await.is_err() {
+            failed = Some(StatusCode::INTERNAL_SERVER_ERROR);
+            break;
+        }
+        if let Some(hasher) = state.hasher.as_mut() {
+            hasher.update(&chunk);
+            state.hashed += chunk.len() as u64;
+        }
+        received += chunk.len() as u64;
+        upload.offset.store(received, Ordering::Relaxed);
+    }
+    if writer.flush().await.is_err() {
+        failed = Some(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    drop(writer);
+    let received = upload.sync_offset(&mut state).await;
+
+    upload.info.lock().unwrap().expires_at = Utc::now() + expiry();
+    upload.save().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if let Some(status) = failed {
+        return Err(status);
+    }
+    if received < length {
+        return Ok((StatusCode::NO_CONTENT, tus_headers(Some(&upload))).into_response());
+    }
+
+    // Complete: hand it to the same import as a form upload
+    let hashes = match state.hasher.take() {
+        Some(hasher) => hasher.finalize(),
+        None => {
+            let path = part_path(&upload.id);
+            tokio::task::spawn_blocking(move || std::fs::File::open(path).and_then(hash_reader))
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        }
+    };
+    let info = upload.info();
+    let staged = StagedUpload {
+        file: StagedFile::new(part_path(&upload.id)),
+        size: length,
+        hashes,
+    };
+    let limits = UploadLimits::from_env();
+    let outcome = import_upload(pool.as_ref(), info.user_id, &limits, staged, UploadMembers::default(), &info.file_name, info.options).await;
+
+    // The partial file is gone either way; remember how it went for clients checking back
+    let (status, result) = match &outcome {
+        Ok(result) => (StatusCode::OK, result.clone()),
+        Err(rejection) => (rejection.status(), rejection.result()),
+    };
+    {
+        let mut info = upload.info.lock().unwrap();
+        info.result = Some(result.clone());
+        info.error_status = outcome.is_err().then(|| status.as_u16());
+    }
+    if let Err(e) = upload.save().await {
+        eprintln!("Failed to save upload {}: {}", upload.id, e);
+    }
+    Ok((status, tus_headers(Some(&upload)), Json(result)).into_response())
+}
+
+#[derive(Serialize)]
+pub struct UploadStatus {
+    pub id: String,
+    pub file_name: String,
+    pub console: Option<String>,
+    pub length: u64,
+    pub offset: u64,
+    pub finished: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// The import result, once the upload is complete
+    pub result: Option<UploadResult>,
+}
+
+/// Progress of a resumable upload
+pub async fn get_upload(
+    Extension(uploads): Extension<Arc<ResumableUploads>>,
+    auth: AuthUser,
+    Path(upload_id): Path<String>,
+) -> Result<Json<UploadStatus>, StatusCode> {
+    let upload = uploads
+        .get(&upload_id)
+        .await
+        .filter(|u| u.is_visible_to(&auth))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let info = upload.info();
+    Ok(Json(UploadStatus {
+        id: upload.id.clone(),
+        file_name: info.file_name,
+        console: info.options.console,
+        length: info.length,
+        offset: upload.offset(),
+        finished: info.result.is_some(),
+        created_at: info.created_at,
+        expires_at: info.expires_at,
+        result: info.result,
+    }))
+}
+
+/// Abandon an upload and delete what has been received
+pub async fn delete_upload(
+    Extension(uploads): Extension<Arc<ResumableUploads>>,
+    auth: AuthUser,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    check_version(&headers)?;
+    let upload = uploads
+        .get(&upload_id)
+        .await
+        .filter(|u| u.is_visible_to(&auth))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let _writing = upload.writer.try_lock().map_err(|_| StatusCode::LOCKED)?;
+    let _ = tokio::fs::remove_file(part_path(&upload.id)).await;
+    let _ = tokio::fs::remove_file(info_path(&upload.id)).await;
+    uploads.uploads.lock().unwrap().remove(&upload.id);
+    Ok((StatusCode::NO_CONTENT, tus_headers(None)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metadata() {
+        // filename "Gran Turismo 4.iso", console "ps2", and a key without a value
+        let metadata = parse_metadata("filename R3JhbiBUdXJpc21vIDQuaXNv, console cHMy,is_confidential").unwrap();
+        assert_eq!(metadata["filename"], "Gran Turismo 4.iso");
+        assert_eq!(metadata["console"], "ps2");
+        assert_eq!(metadata["is_confidential"], "");
+        assert!(parse_metadata("").unwrap().is_empty());
+        assert!(parse_metadata("filename not-base64!").is_none());
+    }
+
+    #[test]
+    fn test_http_date() {
+        let time = DateTime::parse_from_rfc3339("2026-03-09T07:05:02Z").unwrap().with_timezone(&Utc);
+        assert_eq!(http_date(time), "Mon, 09 Mar 2026 07:05:02 GMT");
+    }
+}
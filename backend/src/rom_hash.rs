@@ -0,0 +1,88 @@
+use md5::Md5;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::io::{self, Read};
+
+/// Read size for hashing; large enough to keep disc images moving quickly
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Content hashes of a ROM, as lowercase hex (the format No-Intro/Redump DATs use)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RomHashes {
+    pub crc32: String,
+    pub md5: String,
+    pub sha1: String,
+}
+
+/// Computes CRC32, MD5 and SHA-1 in a single pass over the data
+#[derive(Default)]
+pub struct RomHasher {
+    crc32: crc32fast::Hasher,
+    md5: Md5,
+    sha1: Sha1,
+}
+
+impl RomHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc32.update(data);
+        self.md5.update(data);
+        self.sha1.update(data);
+    }
+
+    pub fn finalize(self) -> RomHashes {
+        RomHashes {
+            crc32: format!("{:08x}", self.crc32.finalize()),
+            md5: format!("{:x}", self.md5.finalize()),
+            sha1: format!("{:x}", self.sha1.finalize()),
+        }
+    }
+}
+
+/// Hash everything a reader yields, streaming so memory use stays flat for big files
+pub fn hash_reader<R: Read>(mut reader: R) -> io::Result<RomHashes> {
+    let mut hasher = RomHasher::new();
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_vectors() {
+        let hashes = hash_reader(&b"abc"[..]).unwrap();
+        assert_eq!(hashes.crc32, "352441c2");
+        assert_eq!(hashes.md5, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hashes.sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn test_chunked_updates_match_single_pass() {
+        let data: Vec<u8> = (0..(HASH_BUFFER_SIZE * 2 + 17)).map(|i| (i % 251) as u8).collect();
+        let mut hasher = RomHasher::new();
+        for chunk in data.chunks(4096) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize(), hash_reader(&data[..]).unwrap());
+    }
+
+    #[test]
+    fn test_empty_input_pads_crc() {
+        let hashes = hash_reader(&b""[..]).unwrap();
+        assert_eq!(hashes.crc32, "00000000");
+    }
+}
@@ -0,0 +1,451 @@
+use serde::Serialize;
+
+/// Cartridge images larger than this are never read whole; N64 tops out at 64MB
+pub const MAX_HEADER_ROM_SIZE: u64 = 64 * 1024 * 1024;
+
+/// What a cartridge's internal header says about it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RomHeader {
+    /// Header layout that matched: `ines`, `nes2`, `snes`, `n64`, `gb` or `gba`
+    pub format: &'static str,
+    /// ROM directory (as in `get_system_mapping`) the header belongs to
+    pub system: &'static str,
+    pub title: Option<String>,
+    pub game_code: Option<String>,
+    /// Mapper number, memory map or cartridge type, depending on the system
+    pub mapper: Option<String>,
+    pub region: Option<String>,
+    /// `None` when the format has no checksum we can verify
+    pub checksum_valid: Option<bool>,
+}
+
+/// Try every known header layout against a ROM image
+pub fn parse_header(data: &[u8]) -> Option<RomHeader> {
+    parse_ines(data)
+        .or_else(|| parse_n64(data))
+        .or_else(|| parse_gba(data))
+        .or_else(|| parse_gb(data))
+        .or_else(|| parse_snes(data))
+}
+
+/// Printable ASCII from a fixed-size header field, trimmed of padding
+fn header_text(bytes: &[u8]) -> Option<String> {
+    let text: String = bytes
+        .iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { ' ' })
+        .collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Region letter used in N64 and GBA game codes
+fn region_from_code(code: u8) -> Option<&'static str> {
+    Some(match code {
+        b'A' => "World",
+        b'B' => "Brazil",
+        b'C' => "China",
+        b'D' => "Germany",
+        b'E' => "USA",
+        b'F' => "France",
+        b'I' => "Italy",
+        b'J' => "Japan",
+        b'K' => "Korea",
+        b'N' => "Canada",
+        b'P' | b'X' | b'Y' => "Europe",
+        b'S' => "Spain",
+        b'U' => "Australia",
+        _ => return None,
+    })
+}
+
+fn parse_ines(data: &[u8]) -> Option<RomHeader> {
+    if data.len() < 16 || &data[0..4] != b"NES\x1a" {
+        return None;
+    }
+    let nes2 = data[7] & 0x0c == 0x08;
+    let mut mapper = u16::from(data[6] >> 4) | u16::from(data[7] & 0xf0);
+    let region = if nes2 {
+        mapper |= u16::from(data[8] & 0x0f) << 8;
+        match data[12] & 0x03 {
+            0 => "NTSC",
+            1 => "PAL",
+            2 => "Multi-region",
+            _ => "Dendy",
+        }
+    } else if data[9] & 0x01 == 1 {
+        "PAL"
+    } else {
+        "NTSC"
+    };
+
+    Some(RomHeader {
+        format: if nes2 { "nes2" } else { "ines" },
+        system: "nes",
+        title: None,
+        game_code: None,
+        mapper: Some(mapper.to_string()),
+        region: Some(region.to_string()),
+        checksum_valid: None,
+    })
+}
+
+/// Boot code CRC32 → (CIC chip, checksum seed)
+fn n64_cic(boot_code_crc: u32) -> Option<(u16, u32)> {
+    Some(match boot_code_crc {
+        0x6170a4a1 => (6101, 0xf8ca4ddc),
+        0x90bb6cb5 => (6102, 0xf8ca4ddc),
+        0x0b050ee0 => (6103, 0xa3886759),
+        0x98bc2c86 => (6105, 0xdf26f436),
+        0xacc8580a => (6106, 0x1fea617a),
+        _ => return None,
+    })
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// The CRC pair stored at 0x10, computed over the first 1MB after the boot code
+fn n64_checksum(rom: &[u8], cic: u16, seed: u32) -> Option<(u32, u32)> {
+    const START: usize = 0x1000;
+    const LENGTH: usize = 0x100000;
+    if rom.len() < START + LENGTH {
+        return None;
+    }
+
+    let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (seed, seed, seed, seed, seed, seed);
+    for i in (START..START + LENGTH).step_by(4) {
+        let d = be32(rom, i);
+        if t6.wrapping_add(d) < t6 {
+            t4 = t4.wrapping_add(1);
+        }
+        t6 = t6.wrapping_add(d);
+        t3 ^= d;
+        let r = d.rotate_left(d & 0x1f);
+        t5 = t5.wrapping_add(r);
+        if t2 > d {
+            t2 ^= r;
+        } else {
+            t2 ^= t6 ^ d;
+        }
+        if cic == 6105 {
+            t1 = t1.wrapping_add(be32(rom, 0x0750 + (i & 0xff)) ^ d);
+        } else {
+            t1 = t1.wrapping_add(t5 ^ d);
+        }
+    }
+
+    Some(match cic {
+        6103 => ((t6 ^ t4).wrapping_add(t3), (t5 ^ t2).wrapping_add(t1)),
+        6106 => (t6.wrapping_mul(t4).wrapping_add(t3), t5.wrapping_mul(t2).wrapping_add(t1)),
+        _ => (t6 ^ t4 ^ t3, t5 ^ t2 ^ t1),
+    })
+}
+
+fn parse_n64(data: &[u8]) -> Option<RomHeader> {
+    if data.len() < 0x1000 {
+        return None;
+    }
+    // .z64 is big-endian, .v64 swaps byte pairs and .n64 is little-endian words
+    let rom: Vec<u8> = match data[0..4] {
+        [0x80, 0x37, 0x12, 0x40] => data.to_vec(),
+        [0x37, 0x80, 0x40, 0x12] => data.chunks(2).flat_map(|c| c.iter().rev().copied()).collect(),
+        [0x40, 0x12, 0x37, 0x80] => data.chunks(4).flat_map(|c| c.iter().rev().copied()).collect(),
+        _ => return None,
+    };
+
+    let cic = n64_cic(crc32fast::hash(&rom[0x40..0x1000]));
+    let checksum_valid = cic.and_then(|(chip, seed)| {
+        n64_checksum(&rom, chip, seed).map(|crcs| crcs == (be32(&rom, 0x10), be32(&rom, 0x14)))
+    });
+
+    Some(RomHeader {
+        format: "n64",
+        system: "n64",
+        title: header_text(&rom[0x20..0x34]),
+        game_code: header_text(&rom[0x3b..0x3f]),
+        mapper: cic.map(|(chip, _)| format!("CIC-NUS-{}", chip)),
+        region: region_from_code(rom[0x3e]).map(str::to_string),
+        checksum_valid,
+    })
+}
+
+fn parse_gba(data: &[u8]) -> Option<RomHeader> {
+    // Fixed value 0x96 at 0xB2, and the entry point is an ARM branch
+    if data.len() < 0xc0 || data[0xb2] != 0x96 || data[3] != 0xea {
+        return None;
+    }
+    let complement = data[0xa0..0xbd]
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b))
+        .wrapping_sub(0x19);
+
+    Some(RomHeader {
+        format: "gba",
+        system: "gba",
+        title: header_text(&data[0xa0..0xac]),
+        game_code: header_text(&data[0xac..0xb0]),
+        mapper: None,
+        region: region_from_code(data[0xaf]).map(str::to_string),
+        checksum_valid: Some(complement == data[0xbd]),
+    })
+}
+
+/// The logo every licensed Game Boy cartridge carries at 0x104; the boot ROM checks it
+const GB_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+fn gb_cartridge_type(code: u8) -> String {
+    match code {
+        0x00 => "ROM".to_string(),
+        0x01..=0x03 => "MBC1".to_string(),
+        0x05 | 0x06 => "MBC2".to_string(),
+        0x0b..=0x0d => "MMM01".to_string(),
+        0x0f..=0x13 => "MBC3".to_string(),
+        0x19..=0x1e => "MBC5".to_string(),
+        0x20 => "MBC6".to_string(),
+        0x22 => "MBC7".to_string(),
+        0xfc => "Pocket Camera".to_string(),
+        0xfe => "HuC3".to_string(),
+        0xff => "HuC1".to_string(),
+        other => format!("0x{:02x}", other),
+    }
+}
+
+fn parse_gb(data: &[u8]) -> Option<RomHeader> {
+    if data.len() < 0x150 || data[0x104..0x134] != GB_LOGO {
+        return None;
+    }
+    let cgb_flag = data[0x143];
+    let color = cgb_flag == 0x80 || cgb_flag == 0xc0;
+    // Colour-era carts shorten the title to make room for a four-character game code
+    let (title, game_code) = if color {
+        let code = &data[0x13f..0x143];
+        let game_code = code.iter().all(u8::is_ascii_alphanumeric).then(|| header_text(code)).flatten();
+        let title_end = if game_code.is_some() { 0x13f } else { 0x143 };
+        (header_text(&data[0x134..title_end]), game_code)
+    } else {
+        (header_text(&data[0x134..0x144]), None)
+    };
+
+    let header_checksum = data[0x134..0x14d]
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1));
+    let global_checksum = data
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 0x14e && *i != 0x14f)
+        .fold(0u16, |acc, (_, &b)| acc.wrapping_add(u16::from(b)));
+    let stored_global = u16::from_be_bytes([data[0x14e], data[0x14f]]);
+
+    Some(RomHeader {
+        format: "gb",
+        system: if color { "gbc" } else { "gb" },
+        title,
+        game_code,
+        mapper: Some(gb_cartridge_type(data[0x147])),
+        region: Some(if data[0x14a] == 0 { "Japan" } else { "Overseas" }.to_string()),
+        checksum_valid: Some(header_checksum == data[0x14d] && global_checksum == stored_global),
+    })
+}
+
+fn snes_region(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "Japan",
+        0x01 => "USA",
+        0x02 => "Europe",
+        0x03 => "Sweden",
+        0x04 => "Finland",
+        0x05 => "Denmark",
+        0x06 => "France",
+        0x07 => "Netherlands",
+        0x08 => "Spain",
+        0x09 => "Germany",
+        0x0a => "Italy",
+        0x0b => "China",
+        0x0d => "Korea",
+        0x0f => "Canada",
+        0x10 => "Brazil",
+        0x11 => "Australia",
+        _ => return None,
+    })
+}
+
+/// Sum of all bytes, with the tail of a non power-of-two ROM mirrored up to the next power
+fn snes_checksum(rom: &[u8]) -> u16 {
+    let sum = |bytes: &[u8]| bytes.iter().fold(0u32, |acc, &b| acc.wrapping_add(u32::from(b)));
+    if rom.is_empty() || rom.len().is_power_of_two() {
+        return sum(rom) as u16;
+    }
+    let base = 1usize << (usize::BITS - 1 - rom.len().leading_zeros());
+    let remainder = &rom[base..];
+    let mirrors = (base / remainder.len()) as u32;
+    sum(&rom[..base]).wrapping_add(sum(remainder).wrapping_mul(mirrors)) as u16
+}
+
+fn parse_snes(data: &[u8]) -> Option<RomHeader> {
+    // Copier dumps carry an extra 512-byte header in front of the ROM
+    let rom = if data.len() % 1024 == 512 { &data[512..] } else { data };
+
+    let candidates = [(0x7fc0, "LoROM", 0x00), (0xffc0, "HiROM", 0x01), (0x40ffc0, "ExHiROM", 0x05)];
+    let (offset, layout, score) = candidates
+        .iter()
+        .filter(|(offset, _, _)| rom.len() >= offset + 0x40)
+        .map(|&(offset, layout, mode_bits)| {
+            let header = &rom[offset..offset + 0x40];
+            let complement = u16::from_le_bytes([header[0x1c], header[0x1d]]);
+            let checksum = u16::from_le_bytes([header[0x1e], header[0x1f]]);
+            let mut score = 0;
+            if complement ^ checksum == 0xffff {
+                score += 2;
+            }
+            if header[0x15] & 0xe0 == 0x20 && header[0x15] & 0x07 == mode_bits {
+                score += 1;
+            }
+            if header[..0x15].iter().all(|b| (0x20..0x7f).contains(b)) {
+                score += 1;
+            }
+            (offset, layout, score)
+        })
+        .max_by_key(|&(_, _, score)| score)?;
+
+    // Without a matching complement and a plausible map mode this is not a SNES header
+    if score < 3 {
+        return None;
+    }
+    let header = &rom[offset..offset + 0x40];
+    let checksum = u16::from_le_bytes([header[0x1e], header[0x1f]]);
+    // Maker code 0x33 means the extended header with a game code sits just before
+    let game_code = (header[0x1a] == 0x33)
+        .then(|| header_text(&rom[offset - 0x0e..offset - 0x0a]))
+        .flatten();
+
+    Some(RomHeader {
+        format: "snes",
+        system: "snes",
+        title: header_text(&header[..0x15]),
+        game_code,
+        mapper: Some(layout.to_string()),
+        region: snes_region(header[0x19]).map(str::to_string),
+        checksum_valid: Some(snes_checksum(rom) == checksum),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ines_and_nes2() {
+        let mut rom = vec![0u8; 16 + 16384];
+        rom[..4].copy_from_slice(b"NES\x1a");
+        rom[6] = 0x40; // mapper 4 low nibble
+        let header = parse_header(&rom).unwrap();
+        assert_eq!(header.format, "ines");
+        assert_eq!(header.mapper.as_deref(), Some("4"));
+        assert_eq!(header.region.as_deref(), Some("NTSC"));
+
+        rom[7] = 0x08;
+        rom[8] = 0x01; // mapper bits 8-11
+        rom[12] = 0x01;
+        let header = parse_header(&rom).unwrap();
+        assert_eq!(header.format, "nes2");
+        assert_eq!(header.mapper.as_deref(), Some("260"));
+        assert_eq!(header.region.as_deref(), Some("PAL"));
+    }
+
+    #[test]
+    fn test_snes_lorom_checksum() {
+        let mut rom = vec![0u8; 0x20000];
+        let header = 0x7fc0;
+        rom[header..header + 0x15].copy_from_slice(b"TEST GAME            ");
+        rom[header + 0x15] = 0x20;
+        rom[header + 0x19] = 0x01;
+        // Complement + checksum always add 0x1fe to the sum, so checksum = sum(rest) + 0x1fe
+        let rest = rom.iter().fold(0u32, |acc, &b| acc + u32::from(b)) as u16;
+        let checksum = rest.wrapping_add(0x1fe);
+        rom[header + 0x1c..header + 0x1e].copy_from_slice(&(!checksum).to_le_bytes());
+        rom[header + 0x1e..header + 0x20].copy_from_slice(&checksum.to_le_bytes());
+
+        let parsed = parse_header(&rom).unwrap();
+        assert_eq!(parsed.format, "snes");
+        assert_eq!(parsed.mapper.as_deref(), Some("LoROM"));
+        assert_eq!(parsed.title.as_deref(), Some("TEST GAME"));
+        assert_eq!(parsed.region.as_deref(), Some("USA"));
+        assert_eq!(parsed.checksum_valid, Some(true));
+
+        // Same ROM behind a copier header
+        let mut copier = vec![0u8; 512];
+        copier.extend_from_slice(&rom);
+        assert_eq!(parse_header(&copier).unwrap().checksum_valid, Some(true));
+
+        rom[0] = 1;
+        assert_eq!(parse_header(&rom).unwrap().checksum_valid, Some(false));
+    }
+
+    #[test]
+    fn test_gb_color_header() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x104..0x134].copy_from_slice(&GB_LOGO);
+        rom[0x134..0x13f].copy_from_slice(b"POKEMON GLD");
+        rom[0x13f..0x143].copy_from_slice(b"AAUE");
+        rom[0x143] = 0x80;
+        rom[0x147] = 0x10;
+        rom[0x14a] = 0x01;
+        rom[0x14d] = rom[0x134..0x14d].iter().fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1));
+        let global = rom.iter().fold(0u16, |acc, &b| acc.wrapping_add(u16::from(b)));
+        rom[0x14e..0x150].copy_from_slice(&global.to_be_bytes());
+
+        let header = parse_header(&rom).unwrap();
+        assert_eq!(header.system, "gbc");
+        assert_eq!(header.title.as_deref(), Some("POKEMON GLD"));
+        assert_eq!(header.game_code.as_deref(), Some("AAUE"));
+        assert_eq!(header.mapper.as_deref(), Some("MBC3"));
+        assert_eq!(header.checksum_valid, Some(true));
+    }
+
+    #[test]
+    fn test_gba_header() {
+        let mut rom = vec![0u8; 0x200];
+        rom[3] = 0xea;
+        rom[0xa0..0xac].copy_from_slice(b"POKEMON EMER");
+        rom[0xac..0xb0].copy_from_slice(b"BPEE");
+        rom[0xb0..0xb2].copy_from_slice(b"01");
+        rom[0xb2] = 0x96;
+        rom[0xbd] = rom[0xa0..0xbd].iter().fold(0u8, |acc, &b| acc.wrapping_sub(b)).wrapping_sub(0x19);
+
+        let header = parse_header(&rom).unwrap();
+        assert_eq!(header.format, "gba");
+        assert_eq!(header.game_code.as_deref(), Some("BPEE"));
+        assert_eq!(header.region.as_deref(), Some("USA"));
+        assert_eq!(header.checksum_valid, Some(true));
+    }
+
+    #[test]
+    fn test_n64_byte_orders() {
+        let mut z64 = vec![0u8; 0x1000];
+        z64[..4].copy_from_slice(&[0x80, 0x37, 0x12, 0x40]);
+        z64[0x20..0x34].copy_from_slice(b"SUPER MARIO 64      ");
+        z64[0x3b..0x3f].copy_from_slice(b"NSME");
+
+        let v64: Vec<u8> = z64.chunks(2).flat_map(|c| [c[1], c[0]]).collect();
+        let n64: Vec<u8> = z64.chunks(4).flat_map(|c| [c[3], c[2], c[1], c[0]]).collect();
+        for image in [&z64, &v64, &n64] {
+            let header = parse_header(image).unwrap();
+            assert_eq!(header.title.as_deref(), Some("SUPER MARIO 64"));
+            assert_eq!(header.game_code.as_deref(), Some("NSME"));
+            assert_eq!(header.region.as_deref(), Some("USA"));
+            // Unknown boot code, so the checksum can't be checked
+            assert_eq!(header.checksum_valid, None);
+        }
+    }
+
+    #[test]
+    fn test_unknown_data() {
+        assert_eq!(parse_header(&[0u8; 0x1000]), None);
+    }
+}
@@ -1,8 +1,21 @@
-use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use serde::Serialize;
 use walkdir::WalkDir;
-use regex;
+use rayon::prelude::*;
+use crate::archive::{for_each_archive_file, is_archive_extension, list_archive};
+use crate::bios::BIOS_DIR_NAME;
 use crate::emulators::{get_all_emulators, EmulatorInfo};
+use crate::rom_hash::{hash_reader, RomHashes};
+use crate::rom_header::{parse_header, RomHeader, MAX_HEADER_ROM_SIZE};
+use crate::rom_tags::{parse_tags, RomTags};
+use crate::disc_sniff::{is_ambiguous_disc_extension, sniff_disc_file};
+use crate::multi_disc::group_multi_file_games;
+use crate::scan_config::ScanConfig;
 
 /// Maps directory names to standardized system names
 pub fn get_system_mapping() -> HashMap<&'static str, &'static str> {
@@ -55,13 +68,121 @@ pub struct RomFile {
     pub system: String,
     pub extension: String,
     pub size: u64,
+    /// Modification time in seconds since the Unix epoch (the archive's, for archived ROMs)
+    pub mtime: i64,
     pub suggested_emulator: Option<String>,
+    /// Region/language/revision tags parsed from the original file name
+    pub tags: RomTags,
+    /// Filled in by `hash_roms`; the directory walk itself only peeks at disc images
+    pub hashes: Option<RomHashes>,
+    /// Internal cartridge header, also filled in by `hash_roms`
+    pub header: Option<RomHeader>,
+    /// Platform (ROM directory name) detected from the contents of a disc image
+    pub disc_platform: Option<&'static str>,
+    /// Track files of a cue/gdi sheet (or of every disc in a set), relative to the ROMs directory
+    pub tracks: Vec<String>,
+    /// Disc files of a multi-disc game, in disc order
+    pub discs: Vec<String>,
+    /// Set when the ROM is a file inside a .zip/.7z rather than a file of its own
+    pub archive: Option<ArchiveMember>,
 }
 
-/// Scan a directory for ROM files
-pub fn scan_roms_directory(base_path: &Path) -> Vec<RomFile> {
+/// Where an archived ROM lives. The game's `file_path` is `<path>#<entry>`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ArchiveMember {
+    /// The archive, relative to the ROMs directory
+    pub path: String,
+    /// The ROM's name inside the archive
+    pub entry: String,
+}
+
+/// Archive entries that are never ROMs and aren't worth decompressing to find out
+pub(crate) const NON_ROM_EXTENSIONS: &[&str] = &["txt", "nfo", "diz", "pdf", "jpg", "jpeg", "png", "gif", "htm", "html", "xml", "dat", "sav", "srm"];
+
+impl RomFile {
+    /// The `games.metadata` object for this ROM. `header` is stored even when null
+    /// so later scans know the file has already been checked.
+    pub fn metadata(&self) -> serde_json::Value {
+        let mut metadata = serde_json::json!({
+            "tags": self.tags,
+            "header": self.header,
+            "disc_platform": self.disc_platform,
+        });
+        if !self.tracks.is_empty() {
+            metadata["tracks"] = serde_json::json!(self.tracks);
+        }
+        if !self.discs.is_empty() {
+            metadata["discs"] = serde_json::json!(self.discs);
+        }
+        if let Some(archive) = &self.archive {
+            metadata["archive"] = serde_json::json!(archive);
+        }
+        metadata
+    }
+
+    /// True when the cartridge header or disc contents belong to a different system
+    /// than the directory the file was found in
+    pub fn is_mislabelled(&self, system_map: &HashMap<String, &'static str>) -> bool {
+        let detected = self.header.as_ref().map(|h| h.system).or(self.disc_platform);
+        let directory = self.file_path.split('/').next().unwrap_or_default();
+        match (detected.and_then(|d| system_map.get(d)), system_map.get(directory)) {
+            (Some(detected), Some(directory)) => detected != directory,
+            _ => false,
+        }
+    }
+}
+
+/// Stage a library scan is in
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanPhase {
+    #[default]
+    Walking,
+    /// Hashing new and changed files
+    Reading,
+    Saving,
+    Identifying,
+    Finished,
+}
+
+/// Counters a scan updates as it goes, so it can be watched while it runs
+#[derive(Default)]
+pub struct ScanProgress {
+    phase: Mutex<(ScanPhase, Option<Instant>)>,
+    pub files_seen: AtomicUsize,
+    pub files_to_read: AtomicUsize,
+    pub files_read: AtomicUsize,
+    pub bytes_to_read: AtomicU64,
+    pub bytes_read: AtomicU64,
+    pub added: AtomicUsize,
+    pub skipped: AtomicUsize,
+    pub errors: AtomicUsize,
+}
+
+impl ScanProgress {
+    pub fn set_phase(&self, phase: ScanPhase) {
+        *self.phase.lock().unwrap() = (phase, Some(Instant::now()));
+    }
+
+    /// The current phase and when it started
+    pub fn phase(&self) -> (ScanPhase, Option<Instant>) {
+        *self.phase.lock().unwrap()
+    }
+}
+
+/// What a walk of the ROMs directory turned up
+pub struct DirectoryScan {
+    /// Multi-file and multi-disc games come back as one entry
+    pub roms: Vec<RomFile>,
+    /// Top-level directories that don't name a known system or alias
+    pub unknown_directories: Vec<String>,
+}
+
+/// Scan a directory for ROM files, one subdirectory per system
+pub fn scan_roms_directory(base_path: &Path, config: &ScanConfig, progress: &ScanProgress) -> DirectoryScan {
     let mut roms = Vec::new();
-    let system_map = get_system_mapping();
+    let mut unknown_directories = Vec::new();
+    let system_map = config.systems();
     let emulators = get_all_emulators();
     
     // Scan each subdirectory
@@ -80,27 +201,62 @@ pub fn scan_roms_directory(base_path: &Path) -> Vec<RomFile> {
             Some(name) => name,
             None => continue,
         };
+        if dir_name.starts_with('.')
+            || dir_name == BIOS_DIR_NAME
+            || config.is_ignored(dir_name)
+            || config.is_ignored(&format!("{}/", dir_name))
+        {
+            continue;
+        }
         
         // Get system name from directory
-        let system_name = match system_map.get(dir_name) {
-            Some(&name) => name,
-            None => continue, // Skip unknown directories
+        let system_name = match config.system_for_directory(dir_name) {
+            Some(name) => name,
+            None => {
+                unknown_directories.push(dir_name.to_string());
+                continue;
+            }
         };
         
         // Scan all files in this directory
         for file_entry in WalkDir::new(&path)
-            .max_depth(3)
+            .max_depth(config.max_depth)
             .into_iter()
             .filter_map(|e| e.ok())
             .filter(|e| e.file_type().is_file())
         {
+            progress.files_seen.fetch_add(1, Ordering::Relaxed);
             let file_path = file_entry.path();
+            let relative_path = file_path
+                .strip_prefix(base_path)
+                .unwrap_or(file_path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            if config.is_ignored(&relative_path) {
+                continue;
+            }
             let extension = file_path
                 .extension()
                 .and_then(|e| e.to_str())
                 .unwrap_or("")
                 .to_lowercase();
             
+            if is_archive_extension(&extension) {
+                roms.extend(scan_archive(base_path, file_path, &extension, system_name, system_map, &emulators));
+                continue;
+            }
+            
+            // A disc image's contents beat the directory it was dropped in
+            let disc_platform = if is_ambiguous_disc_extension(&extension) {
+                sniff_disc_file(file_path, &extension)
+            } else {
+                None
+            };
+            let system_name = disc_platform
+                .and_then(|platform| system_map.get(platform).copied())
+                .filter(|&system| find_compatible_emulator(&emulators, system, &extension).is_some())
+                .unwrap_or(system_name);
+            
             // Find compatible emulator
             let suggested_emulator = find_compatible_emulator(&emulators, system_name, &extension);
             
@@ -109,16 +265,7 @@ pub fn scan_roms_directory(base_path: &Path) -> Vec<RomFile> {
                 continue;
             }
             
-            let file_size = std::fs::metadata(file_path)
-                .ok()
-                .map(|m| m.len())
-                .unwrap_or(0);
-            
-            let relative_path = file_path
-                .strip_prefix(base_path)
-                .unwrap_or(file_path)
-                .to_string_lossy()
-                .replace('\\', "/");
+            let (file_size, mtime) = file_size_and_mtime(file_path);
             
             let file_name = file_path
                 .file_stem()
@@ -132,16 +279,252 @@ pub fn scan_roms_directory(base_path: &Path) -> Vec<RomFile> {
                 system: system_name.to_string(),
                 extension,
                 size: file_size,
+                mtime,
                 suggested_emulator,
+                tags: parse_tags(&file_name),
+                hashes: None,
+                header: None,
+                disc_platform,
+                tracks: Vec::new(),
+                discs: Vec::new(),
+                archive: None,
             });
         }
     }
     
+    unknown_directories.sort();
+    DirectoryScan {
+        roms: group_multi_file_games(base_path, roms, &emulators),
+        unknown_directories,
+    }
+}
+
+/// Size and mtime (Unix seconds) of a file, zero when unreadable
+pub(crate) fn file_size_and_mtime(path: &Path) -> (u64, i64) {
+    std::fs::metadata(path)
+        .map(|m| {
+            let mtime = m
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            (m.len(), mtime)
+        })
+        .unwrap_or((0, 0))
+}
+
+/// Extension an emulator expects for a ROM identified only by its header
+pub(crate) fn extension_for_header_system(system: &str) -> &'static str {
+    match system {
+        "snes" => "sfc",
+        "n64" => "z64",
+        "gb" => "gb",
+        "gbc" => "gbc",
+        "gba" => "gba",
+        _ => "nes",
+    }
+}
+
+/// One ROM per recognisable entry of a .zip/.7z. Entries are matched by extension
+/// first; anything else small enough is decompressed and identified by its header.
+fn scan_archive(
+    base_path: &Path,
+    archive_path: &Path,
+    archive_extension: &str,
+    directory_system: &'static str,
+    system_map: &HashMap<String, &'static str>,
+    emulators: &[EmulatorInfo],
+) -> Vec<RomFile> {
+    let relative_path = archive_path
+        .strip_prefix(base_path)
+        .unwrap_or(archive_path)
+        .to_string_lossy()
+        .replace('\\', "/");
+    let listing = match list_archive(archive_path, archive_extension) {
+        Ok(listing) => listing,
+        Err(e) => {
+            eprintln!("Warning: could not read archive {}: {}", relative_path, e);
+            return Vec::new();
+        }
+    };
+    
+    let extension_of = |name: &str| {
+        Path::new(name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase()
+    };
+    let system_for = |extension: &str| {
+        let compatible = |system: &&str| find_compatible_emulator(emulators, system, extension).is_some();
+        Some(directory_system)
+            .filter(compatible)
+            .or_else(|| {
+                get_directory_for_extension(extension)
+                    .and_then(|dir| system_map.get(dir).copied())
+                    .filter(compatible)
+            })
+    };
+    let listing: Vec<_> = listing
+        .into_iter()
+        .filter(|entry| {
+            let extension = extension_of(&entry.name);
+            !is_archive_extension(&extension) && !NON_ROM_EXTENSIONS.contains(&extension.as_str())
+        })
+        .collect();
+    
+    // Entries the extension doesn't place are identified by header, all read in one pass:
+    // a solid 7z can only be decoded front to back, so reading them one at a time would
+    // decompress the start of the archive again for every entry
+    let unidentified: HashSet<&str> = listing
+        .iter()
+        .filter(|entry| entry.size <= MAX_HEADER_ROM_SIZE && system_for(&extension_of(&entry.name)).is_none())
+        .map(|entry| entry.name.as_str())
+        .collect();
+    let mut headers = HashMap::new();
+    if !unidentified.is_empty() {
+        let read = for_each_archive_file(archive_path, archive_extension, |name, reader| {
+            if unidentified.contains(name) {
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data)?;
+                if let Some(header) = parse_header(&data) {
+                    headers.insert(name.to_string(), header);
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = read {
+            eprintln!("Warning: could not read archive {}: {}", relative_path, e);
+        }
+    }
+    
+    let (_, mtime) = file_size_and_mtime(archive_path);
+    let mut roms = Vec::new();
+    for entry in listing {
+        let mut extension = extension_of(&entry.name);
+        let mut system = system_for(&extension);
+        let header = headers.remove(&entry.name);
+        if let Some(found) = header.as_ref().filter(|_| system.is_none()) {
+            system = system_map.get(found.system).copied();
+            extension = extension_for_header_system(found.system).to_string();
+        }
+        
+        let Some(system) = system else { continue };
+        let Some(suggested_emulator) = find_compatible_emulator(emulators, system, &extension) else { continue };
+        
+        let stem = Path::new(&entry.name)
+            .file_stem()
+            .and_then(|n| n.to_str())
+            .unwrap_or("Unknown")
+            .to_string();
+        
+        roms.push(RomFile {
+            file_path: format!("{}#{}", relative_path, entry.name),
+            file_name: clean_filename(&stem),
+            system: system.to_string(),
+            extension,
+            size: entry.size,
+            mtime,
+            suggested_emulator: Some(suggested_emulator),
+            tags: parse_tags(&stem),
+            hashes: None,
+            header,
+            disc_platform: None,
+            tracks: Vec::new(),
+            discs: Vec::new(),
+            archive: Some(ArchiveMember {
+                path: relative_path.clone(),
+                entry: entry.name,
+            }),
+        });
+    }
+    
     roms
 }
 
+/// Hash a ROM's contents, reading cartridge-sized data whole so the header can be parsed too
+fn hash_and_parse(reader: &mut dyn Read, size: u64) -> std::io::Result<(RomHashes, Option<RomHeader>)> {
+    if size > MAX_HEADER_ROM_SIZE {
+        return Ok((hash_reader(reader)?, None));
+    }
+    let mut data = Vec::with_capacity(size as usize);
+    reader.read_to_end(&mut data)?;
+    Ok((hash_reader(&data[..])?, parse_header(&data)))
+}
+
+/// Store the outcome of reading one ROM, returning the error message if it failed
+fn finish_hashing(
+    rom: &mut RomFile,
+    result: std::io::Result<(RomHashes, Option<RomHeader>)>,
+    progress: &ScanProgress,
+) -> Option<String> {
+    progress.files_read.fetch_add(1, Ordering::Relaxed);
+    progress.bytes_read.fetch_add(rom.size, Ordering::Relaxed);
+    match result {
+        Ok((hashes, header)) => {
+            rom.hashes = Some(hashes);
+            rom.header = header;
+            None
+        }
+        Err(e) => Some(format!("Failed to hash {}: {}", rom.file_path, e)),
+    }
+}
+
+/// Hash the given ROMs from one archive in a single pass over it, so a solid 7z is
+/// only decompressed once however many of its entries are needed
+fn hash_archive(base_path: &Path, archive: &str, roms: Vec<&mut RomFile>, progress: &ScanProgress) -> Vec<String> {
+    let archive_extension = archive.rsplit('.').next().unwrap_or_default().to_lowercase();
+    let mut pending: HashMap<String, &mut RomFile> = roms
+        .into_iter()
+        .filter_map(|rom| Some((rom.archive.as_ref()?.entry.clone(), rom)))
+        .collect();
+    let mut errors = Vec::new();
+    let read = for_each_archive_file(&base_path.join(archive), &archive_extension, |name, reader| {
+        let Some(rom) = pending.remove(name) else { return Ok(()) };
+        let result = hash_and_parse(reader, rom.size);
+        // A failed read leaves the decoder mid-entry, so nothing after it can be trusted
+        let failed = result.as_ref().err().map(|e| std::io::Error::new(e.kind(), e.to_string()));
+        errors.extend(finish_hashing(rom, result, progress));
+        failed.map_or(Ok(()), Err)
+    });
+    let reason = match read {
+        Err(e) => e.to_string(),
+        Ok(()) => "not in archive".to_string(),
+    };
+    for rom in pending.into_values() {
+        errors.extend(finish_hashing(rom, Err(std::io::Error::other(reason.clone())), progress));
+    }
+    errors
+}
+
+/// Compute CRC32/MD5/SHA-1 for the given ROMs in parallel, returning one error
+/// message per file that could not be read. Blocks until every file is done.
+/// Archived ROMs are hashed on their decompressed contents, as DATs list them,
+/// with each archive read once for all of its ROMs.
+pub fn hash_roms(base_path: &Path, roms: Vec<&mut RomFile>, progress: &ScanProgress) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut archives: HashMap<String, Vec<&mut RomFile>> = HashMap::new();
+    for rom in roms {
+        match &rom.archive {
+            Some(member) => archives.entry(member.path.clone()).or_default().push(rom),
+            None => files.push(rom),
+        }
+    }
+    
+    let file_errors = files.into_par_iter().filter_map(|rom| {
+        let result = std::fs::File::open(base_path.join(&rom.file_path))
+            .and_then(|mut file| hash_and_parse(&mut file, rom.size));
+        finish_hashing(rom, result, progress)
+    });
+    let archive_errors = archives
+        .into_par_iter()
+        .flat_map_iter(|(archive, roms)| hash_archive(base_path, &archive, roms, progress));
+    file_errors.chain(archive_errors).collect()
+}
+
 /// Find a compatible emulator for a given system and file extension
-fn find_compatible_emulator(
+pub(crate) fn find_compatible_emulator(
     emulators: &[EmulatorInfo],
     system_name: &str,
     extension: &str,
@@ -156,14 +539,14 @@ fn find_compatible_emulator(
 }
 
 /// Clean up filename for display (remove common tags and underscores)
-fn clean_filename(name: &str) -> String {
+pub(crate) fn clean_filename(name: &str) -> String {
     // Remove common ROM tags like (USA), [!], etc.
     let cleaned = regex::Regex::new(r"\([^)]*\)|\[[^\]]*\]|\{[^}]*\}")
         .unwrap()
         .replace_all(name, "");
     
     // Replace underscores and dots with spaces
-    let cleaned = cleaned.replace('_', " ").replace('.', " ");
+    let cleaned = cleaned.replace(['_', '.'], " ");
     
     // Remove multiple spaces
     let cleaned = regex::Regex::new(r"\s+")
@@ -249,5 +632,56 @@ mod tests {
         assert_eq!(get_directory_for_extension("iso"), Some("gamecube"));
         assert_eq!(get_directory_for_extension("xyz"), None);
     }
+
+    #[test]
+    fn test_hash_roms_reads_each_archive_once() {
+        use std::io::Write;
+
+        let base = std::env::temp_dir().join(format!("hash-roms-test-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(base.join("set.zip")).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            zip.start_file("a.gb", options).unwrap();
+            zip.write_all(b"first").unwrap();
+            zip.start_file("b.gb", options).unwrap();
+            zip.write_all(b"second").unwrap();
+            zip.finish().unwrap();
+        }
+        std::fs::write(base.join("c.gb"), b"third").unwrap();
+
+        let rom = |file_path: &str, size: u64, entry: Option<&str>| RomFile {
+            file_path: file_path.to_string(),
+            file_name: file_path.to_string(),
+            system: "gb".to_string(),
+            extension: "gb".to_string(),
+            size,
+            mtime: 0,
+            suggested_emulator: None,
+            tags: RomTags::default(),
+            hashes: None,
+            header: None,
+            disc_platform: None,
+            tracks: Vec::new(),
+            discs: Vec::new(),
+            archive: entry.map(|entry| ArchiveMember { path: "set.zip".to_string(), entry: entry.to_string() }),
+        };
+        let mut roms = [
+            rom("set.zip#a.gb", 5, Some("a.gb")),
+            rom("set.zip#b.gb", 6, Some("b.gb")),
+            rom("set.zip#gone.gb", 1, Some("gone.gb")),
+            rom("c.gb", 5, None),
+        ];
+        let progress = ScanProgress::default();
+        let errors = hash_roms(&base, roms.iter_mut().collect(), &progress);
+        std::fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(errors, vec!["Failed to hash set.zip#gone.gb: not in archive".to_string()]);
+        assert_eq!(progress.files_read.load(Ordering::Relaxed), 4);
+        for (rom, contents) in roms.iter().zip([&b"first"[..], b"second", b"", b"third"]) {
+            let expected = (!contents.is_empty()).then(|| hash_reader(contents).unwrap());
+            assert_eq!(rom.hashes, expected, "{}", rom.file_path);
+        }
+    }
 }
 
@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+
+/// Structured form of the `(...)`, `[...]` and `{...}` tags in a No-Intro or GoodTools
+/// file name, e.g. "Tetris (Japan) (En,Ja) (Rev 1)" or "Contra (U) [!]"
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RomTags {
+    pub regions: Vec<String>,
+    pub languages: Vec<String>,
+    pub revision: Option<String>,
+    /// GoodTools `[!]`, or a DAT entry marked verified
+    pub verified: bool,
+    /// GoodTools `[b]`, or a DAT entry marked baddump
+    pub bad_dump: bool,
+    pub hack: bool,
+    pub translation: bool,
+}
+
+/// No-Intro region names, as they appear inside the parentheses
+const REGION_NAMES: &[&str] = &[
+    "World", "USA", "Europe", "Japan", "Asia", "Australia", "Brazil", "Canada", "China",
+    "France", "Germany", "Hong Kong", "Italy", "Korea", "Netherlands", "Russia",
+    "Scandinavia", "Spain", "Sweden", "Taiwan", "UK", "Unknown",
+];
+
+/// GoodTools single-country codes
+fn goodtools_region(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "W" => "World",
+        "U" => "USA",
+        "E" => "Europe",
+        "J" => "Japan",
+        "A" => "Australia",
+        "B" => "Brazil",
+        "C" => "China",
+        "F" => "France",
+        "G" => "Germany",
+        "H" => "Netherlands",
+        "I" => "Italy",
+        "K" => "Korea",
+        "R" => "Russia",
+        "S" => "Spain",
+        "Sw" => "Sweden",
+        "Unk" => "Unknown",
+        _ => return None,
+    })
+}
+
+fn parse_regions(group: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = group.split(',').map(str::trim).collect();
+    if parts.iter().all(|p| REGION_NAMES.contains(p)) {
+        return Some(parts.iter().map(|p| p.to_string()).collect());
+    }
+
+    // GoodTools codes: a single code, or several country letters run together ("JU", "UE")
+    if let Some(region) = goodtools_region(group) {
+        return Some(vec![region.to_string()]);
+    }
+    if group.len() > 1 && group.chars().all(|c| c.is_ascii_uppercase()) {
+        return group
+            .chars()
+            .map(|c| goodtools_region(&c.to_string()).map(str::to_string))
+            .collect();
+    }
+    None
+}
+
+/// "En", "En,Fr,De" or "En+Ja" style language lists
+fn parse_languages(group: &str) -> Option<Vec<String>> {
+    let langs: Vec<String> = group.split([',', '+']).map(|l| l.trim().to_string()).collect();
+    let is_code = |l: &String| {
+        let mut chars = l.chars();
+        l.len() == 2
+            && chars.next().is_some_and(|c| c.is_ascii_uppercase())
+            && chars.next().is_some_and(|c| c.is_ascii_lowercase())
+    };
+    langs.iter().all(is_code).then_some(langs)
+}
+
+/// "Rev 1", "Rev A", or GoodTools "V1.1" / "PRG1"
+fn parse_revision(group: &str) -> Option<String> {
+    if let Some(rev) = group.strip_prefix("Rev ") {
+        return Some(rev.trim().to_string());
+    }
+    let version = group
+        .strip_prefix('V')
+        .or_else(|| group.strip_prefix('v'))
+        .or_else(|| group.strip_prefix("PRG"))?;
+    (!version.is_empty() && version.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        .then(|| version.to_string())
+}
+
+fn apply_bracket_flag(tags: &mut RomTags, flag: &str) {
+    // GoodTools numbers repeated flags ("b1", "h2C"), so only the leading letters matter
+    if flag == "!" {
+        tags.verified = true;
+    } else if flag.starts_with("T+") || flag.starts_with("T-") {
+        tags.translation = true;
+    } else if flag.starts_with('b') && flag[1..].chars().all(|c| c.is_ascii_digit()) {
+        tags.bad_dump = true;
+    } else if flag.starts_with('h') {
+        tags.hack = true;
+    }
+}
+
+/// Parse every tag in a file name (without extension). `{...}` groups are read like
+/// `(...)` ones, as some sets put regions, versions or "Hack" in braces. Unrecognised
+/// tags are ignored.
+pub fn parse_tags(name: &str) -> RomTags {
+    let mut tags = RomTags::default();
+    let mut rest = name;
+
+    while let Some(start) = rest.find(['(', '[', '{']) {
+        let close = match rest.as_bytes()[start] {
+            b'(' => ')',
+            b'[' => ']',
+            _ => '}',
+        };
+        let Some(len) = rest[start + 1..].find(close) else { break };
+        let group = rest[start + 1..start + 1 + len].trim();
+        rest = &rest[start + 1 + len + 1..];
+
+        if close == ']' {
+            apply_bracket_flag(&mut tags, group);
+            continue;
+        }
+
+        if tags.regions.is_empty() {
+            if let Some(regions) = parse_regions(group) {
+                tags.regions = regions;
+                continue;
+            }
+        }
+        if tags.languages.is_empty() {
+            if let Some(languages) = parse_languages(group) {
+                tags.languages = languages;
+                continue;
+            }
+        }
+        if tags.revision.is_none() {
+            if let Some(revision) = parse_revision(group) {
+                tags.revision = Some(revision);
+                continue;
+            }
+        }
+        if group.eq_ignore_ascii_case("hack") {
+            tags.hack = true;
+        }
+    }
+
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_intro_names() {
+        let tags = parse_tags("Legend of Zelda, The - A Link to the Past (USA, Europe) (En,Fr,De) (Rev 1)");
+        assert_eq!(tags.regions, vec!["USA", "Europe"]);
+        assert_eq!(tags.languages, vec!["En", "Fr", "De"]);
+        assert_eq!(tags.revision.as_deref(), Some("1"));
+        assert!(!tags.verified && !tags.hack);
+
+        let tags = parse_tags("Pokemon - Red Version (USA, Europe) (SGB Enhanced)");
+        assert_eq!(tags.regions, vec!["USA", "Europe"]);
+        assert!(tags.languages.is_empty());
+    }
+
+    #[test]
+    fn test_goodtools_names() {
+        let tags = parse_tags("Super Mario Bros. 3 (U) (PRG1) [!]");
+        assert_eq!(tags.regions, vec!["USA"]);
+        assert_eq!(tags.revision.as_deref(), Some("1"));
+        assert!(tags.verified);
+
+        let tags = parse_tags("Final Fantasy V (J) [T+Eng1.1_RPGe]");
+        assert_eq!(tags.regions, vec!["Japan"]);
+        assert!(tags.translation);
+
+        let tags = parse_tags("Street Fighter II (JU) (V1.1) [b2][h1C]");
+        assert_eq!(tags.regions, vec!["Japan", "USA"]);
+        assert_eq!(tags.revision.as_deref(), Some("1.1"));
+        assert!(tags.bad_dump && tags.hack && !tags.verified);
+    }
+
+    #[test]
+    fn test_brace_tags() {
+        let tags = parse_tags("Sonic the Hedgehog {Europe} {En,Fr} {Rev 1}");
+        assert_eq!(tags.regions, vec!["Europe"]);
+        assert_eq!(tags.languages, vec!["En", "Fr"]);
+        assert_eq!(tags.revision.as_deref(), Some("1"));
+
+        let tags = parse_tags("Super Mario World (U) {Hack} [!]");
+        assert_eq!(tags.regions, vec!["USA"]);
+        assert!(tags.hack && tags.verified);
+    }
+
+    #[test]
+    fn test_untagged_name() {
+        assert_eq!(parse_tags("Tetris"), RomTags::default());
+        assert_eq!(parse_tags("Broken (USA"), RomTags::default());
+    }
+}
@@ -1,164 +1,1196 @@
 use axum::{
-    extract::Extension,
-    response::Json,
-    http::StatusCode,
+    body::Body,
+    extract::{Extension, Query},
+    response::{IntoResponse, Json, Response},
+    http::{header, StatusCode},
 };
-use axum_extra::extract::Multipart;
+use axum_extra::extract::{multipart::Field, Multipart};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::path::{Path, PathBuf};
-use tokio::io::AsyncWriteExt;
-use crate::rom_scanner::{scan_roms_directory, get_directory_for_extension, RomFile};
-use crate::emulators::get_emulator_by_id;
-use crate::games::Game;
+use rand::{distributions::Alphanumeric, Rng};
+use std::io::Write;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use crate::rom_scanner::{
+    scan_roms_directory, get_directory_for_extension, hash_roms, file_size_and_mtime, find_compatible_emulator,
+    extension_for_header_system, RomFile, ScanPhase, ScanProgress, NON_ROM_EXTENSIONS,
+};
+use crate::archive::{for_each_archive_file, is_upload_archive_extension, with_archive_entry};
+use crate::emulators::{get_emulator_by_id, EmulatorInfo};
+use crate::auth::AuthUser;
+use crate::dat;
+use crate::disc_sniff::{is_ambiguous_disc_extension, sniff_disc_file};
+use crate::rom_hash::{RomHasher, RomHashes};
+use crate::rom_header::{parse_header, MAX_HEADER_ROM_SIZE};
+use crate::rom_tags::{parse_tags, RomTags};
+use crate::multi_disc::group_multi_file_games;
+use crate::scan_config::{scan_config_path, ScanConfig};
+use crate::scan_jobs::{ScanJobs, ScanStatus};
+use crate::upload_limits::UploadLimits;
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone, Default)]
 pub struct ScanResult {
     pub total_found: usize,
     pub newly_added: usize,
     pub already_exists: usize,
+    pub hashed: usize,
+    /// ROMs matched against an imported DAT
+    pub identified: usize,
+    /// Hashed ROMs with no DAT entry (only reported once DATs are imported)
+    pub unmatched: Vec<String>,
+    /// ROMs a DAT lists as a bad or missing dump
+    pub bad_dumps: Vec<String>,
+    /// ROMs whose cartridge header belongs to a different system than their directory
+    pub mislabelled: Vec<String>,
+    /// Known files whose size or mtime changed and were re-read
+    pub changed: usize,
+    /// Games whose file was found again under a new path
+    pub moved: usize,
+    /// Games previously marked missing whose file is back
+    pub restored: usize,
+    /// Files that disappeared; their games are marked missing
+    pub missing: Vec<String>,
+    /// Games deleted because their file disappeared (only with `remove_missing`)
+    pub removed: usize,
+    /// Top-level directories skipped because they don't name a system; add an alias to scan them
+    pub unknown_directories: Vec<String>,
     pub errors: Vec<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct UploadResult {
     pub success: bool,
     pub message: String,
     pub game_id: Option<i32>,
     pub file_path: Option<String>,
+    /// The game already holding these contents, when that stopped the upload
+    #[serde(default)]
+    pub duplicate_of: Option<DuplicateGame>,
+    /// The file as uploaded; `<archive>#<entry>` for one unpacked from an archive
+    #[serde(default)]
+    pub file_name: Option<String>,
+    /// HTTP status of a failed upload: 409 for a clash, 413 when too large and so on
+    #[serde(default)]
+    pub status: Option<u16>,
+}
+
+/// An existing game with the same contents (SHA-1) as an upload
+#[derive(Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct DuplicateGame {
+    pub game_id: i32,
+    pub title: String,
+    pub file_path: String,
+    /// Its file is gone, so replacing it brings the game back
+    pub missing: bool,
 }
 
 #[derive(Deserialize)]
 pub struct ScanRequest {
     pub rescan: Option<bool>,
+    pub remove_missing: Option<bool>,
+}
+
+/// Directory holding the ROMs, one subdirectory per system, set with ROMS_PATH
+pub fn roms_path() -> PathBuf {
+    PathBuf::from(std::env::var("ROMS_PATH").unwrap_or_else(|_| "/roms".to_string()))
 }
 
-/// Scan ROMs directory and add discovered ROMs to database
+/// Start a background scan that syncs the database with the ROMs directory.
+/// Responds 202 with the new job, or 409 with the job that is already running.
 pub async fn scan_roms(
     Extension(pool): Extension<Arc<PgPool>>,
-) -> Result<Json<ScanResult>, StatusCode> {
-    let roms_path = std::env::var("ROMS_PATH").unwrap_or_else(|_| "/roms".to_string());
-    let base_path = Path::new(&roms_path);
+    Extension(jobs): Extension<Arc<ScanJobs>>,
+    auth: AuthUser,
+    Query(request): Query<ScanRequest>,
+) -> Result<(StatusCode, Json<ScanStatus>), StatusCode> {
+    auth.require_admin()?;
     
+    let base_path = roms_path();
     if !base_path.exists() {
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
     
-    let discovered_roms = scan_roms_directory(base_path);
-    let total_found = discovered_roms.len();
-    let mut newly_added = 0;
-    let mut already_exists = 0;
+    let options = ScanOptions {
+        rescan: request.rescan.unwrap_or(false),
+        remove_missing: request.remove_missing.unwrap_or(false),
+    };
+    match jobs.start(pool, base_path, options) {
+        Ok(job) => Ok((StatusCode::ACCEPTED, Json(job.status()))),
+        Err(running) => Ok((StatusCode::CONFLICT, Json(running.status()))),
+    }
+}
+
+/// How a scan treats the existing library
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanOptions {
+    /// Re-read every file even if its size and mtime are unchanged
+    pub rescan: bool,
+    /// Delete games whose files are gone instead of only marking them missing.
+    /// Their save states and play history are deleted with them.
+    pub remove_missing: bool,
+}
+
+/// Rows written per statement when saving a scan
+const WRITE_CHUNK_SIZE: usize = 5000;
+
+/// Column sizes of `games`, checked before a batch so one long name can't fail the rest
+const MAX_FILE_PATH_LEN: usize = 500;
+const MAX_TITLE_LEN: usize = 200;
+
+/// What the database already knows about a file
+#[derive(sqlx::FromRow)]
+struct KnownGame {
+    id: i32,
+    file_path: String,
+    file_size: Option<i64>,
+    file_mtime: Option<i64>,
+    sha1: Option<String>,
+    /// Set once hashes, tags and header have all been stored
+    complete: bool,
+    missing: bool,
+}
+
+impl KnownGame {
+    fn is_unchanged(&self, rom: &RomFile) -> bool {
+        self.complete
+            && self.sha1.is_some()
+            && self.file_size == Some(rom.size as i64)
+            && self.file_mtime == Some(rom.mtime)
+    }
+}
+
+/// Whether a game the directory walk didn't find is still on disk, left out by an
+/// ignore pattern, a lower `max_depth` or a directory no longer mapped to a system.
+/// A ROM inside an archive the walk did read is gone even if the archive is not.
+fn still_on_disk(base_path: &Path, file_path: &str, walked_archives: &HashSet<String>) -> bool {
+    match crate::games::split_archive_path(file_path) {
+        Some((archive, _)) => !walked_archives.contains(archive) && base_path.join(archive).is_file(),
+        None => base_path.join(file_path).exists(),
+    }
+}
+
+/// Emulator id and type name to store for a scanned ROM
+fn emulator_for(rom: &RomFile) -> Result<(String, &'static str), String> {
+    let emulator_id = rom
+        .suggested_emulator
+        .as_ref()
+        .ok_or_else(|| format!("No emulator found for {}", rom.file_name))?;
+    let emulator = get_emulator_by_id(emulator_id)
+        .ok_or_else(|| format!("Invalid emulator ID for {}", rom.file_name))?;
+    
+    let emulator_type_str = match emulator.emulator_type {
+        crate::emulators::EmulatorType::RetroArchCore => "RetroArchCore",
+        crate::emulators::EmulatorType::EmulatorJS => "EmulatorJS",
+        crate::emulators::EmulatorType::NativeService => "NativeService",
+        crate::emulators::EmulatorType::BrowserWASM => "BrowserWASM",
+    };
+    Ok((emulator.id, emulator_type_str))
+}
+
+/// Bring the games table in line with the ROMs directory.
+///
+/// Files whose size and mtime match what was stored last time are skipped. New and
+/// changed files are hashed, and a new file whose SHA-1 matches a game whose file
+/// disappeared is treated as a move, so the game keeps its id, saves and history.
+/// Games whose files are gone are marked missing (or deleted with `remove_missing`).
+/// Freshly hashed ROMs are then identified against the imported DATs.
+/// Only one import may run at a time; `scan_jobs` makes sure of that.
+pub async fn import_roms(
+    pool: &PgPool,
+    base_path: &Path,
+    options: ScanOptions,
+    progress: &Arc<ScanProgress>,
+) -> ScanResult {
     let mut errors = Vec::new();
     
+    // Read on every scan, so edits apply without a restart
+    let (config, config_errors) = ScanConfig::load(&scan_config_path(base_path));
+    errors.extend(config_errors);
+    let config = Arc::new(config);
+    
+    // Walking and hashing touch the disk heavily, keep them off the async runtime
+    progress.set_phase(ScanPhase::Walking);
+    let walk_path = base_path.to_path_buf();
+    let walk_config = config.clone();
+    let walk_progress = progress.clone();
+    let walk = tokio::task::spawn_blocking(move || scan_roms_directory(&walk_path, &walk_config, &walk_progress)).await;
+    let (discovered_roms, unknown_directories, walk_ok) = match walk {
+        Ok(scan) => (scan.roms, scan.unknown_directories, true),
+        Err(e) => {
+            errors.push(format!("ROM scan failed: {}", e));
+            (Vec::new(), Vec::new(), false)
+        }
+    };
+    let total_found = discovered_roms.len();
+    let walked_archives: HashSet<String> = discovered_roms
+        .iter()
+        .filter_map(|rom| Some(rom.archive.as_ref()?.path.clone()))
+        .collect();
+    
+    let known = match sqlx::query_as::<_, KnownGame>(
+        "SELECT id, file_path, file_size, file_mtime, sha1,
+                COALESCE(metadata ?& ARRAY['tags', 'header'], false) AS complete,
+                missing_since IS NOT NULL AS missing
+         FROM games"
+    )
+    .fetch_all(pool)
+    .await
+    {
+        Ok(known) => known,
+        Err(e) => {
+            errors.push(format!("Failed to load existing games: {}", e));
+            return ScanResult {
+                total_found,
+                unknown_directories,
+                errors,
+                ..ScanResult::default()
+            };
+        }
+    };
+    let mut known: HashMap<String, KnownGame> = known.into_iter().map(|g| (g.file_path.clone(), g)).collect();
+    
+    // Only files that are new or changed since the last scan need to be read
+    let mut already_exists = 0;
+    let mut restored = Vec::new();
+    let mut to_insert = Vec::new();
+    let mut to_update = Vec::new();
     for rom in discovered_roms {
-        // Check if already exists
-        let exists: Option<(i64,)> = sqlx::query_as(
-            "SELECT id FROM games WHERE file_path = $1"
-        )
-        .bind(&rom.file_path)
-        .fetch_optional(pool.as_ref())
-        .await
-        .ok()
-        .flatten();
-        
-        if exists.is_some() {
-            already_exists += 1;
-            continue;
+        match known.remove(&rom.file_path) {
+            Some(game) => {
+                already_exists += 1;
+                if game.missing {
+                    restored.push(game.id);
+                }
+                if options.rescan || !game.is_unchanged(&rom) {
+                    to_update.push((game.id, rom));
+                }
+            }
+            None => to_insert.push(rom),
         }
-        
-        // Get emulator info
-        let emulator_id = match &rom.suggested_emulator {
-            Some(id) => id,
-            None => {
-                errors.push(format!("No emulator found for {}", rom.file_name));
+    }
+    // Whatever is left in `known` wasn't found by the walk, but only files that are
+    // really gone count as vanished; the rest are just outside what this scan covers
+    let stat_base = base_path.to_path_buf();
+    let mut vanished = match tokio::task::spawn_blocking(move || {
+        known.retain(|path, _| !still_on_disk(&stat_base, path, &walked_archives));
+        known
+    })
+    .await
+    {
+        Ok(vanished) => vanished,
+        Err(e) => {
+            errors.push(format!("Checking for missing files failed: {}", e));
+            HashMap::new()
+        }
+    };
+    
+    let pending = to_insert.iter().chain(to_update.iter().map(|(_, rom)| rom));
+    progress.skipped.store(already_exists - to_update.len(), Ordering::Relaxed);
+    progress.files_to_read.store(to_insert.len() + to_update.len(), Ordering::Relaxed);
+    progress.bytes_to_read.store(pending.map(|rom| rom.size).sum(), Ordering::Relaxed);
+    progress.set_phase(ScanPhase::Reading);
+    
+    let hash_base = base_path.to_path_buf();
+    let hash_progress = progress.clone();
+    let (to_insert, to_update, hash_errors) = tokio::task::spawn_blocking(move || {
+        let mut to_insert = to_insert;
+        let mut to_update = to_update;
+        let pending = to_insert
+            .iter_mut()
+            .chain(to_update.iter_mut().map(|(_, rom)| rom))
+            .collect();
+        let hash_errors = hash_roms(&hash_base, pending, &hash_progress);
+        (to_insert, to_update, hash_errors)
+    })
+    .await
+    .unwrap_or_else(|e| (Vec::new(), Vec::new(), vec![format!("ROM hashing failed: {}", e)]));
+    errors.extend(hash_errors);
+    progress.errors.store(errors.len(), Ordering::Relaxed);
+    progress.set_phase(ScanPhase::Saving);
+    
+    // A vanished game with the same content as a new file was moved or renamed
+    let mut vanished_by_hash: HashMap<String, Vec<String>> = HashMap::new();
+    for game in vanished.values() {
+        if let Some(sha1) = &game.sha1 {
+            vanished_by_hash.entry(sha1.clone()).or_default().push(game.file_path.clone());
+        }
+    }
+    
+    let mut moves = Vec::new();
+    let mut inserts = Vec::new();
+    for rom in &to_insert {
+        let emulator = match emulator_for(rom) {
+            Ok(emulator) => emulator,
+            Err(e) => {
+                errors.push(e);
                 continue;
             }
         };
+        // One bad row would abort the whole batch, so catch what the schema rejects up front
+        if rom.file_path.chars().count() > MAX_FILE_PATH_LEN {
+            errors.push(format!("Path too long to store: {}", rom.file_path));
+            continue;
+        }
         
-        let emulator = match get_emulator_by_id(emulator_id) {
-            Some(emu) => emu,
-            None => {
-                errors.push(format!("Invalid emulator ID for {}", rom.file_name));
-                continue;
-            }
-        };
+        let old_path = rom
+            .hashes
+            .as_ref()
+            .and_then(|h| vanished_by_hash.get_mut(&h.sha1))
+            .and_then(|paths| paths.pop());
+        match old_path.and_then(|path| vanished.remove(&path)) {
+            Some(game) => moves.push((game, rom, emulator)),
+            None => inserts.push((rom, emulator)),
+        }
+    }
+    let updates: Vec<&(i32, RomFile)> = to_update.iter().filter(|(_, rom)| rom.hashes.is_some()).collect();
+    // A failed walk finds nothing, which must not be mistaken for an empty library
+    let vanished_ids: Vec<i32> = if walk_ok { vanished.values().map(|g| g.id).collect() } else { Vec::new() };
+    
+    let mut changed = 0;
+    let mut moved = 0;
+    let mut newly_added = 0;
+    let mut newly_hashed: Vec<(i32, &RomFile)> = Vec::new();
+    let mut missing: Vec<String> = Vec::new();
+    let mut removed = 0;
+    // Everything is written in one transaction, a few statements per few thousand files
+    let saved: Result<(), sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
         
-        let emulator_type_str = match emulator.emulator_type {
-            crate::emulators::EmulatorType::RetroArchCore => "RetroArchCore",
-            crate::emulators::EmulatorType::EmulatorJS => "EmulatorJS",
-            crate::emulators::EmulatorType::NativeService => "NativeService",
-            crate::emulators::EmulatorType::BrowserWASM => "BrowserWASM",
-        };
+        if !restored.is_empty() {
+            sqlx::query("UPDATE games SET missing_since = NULL WHERE id = ANY($1)")
+                .bind(&restored)
+                .execute(&mut *tx)
+                .await?;
+        }
+        
+        for chunk in updates.chunks(WRITE_CHUNK_SIZE) {
+            sqlx::query(
+                "UPDATE games AS g
+                 SET crc32 = u.crc32, md5 = u.md5, sha1 = u.sha1, file_size = u.file_size,
+                     file_mtime = u.file_mtime, metadata = COALESCE(g.metadata, '{}'::jsonb) || u.metadata
+                 FROM UNNEST($1::int[], $2::text[], $3::text[], $4::text[], $5::bigint[], $6::bigint[], $7::jsonb[])
+                      AS u(id, crc32, md5, sha1, file_size, file_mtime, metadata)
+                 WHERE g.id = u.id"
+            )
+            .bind(chunk.iter().map(|(id, _)| *id).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(_, rom)| rom.hashes.as_ref().map(|h| h.crc32.clone())).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(_, rom)| rom.hashes.as_ref().map(|h| h.md5.clone())).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(_, rom)| rom.hashes.as_ref().map(|h| h.sha1.clone())).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(_, rom)| rom.size as i64).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(_, rom)| rom.mtime).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(_, rom)| rom.metadata()).collect::<Vec<_>>())
+            .execute(&mut *tx)
+            .await?;
+            changed += chunk.len();
+            newly_hashed.extend(chunk.iter().map(|(id, rom)| (*id, rom)));
+        }
+        
+        // Moves are rare enough to go one by one
+        for (game, rom, (emulator_id, emulator_type_str)) in &moves {
+            sqlx::query(
+                "UPDATE games
+                 SET file_path = $1, system = $2, emulator_id = $3, emulator_type = $4,
+                     file_size = $5, file_mtime = $6, missing_since = NULL,
+                     metadata = COALESCE(metadata, '{}'::jsonb) || $7
+                 WHERE id = $8"
+            )
+            .bind(&rom.file_path)
+            .bind(&rom.system)
+            .bind(emulator_id)
+            .bind(*emulator_type_str)
+            .bind(rom.size as i64)
+            .bind(rom.mtime)
+            .bind(rom.metadata())
+            .bind(game.id)
+            .execute(&mut *tx)
+            .await?;
+            moved += 1;
+        }
         
-        // Insert into database
-        let result = sqlx::query(
-            "INSERT INTO games (title, system, file_path, emulator_id, emulator_type, file_size) 
-             VALUES ($1, $2, $3, $4, $5, $6)"
-        )
-        .bind(&rom.file_name)
-        .bind(&rom.system)
-        .bind(&rom.file_path)
-        .bind(emulator_id)
-        .bind(emulator_type_str)
-        .bind(rom.size as i64)
-        .execute(pool.as_ref())
-        .await;
+        for chunk in inserts.chunks(WRITE_CHUNK_SIZE) {
+            // A file added by an upload while the scan ran is already there; leave it be
+            let added: Vec<(i32, String)> = sqlx::query_as(
+                "INSERT INTO games
+                     (title, system, file_path, emulator_id, emulator_type, file_size, file_mtime, crc32, md5, sha1, metadata)
+                 SELECT * FROM UNNEST(
+                     $1::text[], $2::text[], $3::text[], $4::text[], $5::text[], $6::bigint[],
+                     $7::bigint[], $8::text[], $9::text[], $10::text[], $11::jsonb[])
+                 ON CONFLICT (file_path) DO NOTHING
+                 RETURNING id, file_path"
+            )
+            .bind(chunk.iter().map(|(rom, _)| rom.file_name.chars().take(MAX_TITLE_LEN).collect::<String>()).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(rom, _)| rom.system.clone()).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(rom, _)| rom.file_path.clone()).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(_, (emulator_id, _))| emulator_id.clone()).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(_, (_, emulator_type_str))| emulator_type_str.to_string()).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(rom, _)| rom.size as i64).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(rom, _)| rom.mtime).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(rom, _)| rom.hashes.as_ref().map(|h| h.crc32.clone())).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(rom, _)| rom.hashes.as_ref().map(|h| h.md5.clone())).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(rom, _)| rom.hashes.as_ref().map(|h| h.sha1.clone())).collect::<Vec<_>>())
+            .bind(chunk.iter().map(|(rom, _)| rom.metadata()).collect::<Vec<_>>())
+            .fetch_all(&mut *tx)
+            .await?;
+            
+            newly_added += added.len();
+            already_exists += chunk.len() - added.len();
+            progress.added.fetch_add(added.len(), Ordering::Relaxed);
+            let by_path: HashMap<&str, &RomFile> = chunk.iter().map(|(rom, _)| (rom.file_path.as_str(), *rom)).collect();
+            newly_hashed.extend(
+                added
+                    .iter()
+                    .filter_map(|(id, path)| by_path.get(path.as_str()).map(|rom| (*id, *rom)))
+                    .filter(|(_, rom)| rom.hashes.is_some()),
+            );
+        }
         
-        match result {
-            Ok(_) => newly_added += 1,
-            Err(e) => errors.push(format!("Failed to add {}: {}", rom.file_name, e)),
+        if !vanished_ids.is_empty() {
+            if options.remove_missing {
+                let result = sqlx::query("DELETE FROM games WHERE id = ANY($1)")
+                    .bind(&vanished_ids)
+                    .execute(&mut *tx)
+                    .await?;
+                removed = result.rows_affected() as usize;
+            } else {
+                sqlx::query("UPDATE games SET missing_since = COALESCE(missing_since, CURRENT_TIMESTAMP) WHERE id = ANY($1)")
+                    .bind(&vanished_ids)
+                    .execute(&mut *tx)
+                    .await?;
+                missing = vanished.into_keys().collect();
+                missing.sort();
+            }
+        }
+        
+        tx.commit().await
+    }
+    .await;
+    
+    if let Err(e) = saved {
+        // Nothing was written
+        errors.push(format!("Failed to save scan results: {}", e));
+        (changed, moved, newly_added, removed) = (0, 0, 0, 0);
+        progress.added.store(0, Ordering::Relaxed);
+        restored.clear();
+        newly_hashed.clear();
+        missing.clear();
+    }
+    let hashed = newly_hashed.len() + moved;
+    
+    progress.errors.store(errors.len(), Ordering::Relaxed);
+    progress.set_phase(ScanPhase::Identifying);
+    
+    let mislabelled = newly_hashed
+        .iter()
+        .filter(|(_, rom)| rom.is_mislabelled(config.systems()))
+        .map(|(_, rom)| rom.file_path.clone())
+        .collect();
+    
+    // Identify against the DATs; without any DATs loaded nothing counts as unmatched
+    let mut identified = 0;
+    let mut unmatched = Vec::new();
+    let mut bad_dumps = Vec::new();
+    if dat::has_dats(pool).await {
+        let lookups: Vec<(i32, &RomHashes, i64)> = newly_hashed
+            .iter()
+            .filter_map(|(id, rom)| Some((*id, rom.hashes.as_ref()?, rom.size as i64)))
+            .collect();
+        match dat::identify_games(pool, &lookups).await {
+            Ok(matches) => {
+                let matches: HashMap<i32, dat::DatMatch> = matches.into_iter().collect();
+                identified = matches.len();
+                for (id, rom) in newly_hashed.iter().filter(|(_, rom)| rom.hashes.is_some()) {
+                    match matches.get(id) {
+                        Some(dat_match) if dat_match.is_bad_dump() => bad_dumps.push(rom.file_path.clone()),
+                        Some(_) => {}
+                        None => unmatched.push(rom.file_path.clone()),
+                    }
+                }
+            }
+            Err(e) => errors.push(format!("Failed to identify ROMs against the DATs: {}", e)),
         }
     }
     
-    Ok(Json(ScanResult {
+    progress.errors.store(errors.len(), Ordering::Relaxed);
+    ScanResult {
         total_found,
         newly_added,
         already_exists,
+        hashed,
+        identified,
+        unmatched,
+        bad_dumps,
+        mislabelled,
+        changed,
+        moved,
+        restored: restored.len(),
+        missing,
+        removed,
+        unknown_directories,
         errors,
-    }))
+    }
+}
+
+/// Where uploads are written while they arrive. It sits inside ROMS_PATH so moving a
+/// finished file into its system directory is a rename on the same filesystem, and the
+/// scanner skips it like any other dot-directory.
+pub const UPLOAD_STAGING_DIR: &str = ".uploads";
+
+pub fn staging_dir() -> PathBuf {
+    roms_path().join(UPLOAD_STAGING_DIR)
+}
+
+/// Longest `console` or `title` value accepted; the request body itself is unbounded
+const MAX_TEXT_FIELD_LEN: usize = 1024;
+
+/// Buffer between the multipart stream and the staging file
+const UPLOAD_WRITE_BUFFER: usize = 1024 * 1024;
+
+/// A file in the staging directory, deleted when dropped. Once it has been renamed
+/// into the library there is nothing left to delete.
+pub struct StagedFile(PathBuf);
+
+impl StagedFile {
+    pub fn new(path: PathBuf) -> Self {
+        StagedFile(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// A directory in the staging area, deleted with whatever is left in it when dropped
+pub struct StagedDir(PathBuf);
+
+impl StagedDir {
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for StagedDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn staging_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
+
+/// A new, uniquely named file in `staging_dir`, which must exist
+fn new_staged_file(staging_dir: &Path) -> StagedFile {
+    StagedFile(staging_dir.join(format!("{}.part", staging_id())))
+}
+
+/// An upload that has been received in full, with its contents hashed on the way in
+pub struct StagedUpload {
+    pub file: StagedFile,
+    pub size: u64,
+    pub hashes: RomHashes,
+}
+
+/// Stream a multipart field to a new file in `staging_dir`, hashing it as it arrives.
+/// Fails with 413 as soon as more than `max_size` bytes have come in.
+async fn stage_field(mut field: Field, staging_dir: &Path, max_size: u64) -> Result<StagedUpload, StatusCode> {
+    tokio::fs::create_dir_all(staging_dir)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let staged = new_staged_file(staging_dir);
+    let file = tokio::fs::File::create(staged.path())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut writer = BufWriter::with_capacity(UPLOAD_WRITE_BUFFER, file);
+    
+    let mut hasher = RomHasher::new();
+    let mut size = 0u64;
+    while let Some(chunk) = field.chunk().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        hasher.update(&chunk);
+        writer.write_all(&chunk)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    writer.flush().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    writer.into_inner()
+        .sync_all()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    
+    Ok(StagedUpload {
+        file: staged,
+        size,
+        hashes: hasher.finalize(),
+    })
+}
+
+/// Most files one uploaded archive may unpack to
+const MAX_ARCHIVE_FILES: usize = 10_000;
+
+/// Files unpacked from an uploaded archive, under their paths in it
+pub struct UnpackedArchive {
+    pub dir: StagedDir,
+    pub files: Vec<(String, StagedUpload)>,
+}
+
+/// Unpack an uploaded archive into a new directory in `staging_dir`, hashing each file
+/// on the way out. Files keep their paths in the archive, so cue sheets and playlists
+/// still find the files they name; documents, nested archives and paths that would
+/// leave the directory are left out. Fails with 413 once more than `max_size` bytes
+/// have been unpacked (so a zip bomb can't fill the disk) and with 400 if the archive
+/// can't be read.
+fn unpack_archive(
+    archive: &Path,
+    extension: &str,
+    staging_dir: &Path,
+    max_size: u64,
+) -> Result<UnpackedArchive, StatusCode> {
+    let dir = StagedDir(staging_dir.join(format!("{}.unpacked", staging_id())));
+    std::fs::create_dir_all(dir.path()).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut files = Vec::new();
+    let mut unpacked = 0u64;
+    // Set when unpacking stopped for a reason of our own rather than a broken archive
+    let mut failure = None;
+    let walked = for_each_archive_file(archive, extension, |name, reader| {
+        let extension = Path::new(name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        let contained = Path::new(name)
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)));
+        if !contained || NON_ROM_EXTENSIONS.contains(&extension.as_str()) || is_upload_archive_extension(&extension) {
+            return Ok(());
+        }
+        let mut fail = |status: StatusCode| {
+            failure = Some(status);
+            std::io::Error::other(status.to_string())
+        };
+        if files.len() >= MAX_ARCHIVE_FILES {
+            return Err(fail(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+        
+        let staged = StagedFile(dir.path().join(name));
+        if let Some(parent) = staged.path().parent() {
+            std::fs::create_dir_all(parent).map_err(|_| fail(StatusCode::INTERNAL_SERVER_ERROR))?;
+        }
+        // A name that comes up twice is a broken archive, not a file to overwrite
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(staged.path())
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => fail(StatusCode::BAD_REQUEST),
+                _ => fail(StatusCode::INTERNAL_SERVER_ERROR),
+            })?;
+        let mut writer = std::io::BufWriter::with_capacity(UPLOAD_WRITE_BUFFER, file);
+        let mut hasher = RomHasher::new();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut size = 0u64;
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            size += read as u64;
+            unpacked += read as u64;
+            if unpacked > max_size {
+                return Err(fail(StatusCode::PAYLOAD_TOO_LARGE));
+            }
+            hasher.update(&buffer[..read]);
+            writer.write_all(&buffer[..read]).map_err(|_| fail(StatusCode::INTERNAL_SERVER_ERROR))?;
+        }
+        writer.flush().map_err(|_| fail(StatusCode::INTERNAL_SERVER_ERROR))?;
+        
+        files.push((name.to_string(), StagedUpload {
+            file: staged,
+            size,
+            hashes: hasher.finalize(),
+        }));
+        Ok(())
+    });
+    match (walked, failure) {
+        (Ok(()), _) => Ok(UnpackedArchive { dir, files }),
+        (Err(_), Some(status)) => Err(status),
+        (Err(_), None) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+/// Track and disc files that go along with an uploaded cue/gdi sheet or m3u playlist,
+/// by their path relative to it
+#[derive(Default)]
+pub struct UploadMembers {
+    pub files: Vec<(String, StagedUpload)>,
+    /// Everything the sheet or playlist names, stored as `metadata.tracks` and
+    /// `metadata.discs` the way a scan does
+    pub tracks: Vec<String>,
+    pub discs: Vec<String>,
+}
+
+/// Hash a file written into the staging area, such as a playlist made for a disc set
+fn stage_written_file(path: PathBuf) -> std::io::Result<StagedUpload> {
+    let file = StagedFile(path);
+    let size = std::fs::metadata(file.path())?.len();
+    let hashes = crate::rom_hash::hash_reader(std::fs::File::open(file.path())?)?;
+    Ok(StagedUpload { file, size, hashes })
+}
+
+/// Fold the files unpacked into `dir` into games the way a library scan does: a cue/gdi
+/// sheet takes its tracks along, a playlist its discs, and "(Disc N)" sets get a playlist
+/// written for them. Returns each game's main file, by its path in the archive, with
+/// the files that go along with it.
+fn group_unpacked(
+    dir: &Path,
+    files: Vec<(String, StagedUpload)>,
+    console_system: Option<&'static str>,
+    config: &ScanConfig,
+    emulators: &[EmulatorInfo],
+) -> Vec<(String, StagedUpload, UploadMembers)> {
+    let roms = files
+        .iter()
+        .map(|(name, staged)| {
+            let extension = Path::new(name)
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("")
+                .to_lowercase();
+            let system = console_system
+                .or_else(|| get_directory_for_extension(&extension).and_then(|dir| config.system_for_directory(dir)))
+                .unwrap_or_default();
+            RomFile {
+                file_path: name.clone(),
+                file_name: name.clone(),
+                system: system.to_string(),
+                extension,
+                size: staged.size,
+                mtime: 0,
+                suggested_emulator: None,
+                tags: RomTags::default(),
+                hashes: None,
+                header: None,
+                disc_platform: None,
+                tracks: Vec::new(),
+                discs: Vec::new(),
+                archive: None,
+            }
+        })
+        .collect();
+    let mut staged: HashMap<String, StagedUpload> = files.into_iter().collect();
+    
+    group_multi_file_games(dir, roms, emulators)
+        .into_iter()
+        .filter_map(|game| {
+            let main = match staged.remove(&game.file_path) {
+                Some(main) => main,
+                None => stage_written_file(dir.join(&game.file_path)).ok()?,
+            };
+            let prefix = match game.file_path.rsplit_once('/') {
+                Some((parent, _)) => format!("{}/", parent),
+                None => String::new(),
+            };
+            let relative = |path: &String| path.strip_prefix(&prefix).map(str::to_string);
+            let files = game
+                .discs
+                .iter()
+                .chain(&game.tracks)
+                .filter_map(|path| Some((relative(path)?, staged.remove(path)?)))
+                .collect();
+            let members = UploadMembers {
+                files,
+                tracks: game.tracks.iter().filter_map(relative).collect(),
+                discs: game.discs.iter().filter_map(relative).collect(),
+            };
+            Some((game.file_path, main, members))
+        })
+        .collect()
 }
 
-/// Upload a ROM file
+/// A short form value, such as the console or title
+async fn read_text_field(mut field: Field) -> Result<String, StatusCode> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+        if bytes.len() + chunk.len() > MAX_TEXT_FIELD_LEN {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    String::from_utf8(bytes).map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+/// Longest file name accepted, in bytes (the usual filesystem limit)
+const MAX_FILE_NAME_LEN: usize = 255;
+
+/// Reduce a client-supplied file name to a single path component that is safe to join
+/// onto a system directory. Directories are dropped (some browsers send full paths),
+/// as are control characters and the leading and trailing dots and spaces that would
+/// hide the file or name a directory. None if nothing usable is left.
+pub fn sanitize_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if name.is_empty() || name.len() > MAX_FILE_NAME_LEN {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+/// What to do with an upload whose contents are already in the library
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateChoice {
+    /// Point the existing game at the upload, keeping its id, saves and history
+    Replace,
+    /// Add the upload as a game of its own, renaming it if its name is taken
+    KeepBoth,
+}
+
+impl DuplicateChoice {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "replace" => Some(DuplicateChoice::Replace),
+            "keep_both" | "keep-both" => Some(DuplicateChoice::KeepBoth),
+            _ => None,
+        }
+    }
+}
+
+/// What the client said about an upload, besides the file itself
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct UploadOptions {
+    /// System directory to upload into; detected from the file when absent
+    pub console: Option<String>,
+    pub title: Option<String>,
+    /// Overwrite a file of the same name, updating its game
+    #[serde(default)]
+    pub replace: bool,
+    /// Without a choice, an upload that duplicates an existing game is refused
+    #[serde(default)]
+    pub on_duplicate: Option<DuplicateChoice>,
+}
+
+/// Why an upload was not added to the library
+pub enum UploadRejection {
+    Status(StatusCode),
+    /// The same ROM is already in the library and no `on_duplicate` choice was made
+    Duplicate(DuplicateGame),
+}
+
+impl From<StatusCode> for UploadRejection {
+    fn from(status: StatusCode) -> Self {
+        UploadRejection::Status(status)
+    }
+}
+
+impl UploadRejection {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            UploadRejection::Status(status) => *status,
+            UploadRejection::Duplicate(_) => StatusCode::CONFLICT,
+        }
+    }
+
+    /// The rejection as an upload report
+    pub fn result(&self) -> UploadResult {
+        match self {
+            UploadRejection::Status(status) => UploadResult {
+                success: false,
+                message: format!("Import failed: {}", status),
+                game_id: None,
+                file_path: None,
+                duplicate_of: None,
+                file_name: None,
+                status: Some(status.as_u16()),
+            },
+            UploadRejection::Duplicate(game) => UploadResult {
+                success: false,
+                message: format!("Duplicate of game {} ({})", game.game_id, game.title),
+                game_id: None,
+                file_path: None,
+                duplicate_of: Some(game.clone()),
+                file_name: None,
+                status: Some(self.status().as_u16()),
+            },
+        }
+    }
+}
+
+/// `name` if nothing in `dir` is called that yet, otherwise the first free `stem (n).ext`
+fn free_file_name(dir: &Path, name: &str) -> String {
+    if !dir.join(name).exists() {
+        return name.to_string();
+    }
+    let path = Path::new(name);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+    let extension = path.extension().and_then(|e| e.to_str());
+    (2..)
+        .map(|n| match extension {
+            Some(extension) => format!("{} ({}).{}", stem, n, extension),
+            None => format!("{} ({})", stem, n),
+        })
+        .find(|candidate| !dir.join(candidate).exists())
+        .unwrap_or_default()
+}
+
+/// The game already holding a ROM with this SHA-1, preferring one whose file is present
+async fn find_duplicate(pool: &PgPool, sha1: &str) -> Result<Option<DuplicateGame>, sqlx::Error> {
+    sqlx::query_as::<_, DuplicateGame>(
+        "SELECT id AS game_id, title, file_path, missing_since IS NOT NULL AS missing
+         FROM games
+         WHERE sha1 = $1
+         ORDER BY missing_since IS NOT NULL, id
+         LIMIT 1"
+    )
+    .bind(sha1)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Check an upload's name and console before any of it is stored, returning the
+/// file name to use. The console must be a system directory the scanner knows,
+/// built in or aliased in scan.toml.
+pub fn check_upload_target(file_name: &str, options: &UploadOptions, config: &ScanConfig) -> Result<String, StatusCode> {
+    let file_name = sanitize_file_name(file_name).ok_or(StatusCode::BAD_REQUEST)?;
+    if Path::new(&file_name).extension().is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let Some(console) = &options.console {
+        if config.system_for_directory(console).is_none() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    Ok(file_name)
+}
+
+/// Move the track and disc files of a sheet or playlist into `dir`, returning where they
+/// went. If one can't be placed, the ones already moved are taken out again (unless they
+/// replaced existing files).
+async fn place_members(dir: &Path, files: &[(String, StagedUpload)], replace: bool) -> Result<Vec<PathBuf>, StatusCode> {
+    let mut placed = Vec::with_capacity(files.len());
+    for (path, member) in files {
+        let target = dir.join(path);
+        let moved = match target.parent() {
+            Some(parent) => tokio::fs::create_dir_all(parent).await,
+            None => Ok(()),
+        };
+        let moved = match moved {
+            Ok(()) => move_into_place(member.file.path(), &target, replace).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = moved {
+            if !replace {
+                for path in placed {
+                    let _ = tokio::fs::remove_file(path).await;
+                }
+            }
+            return Err(match e.kind() {
+                std::io::ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            });
+        }
+        placed.push(target);
+    }
+    Ok(placed)
+}
+
+/// Put `from` at `target`, refusing to clobber an existing file unless `replace` is set
+async fn place_file(from: &Path, target: &Path, replace: bool) -> std::io::Result<()> {
+    if !replace {
+        // Linking fails if the target exists, so there is no window for another upload to slip in
+        match tokio::fs::hard_link(from, target).await {
+            Ok(()) => {
+                let _ = tokio::fs::remove_file(from).await;
+                return Ok(());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Err(e),
+            // No hard links on this filesystem (or another device); check, then rename
+            Err(_) if tokio::fs::try_exists(target).await.unwrap_or(false) => {
+                return Err(std::io::ErrorKind::AlreadyExists.into());
+            }
+            Err(_) => {}
+        }
+    }
+    tokio::fs::rename(from, target).await
+}
+
+/// Move a staged file to `target`. A system directory mounted from another disk can't
+/// take a rename, so then the file is copied next to the target first and moved from
+/// there; either way the target only ever appears complete.
+async fn move_into_place(staged: &Path, target: &Path, replace: bool) -> std::io::Result<()> {
+    match place_file(staged, target, replace).await {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Err(e),
+        Err(_) => {}
+    }
+    let file_name = target.file_name().unwrap_or_default().to_string_lossy();
+    let partial = target.with_file_name(format!(".{}.part", file_name));
+    let moved = match tokio::fs::copy(staged, &partial).await {
+        Ok(_) => place_file(&partial, target, replace).await,
+        Err(e) => Err(e),
+    };
+    if moved.is_err() {
+        let _ = tokio::fs::remove_file(&partial).await;
+    }
+    moved
+}
+
+/// Upload ROM files. Each file is streamed to the staging directory and only moved
+/// into the library once its system, size and emulator check out. Archives are
+/// unpacked first and their contents imported game by game, a cue sheet or playlist
+/// together with its tracks and discs. Every game gets its own result, so one bad
+/// file doesn't fail the rest.
 pub async fn upload_rom(
     Extension(pool): Extension<Arc<PgPool>>,
+    auth: AuthUser,
     mut multipart: Multipart,
-) -> Result<Json<UploadResult>, StatusCode> {
-    let roms_path = std::env::var("ROMS_PATH").unwrap_or_else(|_| "/roms".to_string());
-    let base_path = PathBuf::from(&roms_path);
+) -> Result<Json<Vec<UploadResult>>, StatusCode> {
+    auth.require_uploader()?;
+    
+    let staging_dir = staging_dir();
+    let limits = UploadLimits::from_env();
+    let config = Arc::new(ScanConfig::load(&scan_config_path(&roms_path())).0);
     
-    let mut file_name: Option<String> = None;
-    let mut file_data: Option<Vec<u8>> = None;
-    let mut console: Option<String> = None;
-    let mut title: Option<String> = None;
+    // By the name they were sent with, or why they were turned away
+    let mut uploads: Vec<(String, Result<StagedUpload, StatusCode>)> = Vec::new();
+    let mut options = UploadOptions::default();
     
     // Parse multipart form
-    while let Some(field) = multipart.next_field().await.ok().flatten() {
+    while let Some(field) = multipart.next_field().await.map_err(|_| StatusCode::BAD_REQUEST)? {
         let name = field.name().unwrap_or("").to_string();
         
         match name.as_str() {
             "file" => {
-                file_name = field.file_name().map(|s| s.to_string());
-                file_data = Some(field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?.to_vec());
+                // Reject a bad name or console before receiving any data; fields sent
+                // after the file are checked again before it is imported
+                let name = field.file_name().unwrap_or_default().to_string();
+                let staged = match check_upload_target(&name, &options, &config) {
+                    Ok(_) => {
+                        // The console may only be sent after the file, in which case any system's limit could apply
+                        let max_size = match &options.console {
+                            Some(console) => limits.for_system(console),
+                            None => limits.largest(),
+                        };
+                        stage_field(field, &staging_dir, max_size).await
+                    }
+                    Err(status) => Err(status),
+                };
+                uploads.push((name, staged));
             }
-            "console" => {
-                console = Some(
-                    String::from_utf8(field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?.to_vec())
-                        .map_err(|_| StatusCode::BAD_REQUEST)?
-                );
-            }
-            "title" => {
-                title = Some(
-                    String::from_utf8(field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?.to_vec())
-                        .map_err(|_| StatusCode::BAD_REQUEST)?
-                );
+            "console" => options.console = Some(read_text_field(field).await?),
+            "title" => options.title = Some(read_text_field(field).await?),
+            "replace" => options.replace = matches!(read_text_field(field).await?.trim(), "true" | "1" | "on"),
+            "on_duplicate" => {
+                options.on_duplicate = Some(DuplicateChoice::parse(&read_text_field(field).await?).ok_or(StatusCode::BAD_REQUEST)?);
             }
             _ => {}
         }
     }
     
-    let file_name = file_name.ok_or(StatusCode::BAD_REQUEST)?;
-    let file_data = file_data.ok_or(StatusCode::BAD_REQUEST)?;
+    if uploads.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    
+    // Archives are unpacked unless the chosen system plays them as they are
+    let emulators = crate::emulators::get_all_emulators();
+    let console_system = options.console.as_deref().and_then(|console| config.system_for_directory(console));
+    let mut files: Vec<(String, String, Result<StagedUpload, StatusCode>, UploadMembers)> = Vec::new();
+    // Unpacked files stay in here until they have been moved into the library
+    let mut unpacked_dirs = Vec::new();
+    for (name, staged) in uploads {
+        let extension = Path::new(&name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        let keep_whole = console_system
+            .is_some_and(|system| find_compatible_emulator(&emulators, system, &extension).is_some());
+        let staged = match staged {
+            Ok(staged) if is_upload_archive_extension(&extension) && !keep_whole => staged,
+            staged => {
+                files.push((name.clone(), name, staged, UploadMembers::default()));
+                continue;
+            }
+        };
+        
+        let (archive, directory, config) = (staged.file.path().to_path_buf(), staging_dir.clone(), config.clone());
+        let max_size = limits.largest();
+        let unpacked = tokio::task::spawn_blocking(move || {
+            let unpacked = unpack_archive(&archive, &extension, &directory, max_size)?;
+            let emulators = crate::emulators::get_all_emulators();
+            let games = group_unpacked(unpacked.dir.path(), unpacked.files, console_system, &config, &emulators);
+            Ok::<_, StatusCode>((unpacked.dir, games))
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        match unpacked {
+            Ok((dir, games)) if !games.is_empty() => {
+                unpacked_dirs.push(dir);
+                for (entry, staged, members) in games {
+                    files.push((format!("{}#{}", name, entry), entry, Ok(staged), members));
+                }
+            }
+            // Nothing in it but documents
+            Ok(_) => files.push((name.clone(), name, Err(StatusCode::BAD_REQUEST), UploadMembers::default())),
+            Err(status) => files.push((name.clone(), name, Err(status), UploadMembers::default())),
+        }
+    }
+    
+    // A title names one game, so it is ignored when several files were sent
+    if files.len() > 1 {
+        options.title = None;
+    }
+    
+    let mut results = Vec::with_capacity(files.len());
+    for (uploaded_as, file_name, staged, members) in files {
+        let imported = match staged {
+            Ok(staged) => {
+                import_upload(pool.as_ref(), auth.user_id, &limits, staged, members, &file_name, options.clone()).await
+            }
+            Err(status) => Err(status.into()),
+        };
+        let mut result = imported.unwrap_or_else(|rejection| rejection.result());
+        result.file_name = Some(uploaded_as);
+        results.push(result);
+    }
+    Ok(Json(results))
+}
+
+/// Add a fully received upload to the library: work out its system, check it against
+/// that system's size limit, move it into the system directory and insert the game.
+/// The staged file is deleted if any of that fails. An existing file of the same name
+/// is a 409 unless `replace` is set, in which case the file and its game are updated.
+/// So is a file whose contents are already in the library, unless `on_duplicate` says
+/// whether to replace that game or keep both. The `members` of a sheet or playlist are
+/// moved in next to it and count towards the size limit.
+pub async fn import_upload(
+    pool: &PgPool,
+    user_id: i32,
+    limits: &UploadLimits,
+    staged: StagedUpload,
+    members: UploadMembers,
+    file_name: &str,
+    options: UploadOptions,
+) -> Result<UploadResult, UploadRejection> {
+    let base_path = roms_path();
+    let (config, _) = ScanConfig::load(&scan_config_path(&base_path));
+    let file_name = check_upload_target(file_name, &options, &config)?;
+    let UploadOptions { console, title, replace, on_duplicate } = options;
     
     // Determine console directory
     let extension = Path::new(&file_name)
@@ -167,45 +1199,59 @@ pub async fn upload_rom(
         .ok_or(StatusCode::BAD_REQUEST)?
         .to_lowercase();
     
+    // Cartridge-sized files are read back whole for the header, as the scanner does. It
+    // also places a file whose extension doesn't name a system.
+    let header = if staged.size <= MAX_HEADER_ROM_SIZE {
+        let path = staged.file.path().to_path_buf();
+        tokio::task::spawn_blocking(move || std::fs::read(path).ok().and_then(|data| parse_header(&data)))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    } else {
+        None
+    };
+    
     let target_dir = if let Some(console_dir) = console {
         console_dir
     } else {
-        get_directory_for_extension(&extension)
+        // .iso/.bin/.chd are shared by several systems, so look inside before trusting the extension
+        let sniffed = if is_ambiguous_disc_extension(&extension) {
+            let (path, extension) = (staged.file.path().to_path_buf(), extension.clone());
+            tokio::task::spawn_blocking(move || sniff_disc_file(&path, &extension))
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        } else {
+            None
+        };
+        sniffed
+            .or_else(|| get_directory_for_extension(&extension))
+            .or_else(|| header.as_ref().map(|header| header.system))
             .ok_or(StatusCode::BAD_REQUEST)?
             .to_string()
     };
     
-    // Create target directory path
-    let target_path = base_path.join(&target_dir);
-    tokio::fs::create_dir_all(&target_path)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    // Save file
-    let file_path = target_path.join(&file_name);
-    let mut file = tokio::fs::File::create(&file_path)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    file.write_all(&file_data)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    // Scan and add to database
-    let relative_path = format!("{}/{}", target_dir, file_name);
-    
     // Get system name and emulator
-    let system_map = crate::rom_scanner::get_system_mapping();
-    let system_name = system_map.get(target_dir.as_str())
+    let system_name = config.system_for_directory(&target_dir)
         .ok_or(StatusCode::BAD_REQUEST)?;
     
-    // Find compatible emulator
+    let total_size = staged.size + members.files.iter().map(|(_, member)| member.size).sum::<u64>();
+    if total_size > limits.for_system(&target_dir) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE.into());
+    }
+    
+    // Find compatible emulator, going by the header for a file placed by it
     let emulators = crate::emulators::get_all_emulators();
-    let emulator = emulators
-        .iter()
-        .find(|e| {
-            e.system.to_lowercase().contains(&system_name.to_lowercase())
-                && e.supported_formats.iter().any(|fmt| fmt == &extension)
+    let header_extension = header
+        .as_ref()
+        .filter(|header| config.system_for_directory(header.system) == Some(system_name))
+        .map(|header| extension_for_header_system(header.system));
+    let emulator = [Some(extension.as_str()), header_extension]
+        .into_iter()
+        .flatten()
+        .find_map(|extension| {
+            emulators.iter().find(|e| {
+                e.system.to_lowercase().contains(&system_name.to_lowercase())
+                    && e.supported_formats.iter().any(|fmt| fmt == extension)
+            })
         })
         .ok_or(StatusCode::BAD_REQUEST)?;
     
@@ -217,7 +1263,7 @@ pub async fn upload_rom(
     };
     
     // Use provided title or clean filename
-    let game_title = title.unwrap_or_else(|| {
+    let game_title = title.clone().unwrap_or_else(|| {
         Path::new(&file_name)
             .file_stem()
             .and_then(|s| s.to_str())
@@ -225,28 +1271,244 @@ pub async fn upload_rom(
             .to_string()
     });
     
-    // Insert into database
-    let game = sqlx::query_as::<_, Game>(
-        "INSERT INTO games (title, system, file_path, emulator_id, emulator_type, file_size) 
-         VALUES ($1, $2, $3, $4, $5, $6)
-         RETURNING *"
-    )
-    .bind(&game_title)
-    .bind(system_name)
-    .bind(&relative_path)
-    .bind(&emulator.id)
-    .bind(emulator_type_str)
-    .bind(file_data.len() as i64)
-    .fetch_one(pool.as_ref())
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tags = parse_tags(
+        Path::new(&file_name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default(),
+    );
+    let duplicate = find_duplicate(pool, &staged.hashes.sha1)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let replacing = match (duplicate, on_duplicate) {
+        (Some(duplicate), None) => return Err(UploadRejection::Duplicate(duplicate)),
+        (Some(duplicate), Some(DuplicateChoice::Replace)) => Some(duplicate),
+        _ => None,
+    };
     
-    Ok(Json(UploadResult {
+    // Create target directory path
+    let target_path = base_path.join(&target_dir);
+    tokio::fs::create_dir_all(&target_path)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let file_name = match on_duplicate {
+        Some(DuplicateChoice::KeepBoth) => free_file_name(&target_path, &file_name),
+        _ => file_name,
+    };
+    let file_path = target_path.join(&file_name);
+    let relative_path = format!("{}/{}", target_dir, file_name);
+    if relative_path.len() > MAX_FILE_PATH_LEN {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    // Uploading over the duplicate's own file is a plain overwrite
+    let replace = replace || replacing.as_ref().is_some_and(|game| game.file_path == relative_path);
+    if !replace && tokio::fs::try_exists(&file_path).await.unwrap_or(false) {
+        return Err(StatusCode::CONFLICT.into());
+    }
+    
+    // The row goes in first and is only committed once the file is in place, so a
+    // failed insert leaves the library untouched and a failed move leaves no game behind.
+    // Replacing a file keeps its game (and so its saves and history) under the same id.
+    let upsert = if replace {
+        " ON CONFLICT (file_path) DO UPDATE SET
+             title = EXCLUDED.title, system = EXCLUDED.system, emulator_id = EXCLUDED.emulator_id,
+             emulator_type = EXCLUDED.emulator_type, file_size = EXCLUDED.file_size,
+             crc32 = EXCLUDED.crc32, md5 = EXCLUDED.md5, sha1 = EXCLUDED.sha1,
+             metadata = EXCLUDED.metadata, missing_since = NULL"
+    } else {
+        ""
+    };
+    let mut metadata = serde_json::json!({ "tags": tags, "header": header });
+    let in_library = |paths: &[String]| -> Vec<String> { paths.iter().map(|path| format!("{}/{}", target_dir, path)).collect() };
+    if !members.tracks.is_empty() {
+        metadata["tracks"] = serde_json::json!(in_library(&members.tracks));
+    }
+    if !members.discs.is_empty() {
+        metadata["discs"] = serde_json::json!(in_library(&members.discs));
+    }
+    let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let saved = match &replacing {
+        // The duplicate becomes this file; its title stays unless a new one was given
+        Some(game) => {
+            sqlx::query_as::<_, (i32,)>(
+                "UPDATE games
+                 SET title = COALESCE($1, title), system = $2, file_path = $3, emulator_id = $4,
+                     emulator_type = $5, file_size = $6, crc32 = $7, md5 = $8, sha1 = $9,
+                     metadata = COALESCE(metadata, '{}'::jsonb) || $10, missing_since = NULL
+                 WHERE id = $11
+                 RETURNING id"
+            )
+            .bind(&title)
+            .bind(system_name)
+            .bind(&relative_path)
+            .bind(&emulator.id)
+            .bind(emulator_type_str)
+            .bind(staged.size as i64)
+            .bind(&staged.hashes.crc32)
+            .bind(&staged.hashes.md5)
+            .bind(&staged.hashes.sha1)
+            .bind(&metadata)
+            .bind(game.game_id)
+            .fetch_one(&mut *tx)
+            .await
+        }
+        None => {
+            sqlx::query_as::<_, (i32,)>(&format!(
+                "INSERT INTO games (title, system, file_path, emulator_id, emulator_type, file_size, crc32, md5, sha1, user_id, metadata) 
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11){}
+                 RETURNING id",
+                upsert
+            ))
+            .bind(&game_title)
+            .bind(system_name)
+            .bind(&relative_path)
+            .bind(&emulator.id)
+            .bind(emulator_type_str)
+            .bind(staged.size as i64)
+            .bind(&staged.hashes.crc32)
+            .bind(&staged.hashes.md5)
+            .bind(&staged.hashes.sha1)
+            .bind(user_id)
+            .bind(&metadata)
+            .fetch_one(&mut *tx)
+            .await
+        }
+    };
+    let (game_id,) = saved.map_err(|e| match e.as_database_error().and_then(|e| e.code()) {
+        // A game already points at this path, though its file is gone
+        Some(code) if code == "23505" => StatusCode::CONFLICT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+    
+    // Tracks and discs go in first, so the sheet never names a file that isn't there yet
+    let placed = place_members(&target_path, &members.files, replace).await?;
+    let remove_placed = |placed: Vec<PathBuf>| async move {
+        // Replaced files can't be put back; the next scan picks up the new contents
+        if !replace {
+            for path in placed {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+        }
+    };
+    if let Err(e) = move_into_place(staged.file.path(), &file_path, replace).await {
+        remove_placed(placed).await;
+        return Err(match e.kind() {
+            std::io::ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+        .into());
+    }
+    
+    // With the mtime stored the next scan sees the file as known and leaves it alone
+    let (_, mtime) = file_size_and_mtime(&file_path);
+    let committed = async {
+        sqlx::query("UPDATE games SET file_mtime = $1 WHERE id = $2")
+            .bind(mtime)
+            .bind(game_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+    .await;
+    if committed.is_err() {
+        remove_placed(placed.into_iter().chain([file_path]).collect()).await;
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    
+    // Left in place, the replaced file would come back as a game of its own on the next
+    // scan. ROMs inside archives stay, since the archive may hold others.
+    if let Some(game) = replacing.as_ref().filter(|game| game.file_path != relative_path) {
+        if !game.missing && !game.file_path.contains('#') {
+            if let Err(e) = tokio::fs::remove_file(base_path.join(&game.file_path)).await {
+                eprintln!("Failed to remove replaced file {}: {}", game.file_path, e);
+            }
+        }
+    }
+    
+    if dat::has_dats(pool).await {
+        if let Err(e) = dat::identify_games(pool, &[(game_id, &staged.hashes, staged.size as i64)]).await {
+            eprintln!("Failed to identify {}: {}", file_name, e);
+        }
+    }
+    
+    let message = match &replacing {
+        Some(game) => format!("Successfully uploaded {}, replacing {}", file_name, game.file_path),
+        None => format!("Successfully uploaded {}", file_name),
+    };
+    Ok(UploadResult {
         success: true,
-        message: format!("Successfully uploaded {}", file_name),
-        game_id: Some(game.id as i32),
+        message,
+        game_id: Some(game_id),
         file_path: Some(relative_path),
-    }))
+        duplicate_of: None,
+        file_name: None,
+        status: None,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ArchiveEntryQuery {
+    /// The archive, relative to the ROMs directory
+    pub rom: String,
+    pub entry: String,
+}
+
+/// Serve a ROM stored inside a .zip/.7z unpacked, for emulators that load plain files.
+/// The launch URL of an archived game names the archive and entry separately; only
+/// pairs that belong to a game in the library are served.
+pub async fn get_archive_entry(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Query(query): Query<ArchiveEntryQuery>,
+) -> Result<Response, StatusCode> {
+    let known: Option<i32> = sqlx::query_scalar("SELECT id FROM games WHERE file_path = $1")
+        .bind(format!("{}#{}", query.rom, query.entry))
+        .fetch_optional(pool.as_ref())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if known.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    
+    // Unpacked to the staging directory first: a 7z can't be decoded as it is sent
+    let staging_dir = staging_dir();
+    tokio::fs::create_dir_all(&staging_dir)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let staged = new_staged_file(&staging_dir);
+    let archive = roms_path().join(&query.rom);
+    let extension = Path::new(&query.rom)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let (path, entry) = (staged.path().to_path_buf(), query.entry.clone());
+    tokio::task::spawn_blocking(move || {
+        with_archive_entry(&archive, &extension, &entry, |reader| {
+            let mut file = std::fs::File::create(&path)?;
+            std::io::copy(reader, &mut file)
+        })
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+    
+    // The open handle keeps the contents readable once the staged file is removed
+    let file = tokio::fs::File::open(staged.path())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    drop(staged);
+    let file_name = query.entry.rsplit('/').next().unwrap_or_default().replace('"', "_");
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+        ],
+        Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+    )
+        .into_response())
 }
 
 /// Get available console directories
@@ -285,3 +1547,212 @@ pub async fn get_consoles() -> Json<Vec<ConsoleInfo>> {
     Json(consoles)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("Super Mario Bros. (World).nes").as_deref(), Some("Super Mario Bros. (World).nes"));
+        // Only the last component survives, whichever separator is used
+        assert_eq!(sanitize_file_name("../../etc/passwd").as_deref(), Some("passwd"));
+        assert_eq!(sanitize_file_name("/etc/cron.d/evil.nes").as_deref(), Some("evil.nes"));
+        assert_eq!(sanitize_file_name("..\\..\\windows\\evil.nes").as_deref(), Some("evil.nes"));
+        assert_eq!(sanitize_file_name("C:\\Users\\me\\Zelda.sfc").as_deref(), Some("Zelda.sfc"));
+        // Hidden files and control characters
+        assert_eq!(sanitize_file_name("..hidden.nes").as_deref(), Some("hidden.nes"));
+        assert_eq!(sanitize_file_name("evil\0.nes\n").as_deref(), Some("evil.nes"));
+        assert_eq!(sanitize_file_name("game.nes. . ").as_deref(), Some("game.nes"));
+        // Nothing left to use
+        assert_eq!(sanitize_file_name(".."), None);
+        assert_eq!(sanitize_file_name("../"), None);
+        assert_eq!(sanitize_file_name("roms/.."), None);
+        assert_eq!(sanitize_file_name(""), None);
+        assert_eq!(sanitize_file_name(&"a".repeat(300)), None);
+    }
+
+    #[test]
+    fn test_check_upload_target() {
+        let with_console = |console: &str| UploadOptions {
+            console: Some(console.to_string()),
+            ..UploadOptions::default()
+        };
+        let config = ScanConfig::default();
+        assert_eq!(check_upload_target("Zelda.sfc", &with_console("snes"), &config), Ok("Zelda.sfc".to_string()));
+        assert_eq!(check_upload_target("../Zelda.sfc", &UploadOptions::default(), &config), Ok("Zelda.sfc".to_string()));
+        for console in ["../../etc", "snes/../..", "/etc", "snes/", "", ".", "SNES", "bios", ".uploads", "genesis"] {
+            assert_eq!(check_upload_target("Zelda.sfc", &with_console(console), &config), Err(StatusCode::BAD_REQUEST), "{}", console);
+        }
+        assert_eq!(check_upload_target("..", &UploadOptions::default(), &config), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(check_upload_target("no_extension", &UploadOptions::default(), &config), Err(StatusCode::BAD_REQUEST));
+        
+        // Directories aliased in scan.toml are accepted too
+        let (config, _) = ScanConfig::parse("[aliases]\ngenesis = \"Sega Genesis\"\npsx = \"ps1\"\n");
+        for console in ["genesis", "psx", "snes"] {
+            assert!(check_upload_target("Sonic.md", &with_console(console), &config).is_ok(), "{}", console);
+        }
+    }
+
+    #[test]
+    fn test_free_file_name() {
+        let dir = std::env::temp_dir().join(format!("upload-name-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(free_file_name(&dir, "Tetris.gb"), "Tetris.gb");
+        std::fs::write(dir.join("Tetris.gb"), b"").unwrap();
+        std::fs::write(dir.join("Tetris (2).gb"), b"").unwrap();
+        assert_eq!(free_file_name(&dir, "Tetris.gb"), "Tetris (3).gb");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_still_on_disk() {
+        let dir = std::env::temp_dir().join(format!("still-on-disk-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("snes/_unused")).unwrap();
+        std::fs::write(dir.join("snes/_unused/Mario.sfc"), b"").unwrap();
+        std::fs::write(dir.join("gb.zip"), b"").unwrap();
+        let walked: HashSet<String> = ["gb.zip".to_string()].into();
+
+        // Left out by an ignore pattern or a lower max_depth, but still there
+        assert!(still_on_disk(&dir, "snes/_unused/Mario.sfc", &HashSet::new()));
+        assert!(still_on_disk(&dir, "gb.zip#Tetris.gb", &HashSet::new()));
+        assert!(!still_on_disk(&dir, "snes/Zelda.sfc", &HashSet::new()));
+        // An entry the walk didn't find in an archive it read is gone
+        assert!(!still_on_disk(&dir, "gb.zip#Tetris.gb", &walked));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_choice_parse() {
+        assert_eq!(DuplicateChoice::parse("replace"), Some(DuplicateChoice::Replace));
+        assert_eq!(DuplicateChoice::parse(" Keep_Both "), Some(DuplicateChoice::KeepBoth));
+        assert_eq!(DuplicateChoice::parse("keep-both"), Some(DuplicateChoice::KeepBoth));
+        assert_eq!(DuplicateChoice::parse("skip"), None);
+    }
+
+    #[tokio::test]
+    async fn test_move_into_place_refuses_to_overwrite() {
+        let dir = std::env::temp_dir().join(format!("upload-place-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("game.nes");
+        std::fs::write(&target, b"original").unwrap();
+        std::fs::write(dir.join("staged"), b"upload").unwrap();
+
+        let refused = move_into_place(&dir.join("staged"), &target, false).await;
+        assert_eq!(refused.unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(&target).unwrap(), b"original");
+
+        move_into_place(&dir.join("staged"), &target, true).await.unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"upload");
+        assert!(!dir.join("staged").exists());
+
+        std::fs::write(dir.join("staged"), b"new").unwrap();
+        move_into_place(&dir.join("staged"), &dir.join("new.nes"), false).await.unwrap();
+        assert!(!dir.join("staged").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_archive() {
+        let dir = std::env::temp_dir().join(format!("unpack-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("set.zip");
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            for (name, data) in [
+                ("readme.txt", &b"hello"[..]),
+                ("inner.7z", &b"7z"[..]),
+                ("Disc 1/Game (Disc 1).cue", &b"FILE"[..]),
+                ("Tetris (World).gb", &[1, 2, 3, 4][..]),
+            ] {
+                zip.start_file(name, options).unwrap();
+                zip.write_all(data).unwrap();
+            }
+            zip.finish().unwrap();
+        }
+
+        let unpacked = unpack_archive(&archive, "zip", &dir, 1024).unwrap();
+        let names: Vec<&str> = unpacked.files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Disc 1/Game (Disc 1).cue", "Tetris (World).gb"]);
+        let (_, tetris) = &unpacked.files[1];
+        assert_eq!(tetris.file.path(), unpacked.dir.path().join("Tetris (World).gb"));
+        assert_eq!(tetris.size, 4);
+        assert_eq!(tetris.hashes.crc32, crate::rom_hash::hash_reader(&mut &[1u8, 2, 3, 4][..]).unwrap().crc32);
+        assert_eq!(std::fs::read(tetris.file.path()).unwrap(), [1, 2, 3, 4]);
+
+        // Over the limit, nothing unpacked so far is kept
+        assert_eq!(unpack_archive(&archive, "zip", &dir, 6).err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+        std::fs::write(dir.join("broken.zip"), b"not a zip").unwrap();
+        assert_eq!(unpack_archive(&dir.join("broken.zip"), "zip", &dir, 1024).err(), Some(StatusCode::BAD_REQUEST));
+
+        drop(unpacked);
+        let mut left: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        left.sort();
+        assert_eq!(left, ["broken.zip", "set.zip"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_group_unpacked_cue_sheets() {
+        let dir = std::env::temp_dir().join(format!("group-unpacked-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("games.zip");
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            for (name, data) in [
+                ("Crash/Crash (USA).cue", &b"FILE \"Crash (USA).bin\" BINARY\n"[..]),
+                ("Crash/Crash (USA).bin", &b"crash"[..]),
+                ("Game (USA) (Disc 1).cue", &b"FILE \"Game (USA) (Disc 1).bin\" BINARY\n"[..]),
+                ("Game (USA) (Disc 1).bin", &b"disc one"[..]),
+                ("Game (USA) (Disc 2).cue", &b"FILE \"Game (USA) (Disc 2).bin\" BINARY\n"[..]),
+                ("Game (USA) (Disc 2).bin", &b"disc two"[..]),
+                ("Tetris (World).gb", &[1, 2, 3, 4][..]),
+            ] {
+                zip.start_file(name, options).unwrap();
+                zip.write_all(data).unwrap();
+            }
+            zip.finish().unwrap();
+        }
+
+        let unpacked = unpack_archive(&archive, "zip", &dir, 1024).unwrap();
+        let emulators = crate::emulators::get_all_emulators();
+        let games = group_unpacked(unpacked.dir.path(), unpacked.files, None, &ScanConfig::default(), &emulators);
+        let games: HashMap<String, (StagedUpload, UploadMembers)> =
+            games.into_iter().map(|(name, main, members)| (name, (main, members))).collect();
+        let member_names = |members: &UploadMembers| -> Vec<String> {
+            members.files.iter().map(|(name, _)| name.clone()).collect()
+        };
+
+        // Tracks and discs are no games of their own
+        let mut names: Vec<&str> = games.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["Crash/Crash (USA).cue", "Game (USA).m3u", "Tetris (World).gb"]);
+
+        let (_, tetris) = &games["Tetris (World).gb"];
+        assert!(tetris.files.is_empty() && tetris.tracks.is_empty() && tetris.discs.is_empty());
+
+        // Members are named relative to their sheet
+        let (crash, crash_members) = &games["Crash/Crash (USA).cue"];
+        assert_eq!(crash.size, 30);
+        assert_eq!(member_names(crash_members), ["Crash (USA).bin"]);
+        assert_eq!(crash_members.tracks, ["Crash (USA).bin"]);
+
+        // The playlist written for the set is staged and hashed like an uploaded file
+        let (playlist, set) = &games["Game (USA).m3u"];
+        let contents = std::fs::read(playlist.file.path()).unwrap();
+        assert_eq!(contents, b"Game (USA) (Disc 1).cue\nGame (USA) (Disc 2).cue\n");
+        assert_eq!(playlist.hashes, crate::rom_hash::hash_reader(&contents[..]).unwrap());
+        assert_eq!(set.discs, ["Game (USA) (Disc 1).cue", "Game (USA) (Disc 2).cue"]);
+        assert_eq!(set.tracks, ["Game (USA) (Disc 1).bin", "Game (USA) (Disc 2).bin"]);
+        assert_eq!(
+            member_names(set),
+            ["Game (USA) (Disc 1).cue", "Game (USA) (Disc 2).cue", "Game (USA) (Disc 1).bin", "Game (USA) (Disc 2).bin"]
+        );
+
+        drop(games);
+        drop(unpacked.dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
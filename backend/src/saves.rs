@@ -0,0 +1,190 @@
+use axum::{
+    body::Bytes,
+    extract::{Extension, Path},
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+use crate::auth::AuthUser;
+
+/// Largest save state accepted by `put_save` (PS2/GameCube states can be tens of MB)
+pub const MAX_SAVE_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Serialize)]
+pub struct SaveSlotInfo {
+    pub slot: i32,
+    pub size: i64,
+    pub updated_at: String,
+}
+
+#[derive(Serialize)]
+pub struct SaveResult {
+    pub success: bool,
+    pub message: String,
+    pub slot: i32,
+    pub size: usize,
+}
+
+/// Make sure the game exists before touching its saves
+async fn ensure_game_exists(pool: &PgPool, game_id: i32) -> Result<(), StatusCode> {
+    sqlx::query_as::<_, (i32,)>("SELECT id FROM games WHERE id = $1")
+        .bind(game_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error looking up game: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(|_| ())
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+fn validate_slot(slot: i32) -> Result<(), StatusCode> {
+    if !(0..=99).contains(&slot) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+/// List the save slots the authenticated user has for a game
+pub async fn list_saves(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Path(game_id): Path<i32>,
+    auth: AuthUser,
+) -> Result<Json<Vec<SaveSlotInfo>>, StatusCode> {
+    ensure_game_exists(pool.as_ref(), game_id).await?;
+
+    let slots = sqlx::query_as::<_, (i32, i64, chrono::NaiveDateTime)>(
+        "SELECT slot, COALESCE(octet_length(save_data), 0)::BIGINT, created_at
+         FROM save_states
+         WHERE game_id = $1 AND user_id = $2
+         ORDER BY slot"
+    )
+    .bind(game_id)
+    .bind(auth.user_id)
+    .fetch_all(pool.as_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Error listing save states: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(
+        slots
+            .into_iter()
+            .map(|(slot, size, updated_at)| SaveSlotInfo {
+                slot,
+                size,
+                updated_at: updated_at.and_utc().to_rfc3339(),
+            })
+            .collect(),
+    ))
+}
+
+/// Download the binary save data stored in a slot
+pub async fn get_save(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Path((game_id, slot)): Path<(i32, i32)>,
+    auth: AuthUser,
+) -> Result<Response, StatusCode> {
+    validate_slot(slot)?;
+
+    let save_data = sqlx::query_as::<_, (Option<Vec<u8>>,)>(
+        "SELECT save_data FROM save_states WHERE game_id = $1 AND user_id = $2 AND slot = $3"
+    )
+    .bind(game_id)
+    .bind(auth.user_id)
+    .bind(slot)
+    .fetch_optional(pool.as_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Error fetching save state: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .and_then(|(data,)| data)
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"game{}_slot{}.state\"", game_id, slot),
+            ),
+        ],
+        save_data,
+    )
+        .into_response())
+}
+
+/// Store (or overwrite) the binary save data for a slot
+pub async fn put_save(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Path((game_id, slot)): Path<(i32, i32)>,
+    auth: AuthUser,
+    body: Bytes,
+) -> Result<Json<SaveResult>, StatusCode> {
+    validate_slot(slot)?;
+    if body.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    ensure_game_exists(pool.as_ref(), game_id).await?;
+
+    sqlx::query(
+        "INSERT INTO save_states (game_id, user_id, slot, save_data, created_at)
+         VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
+         ON CONFLICT (game_id, user_id, slot)
+         DO UPDATE SET save_data = EXCLUDED.save_data, created_at = CURRENT_TIMESTAMP"
+    )
+    .bind(game_id)
+    .bind(auth.user_id)
+    .bind(slot)
+    .bind(body.as_ref())
+    .execute(pool.as_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Error storing save state: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(SaveResult {
+        success: true,
+        message: format!("Saved slot {}", slot),
+        slot,
+        size: body.len(),
+    }))
+}
+
+/// Delete the save data stored in a slot
+pub async fn delete_save(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Path((game_id, slot)): Path<(i32, i32)>,
+    auth: AuthUser,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    validate_slot(slot)?;
+
+    let result = sqlx::query(
+        "DELETE FROM save_states WHERE game_id = $1 AND user_id = $2 AND slot = $3"
+    )
+    .bind(game_id)
+    .bind(auth.user_id)
+    .bind(slot)
+    .execute(pool.as_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Error deleting save state: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": format!("Deleted slot {}", slot)
+    })))
+}
@@ -0,0 +1,178 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::rom_scanner::get_system_mapping;
+
+const DEFAULT_MAX_DEPTH: usize = 3;
+
+/// The config file as written, e.g.
+///
+/// ```toml
+/// max_depth = 5
+/// ignore = ["**/_unused/**", "*.sav"]
+///
+/// [aliases]
+/// megadrive = "Sega Genesis"
+/// psx = "ps1"
+/// ```
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ScanConfigFile {
+    max_depth: usize,
+    ignore: Vec<String>,
+    aliases: HashMap<String, String>,
+}
+
+impl Default for ScanConfigFile {
+    fn default() -> Self {
+        ScanConfigFile {
+            max_depth: DEFAULT_MAX_DEPTH,
+            ignore: Vec::new(),
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+/// How the scanner walks the ROMs directory
+pub struct ScanConfig {
+    /// How far below a system directory files are picked up
+    pub max_depth: usize,
+    ignore: GlobSet,
+    /// System directory names, built-in and aliased, to system names
+    systems: HashMap<String, &'static str>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            max_depth: DEFAULT_MAX_DEPTH,
+            ignore: GlobSet::empty(),
+            systems: get_system_mapping()
+                .into_iter()
+                .map(|(dir, system)| (dir.to_string(), system))
+                .collect(),
+        }
+    }
+}
+
+/// Config file path, set with SCAN_CONFIG; `scan.toml` at the top of the ROMs directory by default
+pub fn scan_config_path(roms_path: &Path) -> PathBuf {
+    std::env::var("SCAN_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| roms_path.join("scan.toml"))
+}
+
+impl ScanConfig {
+    /// Read the config file. A missing file means the defaults; problems with individual
+    /// settings are returned alongside a config that leaves them out.
+    pub fn load(path: &Path) -> (ScanConfig, Vec<String>) {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (ScanConfig::default(), Vec::new()),
+            Err(e) => (ScanConfig::default(), vec![format!("Could not read {}: {}", path.display(), e)]),
+        }
+    }
+
+    pub fn parse(text: &str) -> (ScanConfig, Vec<String>) {
+        let file: ScanConfigFile = match toml::from_str(text) {
+            Ok(file) => file,
+            Err(e) => return (ScanConfig::default(), vec![format!("Invalid scan config: {}", e)]),
+        };
+        let mut config = ScanConfig {
+            max_depth: file.max_depth,
+            ..ScanConfig::default()
+        };
+        let mut errors = Vec::new();
+
+        let mut ignore = GlobSetBuilder::new();
+        for pattern in &file.ignore {
+            match Glob::new(pattern) {
+                Ok(glob) => {
+                    ignore.add(glob);
+                }
+                Err(e) => errors.push(format!("Invalid ignore pattern {:?}: {}", pattern, e)),
+            }
+        }
+        config.ignore = ignore.build().unwrap_or_else(|e| {
+            errors.push(format!("Invalid ignore patterns: {}", e));
+            GlobSet::empty()
+        });
+
+        // An alias may name the system ("Sega Genesis") or a built-in directory ("ps1")
+        let builtin = get_system_mapping();
+        for (dir, target) in file.aliases {
+            let system = builtin
+                .values()
+                .find(|system| system.eq_ignore_ascii_case(target.trim()))
+                .or_else(|| builtin.get(target.trim()));
+            match system {
+                Some(&system) => {
+                    config.systems.insert(dir, system);
+                }
+                None => errors.push(format!("Alias {:?} points to unknown system {:?}", dir, target)),
+            }
+        }
+
+        (config, errors)
+    }
+
+    /// Whether a path relative to the ROMs directory matches an ignore pattern
+    pub fn is_ignored(&self, relative_path: &str) -> bool {
+        self.ignore.is_match(relative_path)
+    }
+
+    /// The system a top-level directory holds
+    pub fn system_for_directory(&self, dir_name: &str) -> Option<&'static str> {
+        self.systems.get(dir_name).copied()
+    }
+
+    /// Directory names to system names, including aliases
+    pub fn systems(&self) -> &HashMap<String, &'static str> {
+        &self.systems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scan_config() {
+        let (config, errors) = ScanConfig::parse(
+            r#"
+            max_depth = 5
+            ignore = ["**/_unused/**", "*.sav", "docs/**"]
+
+            [aliases]
+            genesis = "Sega Genesis"
+            megadrive = "sega genesis"
+            psx = "ps1"
+            "#,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.max_depth, 5);
+        assert_eq!(config.system_for_directory("megadrive"), Some("Sega Genesis"));
+        assert_eq!(config.system_for_directory("psx"), Some("PlayStation 1"));
+        assert_eq!(config.system_for_directory("nes"), Some("Nintendo Entertainment System"));
+        assert_eq!(config.system_for_directory("amiga"), None);
+
+        assert!(config.is_ignored("snes/_unused/Mario.sfc"));
+        assert!(config.is_ignored("gba/saves/Pokemon.sav"));
+        assert!(!config.is_ignored("snes/Mario.sfc"));
+        // Top-level directories are checked with a trailing slash
+        assert!(config.is_ignored("docs/"));
+    }
+
+    #[test]
+    fn test_parse_scan_config_errors() {
+        let (config, errors) = ScanConfig::parse("ignore = [\"[unclosed\"]\n[aliases]\namiga = \"Commodore Amiga\"\n");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(config.max_depth, DEFAULT_MAX_DEPTH);
+        assert_eq!(config.system_for_directory("amiga"), None);
+
+        let (config, errors) = ScanConfig::parse("max_depht = 2\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(config.max_depth, DEFAULT_MAX_DEPTH);
+    }
+}
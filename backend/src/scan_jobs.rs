@@ -0,0 +1,257 @@
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Json,
+    },
+};
+use futures::stream::{self, Stream};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use rand::{distributions::Alphanumeric, Rng};
+use tokio::sync::watch;
+use crate::auth::AuthUser;
+use crate::rom_scanner::{ScanPhase, ScanProgress};
+use crate::roms::{import_roms, ScanOptions, ScanResult};
+
+const JOB_ID_LEN: usize = 12;
+
+/// Finished jobs kept around so their results can still be fetched
+const KEEP_FINISHED: usize = 20;
+
+/// How often the event stream reports progress
+const STREAM_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One run of `import_roms` in the background
+pub struct ScanJob {
+    pub id: String,
+    started_at: chrono::DateTime<chrono::Utc>,
+    started: Instant,
+    progress: Arc<ScanProgress>,
+    /// The report and how long the scan took, once it is done
+    result: Mutex<Option<(ScanResult, Duration)>>,
+    finished: watch::Sender<bool>,
+}
+
+/// A snapshot of a job, as returned by the API
+#[derive(Serialize, Clone)]
+pub struct ScanStatus {
+    pub job_id: String,
+    pub phase: ScanPhase,
+    pub finished: bool,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub elapsed_secs: u64,
+    pub files_seen: usize,
+    pub files_to_read: usize,
+    pub files_read: usize,
+    pub added: usize,
+    /// Known files left alone because they haven't changed
+    pub skipped: usize,
+    pub errors: usize,
+    /// Estimated seconds left, once hashing has made some headway
+    pub eta_secs: Option<u64>,
+    /// The full report, once the job is finished
+    pub result: Option<ScanResult>,
+}
+
+/// Seconds left if the rest goes as fast as `done` out of `total` did in `elapsed`
+fn estimate_remaining(elapsed: Duration, done: u64, total: u64) -> Option<u64> {
+    if done == 0 {
+        return None;
+    }
+    let rate = done as f64 / elapsed.as_secs_f64().max(0.001);
+    Some((total.saturating_sub(done) as f64 / rate).round() as u64)
+}
+
+impl ScanJob {
+    pub fn is_finished(&self) -> bool {
+        *self.finished.borrow()
+    }
+
+    pub fn status(&self) -> ScanStatus {
+        let progress = &self.progress;
+        let (phase, phase_started) = progress.phase();
+        let bytes_read = progress.bytes_read.load(Ordering::Relaxed);
+        let bytes_to_read = progress.bytes_to_read.load(Ordering::Relaxed);
+        // Hashing is most of the work; extrapolate from the bytes it has got through
+        let eta_secs = match (phase, phase_started) {
+            (ScanPhase::Reading, Some(since)) => estimate_remaining(since.elapsed(), bytes_read, bytes_to_read),
+            (ScanPhase::Finished, _) => Some(0),
+            _ => None,
+        };
+        let result = self.result.lock().unwrap().clone();
+        let elapsed = result.as_ref().map(|(_, took)| *took).unwrap_or_else(|| self.started.elapsed());
+        ScanStatus {
+            job_id: self.id.clone(),
+            phase,
+            finished: self.is_finished(),
+            started_at: self.started_at,
+            elapsed_secs: elapsed.as_secs(),
+            files_seen: progress.files_seen.load(Ordering::Relaxed),
+            files_to_read: progress.files_to_read.load(Ordering::Relaxed),
+            files_read: progress.files_read.load(Ordering::Relaxed),
+            added: progress.added.load(Ordering::Relaxed),
+            skipped: progress.skipped.load(Ordering::Relaxed),
+            errors: progress.errors.load(Ordering::Relaxed),
+            eta_secs,
+            result: result.map(|(result, _)| result),
+        }
+    }
+
+    /// Wait for the job to finish and return its report
+    pub async fn wait(&self) -> ScanResult {
+        let mut finished = self.finished.subscribe();
+        let _ = finished.wait_for(|&done| done).await;
+        self.result.lock().unwrap().clone().map(|(result, _)| result).unwrap_or_default()
+    }
+
+    /// Wait up to `timeout` for the job to finish; true if it has
+    async fn wait_timeout(&self, timeout: Duration) -> bool {
+        let mut finished = self.finished.subscribe();
+        let done = tokio::time::timeout(timeout, finished.wait_for(|&done| done)).await.is_ok();
+        done
+    }
+}
+
+#[derive(Default)]
+struct Jobs {
+    by_id: HashMap<String, Arc<ScanJob>>,
+    running: Option<Arc<ScanJob>>,
+}
+
+/// Background library scans. Only one runs at a time; scans started from the API,
+/// on startup and by the watcher all go through here.
+#[derive(Default)]
+pub struct ScanJobs {
+    jobs: Mutex<Jobs>,
+}
+
+impl ScanJobs {
+    /// Start a scan, or hand back the one already running as the error
+    pub fn start(&self, pool: Arc<PgPool>, base_path: PathBuf, options: ScanOptions) -> Result<Arc<ScanJob>, Arc<ScanJob>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(running) = jobs.running.as_ref().filter(|job| !job.is_finished()) {
+            return Err(running.clone());
+        }
+
+        let id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(JOB_ID_LEN)
+            .map(char::from)
+            .collect();
+        let job = Arc::new(ScanJob {
+            id: id.clone(),
+            started_at: chrono::Utc::now(),
+            started: Instant::now(),
+            progress: Arc::new(ScanProgress::default()),
+            result: Mutex::new(None),
+            finished: watch::channel(false).0,
+        });
+
+        // Forget the oldest finished jobs
+        if jobs.by_id.len() >= KEEP_FINISHED {
+            let mut finished: Vec<&Arc<ScanJob>> = jobs.by_id.values().filter(|j| j.is_finished()).collect();
+            finished.sort_by_key(|j| j.started);
+            let excess = jobs.by_id.len() + 1 - KEEP_FINISHED;
+            let stale: Vec<String> = finished.iter().take(excess).map(|j| j.id.clone()).collect();
+            for id in stale {
+                jobs.by_id.remove(&id);
+            }
+        }
+        jobs.by_id.insert(id, job.clone());
+        jobs.running = Some(job.clone());
+
+        let task_job = job.clone();
+        tokio::spawn(async move {
+            // A panicking scan must still finish its job, or no scan could ever start again
+            let progress = task_job.progress.clone();
+            let result = tokio::spawn(async move { import_roms(pool.as_ref(), &base_path, options, &progress).await })
+                .await
+                .unwrap_or_else(|e| ScanResult {
+                    errors: vec![format!("ROM scan failed: {}", e)],
+                    ..ScanResult::default()
+                });
+            task_job.progress.set_phase(ScanPhase::Finished);
+            *task_job.result.lock().unwrap() = Some((result, task_job.started.elapsed()));
+            task_job.finished.send_replace(true);
+        });
+        Ok(job)
+    }
+
+    /// Run a scan to completion, queueing behind one that is already running
+    pub async fn run(&self, pool: Arc<PgPool>, base_path: PathBuf, options: ScanOptions) -> ScanResult {
+        loop {
+            match self.start(pool.clone(), base_path.clone(), options) {
+                Ok(job) => return job.wait().await,
+                Err(running) => {
+                    running.wait().await;
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<ScanJob>> {
+        self.jobs.lock().unwrap().by_id.get(id).cloned()
+    }
+}
+
+/// Progress of a scan job (admin only)
+pub async fn get_scan_job(
+    Extension(jobs): Extension<Arc<ScanJobs>>,
+    auth: AuthUser,
+    Path(job_id): Path<String>,
+) -> Result<Json<ScanStatus>, StatusCode> {
+    auth.require_admin()?;
+
+    let job = jobs.get(&job_id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(job.status()))
+}
+
+/// Progress of a scan job as server-sent events (admin only). A `progress` event
+/// is sent every second while the job runs, then one `finished` event with the result.
+pub async fn scan_job_events(
+    Extension(jobs): Extension<Arc<ScanJobs>>,
+    auth: AuthUser,
+    Path(job_id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    auth.require_admin()?;
+
+    let job = jobs.get(&job_id).ok_or(StatusCode::NOT_FOUND)?;
+    // State is (job, whether a report has been sent yet, whether the last one was final)
+    let events = stream::unfold((job, false, false), |(job, sent, done)| async move {
+        if done {
+            return None;
+        }
+        let finished = if sent {
+            job.wait_timeout(STREAM_INTERVAL).await
+        } else {
+            job.is_finished()
+        };
+        let status = job.status();
+        let event = Event::default()
+            .event(if finished { "finished" } else { "progress" })
+            .json_data(&status);
+        Some((event, (job, true, finished)))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_remaining() {
+        assert_eq!(estimate_remaining(Duration::from_secs(10), 0, 100), None);
+        assert_eq!(estimate_remaining(Duration::from_secs(10), 25, 100), Some(30));
+        assert_eq!(estimate_remaining(Duration::from_secs(10), 100, 100), Some(0));
+        // Sizes can grow while a file is read; never go negative
+        assert_eq!(estimate_remaining(Duration::from_secs(10), 120, 100), Some(0));
+    }
+}
@@ -1,12 +1,16 @@
 use axum::{
     extract::Extension,
-    http::{HeaderMap, StatusCode},
-    response::Json,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
 };
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use std::sync::Arc;
-use bcrypt::{hash, verify, DEFAULT_COST};
+use bcrypt::verify;
+use crate::auth::{issue_tokens, AuthUser, Role};
+use crate::client_ip::ClientIp;
+use crate::rate_limit::LoginLimiter;
+use crate::users::is_last_admin;
 
 #[derive(Serialize, Deserialize)]
 pub struct LoginRequest {
@@ -14,17 +18,46 @@ pub struct LoginRequest {
     pub password: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize)]
 pub struct LoginResponse {
     pub success: bool,
     pub message: String,
     pub username: Option<String>,
     pub user_id: Option<i32>,
+    pub role: Option<Role>,
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct SessionRequest {
-    pub username: String,
+impl LoginResponse {
+    pub(crate) fn failure(message: &str) -> Self {
+        LoginResponse {
+            success: false,
+            message: message.to_string(),
+            username: None,
+            user_id: None,
+            role: None,
+            access_token: None,
+            refresh_token: None,
+            expires_in: None,
+        }
+    }
+
+    /// Successful login, carrying a freshly signed token pair
+    pub(crate) fn success(message: &str, username: String, user_id: i32, role: Role) -> Result<Self, StatusCode> {
+        let tokens = issue_tokens(user_id, &username, role)?;
+        Ok(LoginResponse {
+            success: true,
+            message: message.to_string(),
+            username: Some(username),
+            user_id: Some(user_id),
+            role: Some(role),
+            access_token: Some(tokens.access_token),
+            refresh_token: Some(tokens.refresh_token),
+            expires_in: Some(tokens.expires_in),
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -41,7 +74,6 @@ pub struct ConnectedUsersResponse {
 
 #[derive(Serialize, Deserialize)]
 pub struct DeleteAccountRequest {
-    pub username: String,
     pub password: String,
 }
 
@@ -50,204 +82,92 @@ pub struct PreviousUsernamesResponse {
     pub usernames: Vec<String>,
 }
 
-/// Extract IP address from headers, handling Cloudflare headers
-fn extract_ip_address(headers: &HeaderMap) -> String {
-    // Check Cloudflare headers first (when behind Cloudflare Tunnel)
-    if let Some(cf_connecting_ip) = headers.get("cf-connecting-ip") {
-        if let Ok(ip) = cf_connecting_ip.to_str() {
-            return ip.to_string();
-        }
-    }
+/// Whether `/api/auth/previous-usernames` lists accounts, set with PUBLIC_USERNAME_LIST
+fn public_username_list_enabled() -> bool {
+    std::env::var("PUBLIC_USERNAME_LIST")
+        .map(|v| !matches!(v.to_lowercase().as_str(), "false" | "0" | "no" | "off"))
+        .unwrap_or(true)
+}
+
+/// Authenticate an existing user, throttling repeated failures per IP and per username
+pub async fn login(
+    Extension(pool): Extension<Arc<PgPool>>,
+    Extension(limiter): Extension<Arc<LoginLimiter>>,
+    ClientIp(client_ip): ClientIp,
+    Json(login_req): Json<LoginRequest>,
+) -> Result<Response, StatusCode> {
+    let ip_address = client_ip.to_string();
     
-    // Check X-Forwarded-For header
-    if let Some(xff) = headers.get("x-forwarded-for") {
-        if let Ok(ip) = xff.to_str() {
-            // X-Forwarded-For can contain multiple IPs, take the first one
-            return ip.split(',').next().unwrap_or("").trim().to_string();
-        }
+    if let Some(wait) = limiter.check(&ip_address, &login_req.username) {
+        let retry_after = wait.as_secs().max(1);
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            Json(LoginResponse::failure(&format!(
+                "Too many failed login attempts, try again in {} seconds",
+                retry_after
+            ))),
+        )
+            .into_response());
     }
     
-    // Check X-Real-IP header
-    if let Some(xri) = headers.get("x-real-ip") {
-        if let Ok(ip) = xri.to_str() {
-            return ip.to_string();
-        }
+    // The attempt was counted as a failure by `check`; take it back unless it was one
+    let username = login_req.username.clone();
+    let response = authenticate(pool.as_ref(), login_req).await;
+    match &response {
+        Ok(response) if response.success => limiter.record_success(&ip_address, &username),
+        Ok(_) => {}
+        Err(_) => limiter.release(&ip_address, &username),
     }
     
-    // Fallback
-    "unknown".to_string()
+    Ok(response?.into_response())
 }
 
-/// Authenticate user with username and password from database
-pub async fn login(
-    Extension(pool): Extension<Arc<PgPool>>,
-    Json(login_req): Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, StatusCode> {
+/// Check a username/password pair against the database
+async fn authenticate(pool: &PgPool, login_req: LoginRequest) -> Result<Json<LoginResponse>, StatusCode> {
     // Validate username (must be non-empty and reasonable length)
     if login_req.username.is_empty() || login_req.username.len() > 50 {
-        return Ok(Json(LoginResponse {
-            success: false,
-            message: "Invalid username".to_string(),
-            username: None,
-            user_id: None,
-        }));
+        return Ok(Json(LoginResponse::failure("Invalid username")));
     }
     
     // Check if user exists in database
-    let user_result = sqlx::query_as::<_, (i32, String)>(
-        "SELECT id, password_hash FROM users WHERE username = $1"
+    let user_result = sqlx::query_as::<_, (i32, String, String)>(
+        "SELECT id, password_hash, role FROM users WHERE username = $1"
     )
     .bind(&login_req.username)
-    .fetch_optional(pool.as_ref())
+    .fetch_optional(pool)
     .await
     .map_err(|e| {
         eprintln!("Database error during login: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
     
-    match user_result {
-        Some((user_id, password_hash)) => {
-            // User exists, verify password
-            match verify(&login_req.password, &password_hash) {
-                Ok(true) => {
-    Ok(Json(LoginResponse {
-        success: true,
-        message: "Login successful".to_string(),
-        username: Some(login_req.username),
-                        user_id: Some(user_id),
-    }))
-                }
-                Ok(false) => {
-                    Ok(Json(LoginResponse {
-                        success: false,
-                        message: "Invalid password".to_string(),
-                        username: None,
-                        user_id: None,
-                    }))
-                }
-                Err(e) => {
-                    eprintln!("Password verification error: {}", e);
-                    Ok(Json(LoginResponse {
-                        success: false,
-                        message: "Authentication error".to_string(),
-                        username: None,
-                        user_id: None,
-                    }))
-                }
-            }
+    // Unknown users and wrong passwords get the same answer
+    let (user_id, password_hash, role) = match user_result {
+        Some(user) => user,
+        None => return Ok(Json(LoginResponse::failure("Invalid username or password"))),
+    };
+    
+    match verify(&login_req.password, &password_hash) {
+        Ok(true) => {
+            let role = Role::parse(&role).unwrap_or(Role::Player);
+            LoginResponse::success("Login successful", login_req.username, user_id, role).map(Json)
         }
-        None => {
-            // User doesn't exist, create new user with hashed password
-            let password_hash = hash(&login_req.password, DEFAULT_COST)
-                .map_err(|e| {
-                    eprintln!("Password hashing error: {}", e);
-                    StatusCode::INTERNAL_SERVER_ERROR
-                })?;
-            
-            // Generate a dummy email (username@gamersunite.local) since email is required but not used
-            let email = format!("{}@gamersunite.local", login_req.username);
-            
-            let result = sqlx::query(
-                "INSERT INTO users (username, email, password_hash) 
-                 VALUES ($1, $2, $3)
-                 ON CONFLICT (username) DO NOTHING"
-            )
-            .bind(&login_req.username)
-            .bind(&email)
-            .bind(&password_hash)
-            .execute(pool.as_ref())
-            .await;
-            
-            match result {
-                Ok(_result) => {
-                    // Get the created user's ID
-                    let created_user_id = sqlx::query_as::<_, (i32,)>(
-                        "SELECT id FROM users WHERE username = $1"
-                    )
-                    .bind(&login_req.username)
-                    .fetch_optional(pool.as_ref())
-                    .await
-                    .ok()
-                    .flatten()
-                    .map(|(id,)| id);
-                    
-                    Ok(Json(LoginResponse {
-                        success: true,
-                        message: "Account created and login successful".to_string(),
-                        username: Some(login_req.username),
-                        user_id: created_user_id,
-                    }))
-                }
-                Err(sqlx::Error::Database(db_err)) if db_err.constraint().is_some() => {
-                    // Username conflict, try to login again (race condition)
-                    // This shouldn't happen often, but handle it gracefully
-                    let user_result = sqlx::query_as::<_, (i32, String)>(
-                        "SELECT id, password_hash FROM users WHERE username = $1"
-                    )
-                    .bind(&login_req.username)
-                    .fetch_optional(pool.as_ref())
-                    .await
-                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                    
-                    if let Some((_, password_hash)) = user_result {
-                        let user_id_result = sqlx::query_as::<_, (i32,)>(
-                            "SELECT id FROM users WHERE username = $1"
-                        )
-                        .bind(&login_req.username)
-                        .fetch_optional(pool.as_ref())
-                        .await
-                        .ok()
-                        .flatten();
-                        
-                        match verify(&login_req.password, &password_hash) {
-                            Ok(true) => {
-                                Ok(Json(LoginResponse {
-                                    success: true,
-                                    message: "Login successful".to_string(),
-                                    username: Some(login_req.username),
-                                    user_id: user_id_result.map(|(id,)| id),
-                                }))
-                            }
-                            _ => {
-                                Ok(Json(LoginResponse {
-                                    success: false,
-                                    message: "Invalid password".to_string(),
-                                    username: None,
-                                    user_id: None,
-                                }))
-                            }
-                        }
-                    } else {
-                        Ok(Json(LoginResponse {
-                            success: false,
-                            message: "Account creation failed".to_string(),
-                            username: None,
-                            user_id: None,
-                        }))
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Error creating user: {}", e);
-                    Err(StatusCode::INTERNAL_SERVER_ERROR)
-                }
-            }
+        Ok(false) => Ok(Json(LoginResponse::failure("Invalid username or password"))),
+        Err(e) => {
+            eprintln!("Password verification error: {}", e);
+            Ok(Json(LoginResponse::failure("Authentication error")))
         }
     }
 }
 
-/// Register or update user session with IP address
+/// Register or update the authenticated user's session with IP address
 pub async fn register_session(
     Extension(pool): Extension<Arc<PgPool>>,
-    headers: HeaderMap,
-    Json(session_req): Json<SessionRequest>,
+    auth: AuthUser,
+    ClientIp(client_ip): ClientIp,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    // Extract IP address from headers
-    let ip_address = extract_ip_address(&headers);
-    
-    // Validate username
-    if session_req.username.is_empty() || session_req.username.len() > 50 {
-        return Err(StatusCode::BAD_REQUEST);
-    }
+    let ip_address = client_ip.to_string();
     
     // Upsert session (insert or update last_seen)
     let result = sqlx::query(
@@ -256,7 +176,7 @@ pub async fn register_session(
          ON CONFLICT (username, ip_address)
          DO UPDATE SET last_seen = CURRENT_TIMESTAMP"
     )
-    .bind(&session_req.username)
+    .bind(&auth.username)
     .bind(&ip_address)
     .execute(pool.as_ref())
     .await;
@@ -315,12 +235,17 @@ pub async fn cleanup_old_sessions(pool: Arc<PgPool>) {
     .await;
 }
 
-/// Get list of all previous usernames (for quick login)
+/// Get list of all previous usernames (for quick login).
+/// Returns an empty list when PUBLIC_USERNAME_LIST is turned off.
 pub async fn get_previous_usernames(
     Extension(pool): Extension<Arc<PgPool>>,
 ) -> Result<Json<PreviousUsernamesResponse>, StatusCode> {
+    if !public_username_list_enabled() {
+        return Ok(Json(PreviousUsernamesResponse { usernames: Vec::new() }));
+    }
+    
     let usernames = sqlx::query_as::<_, (String,)>(
-        "SELECT DISTINCT username FROM users ORDER BY created_at DESC LIMIT 50"
+        "SELECT username FROM users ORDER BY created_at DESC LIMIT 50"
     )
     .fetch_all(pool.as_ref())
     .await
@@ -339,21 +264,19 @@ pub async fn get_previous_usernames(
     }))
 }
 
-/// Delete user account and all associated data
+/// Delete the authenticated user's account and all associated data.
+/// The password is required again so a stolen access token cannot wipe an account.
+/// The last admin can't delete their account.
 pub async fn delete_account(
     Extension(pool): Extension<Arc<PgPool>>,
+    auth: AuthUser,
     Json(delete_req): Json<DeleteAccountRequest>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    // Validate username
-    if delete_req.username.is_empty() || delete_req.username.len() > 50 {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-    
     // Verify user exists and password is correct
     let user_result = sqlx::query_as::<_, (i32, String)>(
-        "SELECT id, password_hash FROM users WHERE username = $1"
+        "SELECT id, password_hash FROM users WHERE id = $1"
     )
-    .bind(&delete_req.username)
+    .bind(auth.user_id)
     .fetch_optional(pool.as_ref())
     .await
     .map_err(|e| {
@@ -366,17 +289,31 @@ pub async fn delete_account(
             // Verify password
             match verify(&delete_req.password, &password_hash) {
                 Ok(true) => {
-                    // Password is correct, delete user (CASCADE will handle related data)
-                    let result = sqlx::query("DELETE FROM users WHERE id = $1")
-                        .bind(user_id)
-                        .execute(pool.as_ref())
-                        .await;
+                    // Password is correct, delete user (CASCADE will handle related data),
+                    // unless that would leave nobody able to manage the instance
+                    let result = async {
+                        let mut tx = pool.begin().await?;
+                        if is_last_admin(&mut tx, user_id).await? {
+                            return Ok(false);
+                        }
+                        sqlx::query("DELETE FROM users WHERE id = $1")
+                            .bind(user_id)
+                            .execute(&mut *tx)
+                            .await?;
+                        tx.commit().await?;
+                        Ok::<_, sqlx::Error>(true)
+                    }
+                    .await;
                     
                     match result {
-                        Ok(_) => {
+                        Ok(false) => Ok(Json(serde_json::json!({
+                            "success": false,
+                            "message": "Cannot delete the last admin account, make another user an admin first"
+                        }))),
+                        Ok(true) => {
                             // Also clean up active sessions for this user
                             let _ = sqlx::query("DELETE FROM active_sessions WHERE username = $1")
-                                .bind(&delete_req.username)
+                                .bind(&auth.username)
                                 .execute(pool.as_ref())
                                 .await;
                             
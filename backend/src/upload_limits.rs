@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// Largest upload accepted for systems without their own limit
+const DEFAULT_MAX_SIZE: u64 = 4 * GIB;
+
+/// Systems whose dumps regularly outgrow the default: dual-layer DVDs, Blu-rays and
+/// large cartridges
+const SYSTEM_DEFAULTS: &[(&str, u64)] = &[
+    ("gamecube", 16 * GIB),
+    ("wii", 16 * GIB),
+    ("ps2", 16 * GIB),
+    ("3ds", 8 * GIB),
+    ("psvita", 8 * GIB),
+    ("ps3", 64 * GIB),
+    ("switch", 64 * GIB),
+];
+
+/// Largest file that may be uploaded for each system. UPLOAD_MAX_SIZE sets the default
+/// and UPLOAD_MAX_SIZE_<DIR> (e.g. UPLOAD_MAX_SIZE_PS3=100G) overrides one system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadLimits {
+    default: u64,
+    /// By system directory name
+    systems: HashMap<String, u64>,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        UploadLimits {
+            default: DEFAULT_MAX_SIZE,
+            systems: SYSTEM_DEFAULTS
+                .iter()
+                .map(|&(dir, size)| (dir.to_string(), size))
+                .collect(),
+        }
+    }
+}
+
+/// A size like `700M`, `4G`, `64GiB` or a plain number of bytes
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let multiplier: u64 = match unit.trim().to_lowercase().trim_end_matches("ib").trim_end_matches('b') {
+        "" => 1,
+        "k" => 1024,
+        "m" => 1024 * 1024,
+        "g" => GIB,
+        "t" => 1024 * GIB,
+        _ => return None,
+    };
+    number.checked_mul(multiplier)
+}
+
+impl UploadLimits {
+    pub fn from_env() -> Self {
+        Self::parse(std::env::vars())
+    }
+
+    fn parse(vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut limits = UploadLimits::default();
+        for (key, value) in vars {
+            let Some(target) = key.strip_prefix("UPLOAD_MAX_SIZE") else { continue };
+            let Some(size) = parse_size(&value) else {
+                println!("Warning: ignoring {}={:?}, expected a size like 4G", key, value);
+                continue;
+            };
+            match target.strip_prefix('_') {
+                None if target.is_empty() => limits.default = size,
+                Some(dir) if !dir.is_empty() => {
+                    // Directory names use dashes, which env var names can't
+                    limits.systems.insert(dir.to_lowercase().replace('_', "-"), size);
+                }
+                _ => {}
+            }
+        }
+        limits
+    }
+
+    /// Limit for uploads into a system directory
+    pub fn for_system(&self, dir: &str) -> u64 {
+        self.systems.get(dir).copied().unwrap_or(self.default)
+    }
+
+    /// The most any upload may be, for when the system isn't known yet
+    pub fn largest(&self) -> u64 {
+        self.systems.values().copied().fold(self.default, u64::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("700M"), Some(700 * 1024 * 1024));
+        assert_eq!(parse_size(" 4g "), Some(4 * GIB));
+        assert_eq!(parse_size("64GiB"), Some(64 * GIB));
+        assert_eq!(parse_size("2 TB"), Some(2048 * GIB));
+        assert_eq!(parse_size("lots"), None);
+        assert_eq!(parse_size("4X"), None);
+        assert_eq!(parse_size(""), None);
+    }
+
+    #[test]
+    fn test_upload_limits_parse() {
+        let vars = [
+            ("UPLOAD_MAX_SIZE", "1G"),
+            ("UPLOAD_MAX_SIZE_PS3", "100G"),
+            ("UPLOAD_MAX_SIZE_SEGA_CD", "900M"),
+            ("UPLOAD_MAX_SIZE_NES", "nonsense"),
+            ("UPLOAD_MAX_SIZES", "2G"),
+            ("PATH", "/usr/bin"),
+        ];
+        let limits = UploadLimits::parse(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        assert_eq!(limits.for_system("nes"), GIB);
+        assert_eq!(limits.for_system("ps3"), 100 * GIB);
+        assert_eq!(limits.for_system("sega-cd"), 900 * 1024 * 1024);
+        assert_eq!(limits.for_system("switch"), 64 * GIB);
+        assert_eq!(limits.largest(), 100 * GIB);
+    }
+}
@@ -0,0 +1,110 @@
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use crate::auth::{AuthUser, Role};
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct UserSummary {
+    pub id: i32,
+    pub username: String,
+    pub email: Option<String>,
+    pub role: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct SetRoleRequest {
+    pub role: Role,
+}
+
+/// Whether `user_id` is the only admin. Every admin row stays locked until the transaction
+/// ends, so two admins demoting or deleting each other at once can't both get through.
+pub(crate) async fn is_last_admin(tx: &mut Transaction<'_, Postgres>, user_id: i32) -> Result<bool, sqlx::Error> {
+    let admins: Vec<i32> = sqlx::query_scalar("SELECT id FROM users WHERE role = 'admin' ORDER BY id FOR UPDATE")
+        .fetch_all(&mut **tx)
+        .await?;
+    Ok(admins == [user_id])
+}
+
+/// List all accounts with their roles (admin only)
+pub async fn list_users(
+    Extension(pool): Extension<Arc<PgPool>>,
+    auth: AuthUser,
+) -> Result<Json<Vec<UserSummary>>, StatusCode> {
+    auth.require_admin()?;
+
+    let users = sqlx::query_as::<_, UserSummary>(
+        "SELECT id, username, email, role, created_at FROM users ORDER BY created_at"
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Error listing users: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(users))
+}
+
+/// Change a user's role (admin only). The last admin cannot be demoted.
+pub async fn set_user_role(
+    Extension(pool): Extension<Arc<PgPool>>,
+    auth: AuthUser,
+    Path(user_id): Path<i32>,
+    Json(req): Json<SetRoleRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    auth.require_admin()?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        eprintln!("Database error changing role: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // Admins are locked before the target row, in the same order as `delete_account`
+    let last_admin = is_last_admin(&mut tx, user_id).await.map_err(|e| {
+        eprintln!("Database error changing role: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    sqlx::query_as::<_, (i32,)>("SELECT id FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error changing role: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if last_admin && req.role != Role::Admin {
+        return Ok(Json(serde_json::json!({
+            "success": false,
+            "message": "Cannot demote the last admin"
+        })));
+    }
+
+    sqlx::query("UPDATE users SET role = $1 WHERE id = $2")
+        .bind(req.role.as_str())
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error changing role: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    tx.commit().await.map_err(|e| {
+        eprintln!("Database error changing role: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": format!("Role changed to {}", req.role.as_str())
+    })))
+}
@@ -0,0 +1,162 @@
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use sqlx::PgPool;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use crate::roms::{ScanOptions, UPLOAD_STAGING_DIR};
+use crate::scan_jobs::ScanJobs;
+
+/// How long the ROMs directory has to stay quiet before a scan starts, so a large
+/// copy or a batch of moves is picked up in one pass rather than file by file
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 300;
+
+/// How the library follows changes to ROMS_PATH, set with ROMS_WATCH
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    /// Filesystem notifications (inotify on Linux)
+    Notify,
+    /// Rescan every ROMS_POLL_INTERVAL seconds, for network mounts that send no notifications
+    Poll(Duration),
+    /// Only scan on startup and on request
+    Off,
+}
+
+impl WatchMode {
+    pub fn from_env() -> Self {
+        let interval = std::env::var("ROMS_POLL_INTERVAL").ok();
+        Self::parse(&std::env::var("ROMS_WATCH").unwrap_or_default(), interval.as_deref())
+    }
+
+    fn parse(mode: &str, interval: Option<&str>) -> Self {
+        let interval = interval
+            .and_then(|secs| secs.trim().parse::<u64>().ok())
+            .filter(|&secs| secs > 0)
+            .unwrap_or(DEFAULT_POLL_INTERVAL_SECS);
+        match mode.trim().to_lowercase().as_str() {
+            "poll" | "polling" => WatchMode::Poll(Duration::from_secs(interval)),
+            "off" | "none" | "false" => WatchMode::Off,
+            _ => WatchMode::Notify,
+        }
+    }
+}
+
+/// Opening and reading files (which the scan itself does) is not a change, and neither
+/// is an upload being written to the staging directory; the finished file is added when
+/// it is renamed into place
+fn is_change(event: &Event, staging_dir: &Path) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && (event.paths.is_empty() || event.paths.iter().any(|path| !path.starts_with(staging_dir)))
+}
+
+/// Keep the games table in sync with the ROMs directory in the background.
+///
+/// Changes are batched until the directory has been quiet for a moment, then the
+/// regular incremental import runs: new files are added, changed ones re-read, moved
+/// ones followed, and vanished ones marked missing. If notifications can't be set up
+/// (no inotify, watch limit reached) this falls back to polling.
+pub fn spawn(pool: Arc<PgPool>, jobs: Arc<ScanJobs>, roms_path: PathBuf) {
+    let mode = WatchMode::from_env();
+    tokio::spawn(async move {
+        match mode {
+            WatchMode::Off => {}
+            WatchMode::Poll(interval) => poll(pool, jobs, roms_path, interval).await,
+            WatchMode::Notify => {
+                let (tx, rx) = mpsc::unbounded_channel();
+                let staging_dir = roms_path.join(UPLOAD_STAGING_DIR);
+                let watcher = RecommendedWatcher::new(
+                    move |event: notify::Result<Event>| match event {
+                        Ok(event) if is_change(&event, &staging_dir) => {
+                            let _ = tx.send(());
+                        }
+                        Ok(_) => {}
+                        Err(e) => eprintln!("ROM watcher error: {}", e),
+                    },
+                    notify::Config::default(),
+                )
+                .and_then(|mut watcher| {
+                    watcher.watch(&roms_path, RecursiveMode::Recursive)?;
+                    Ok(watcher)
+                });
+                match watcher {
+                    Ok(watcher) => {
+                        println!("Watching {} for ROM changes", roms_path.display());
+                        debounce(pool, jobs, roms_path, rx).await;
+                        drop(watcher);
+                    }
+                    Err(e) => {
+                        println!(
+                            "Warning: could not watch {} ({}), polling every {}s instead",
+                            roms_path.display(),
+                            e,
+                            DEFAULT_POLL_INTERVAL_SECS
+                        );
+                        poll(pool, jobs, roms_path, Duration::from_secs(DEFAULT_POLL_INTERVAL_SECS)).await;
+                    }
+                }
+            }
+        }
+    });
+}
+
+async fn debounce(pool: Arc<PgPool>, jobs: Arc<ScanJobs>, roms_path: PathBuf, mut rx: mpsc::UnboundedReceiver<()>) {
+    let mut known_missing = Vec::new();
+    while rx.recv().await.is_some() {
+        // Keep waiting while events keep arriving
+        while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {}
+        scan(&pool, &jobs, &roms_path, &mut known_missing).await;
+    }
+}
+
+/// Mounts that don't deliver events (NFS, SMB, some FUSE filesystems) are rescanned on
+/// a timer instead. Unchanged files are only stat'ed, so a pass over a large library is cheap.
+async fn poll(pool: Arc<PgPool>, jobs: Arc<ScanJobs>, roms_path: PathBuf, interval: Duration) {
+    println!("Polling {} for ROM changes every {}s", roms_path.display(), interval.as_secs());
+    let mut ticker = tokio::time::interval(interval);
+    // The startup scan has just run
+    ticker.tick().await;
+    let mut known_missing = Vec::new();
+    loop {
+        ticker.tick().await;
+        scan(&pool, &jobs, &roms_path, &mut known_missing).await;
+    }
+}
+
+/// Run an incremental import and log what it changed. Games that were already missing
+/// are reported by every scan, so only files missing since `known_missing` are logged.
+async fn scan(pool: &Arc<PgPool>, jobs: &ScanJobs, roms_path: &Path, known_missing: &mut Vec<String>) {
+    if !roms_path.exists() {
+        return;
+    }
+    let result = jobs.run(pool.clone(), roms_path.to_path_buf(), ScanOptions::default()).await;
+    for error in &result.errors {
+        println!("Warning: {}", error);
+    }
+    let newly_missing = result.missing.iter().filter(|path| !known_missing.contains(path)).count();
+    if result.newly_added + result.changed + result.moved + result.restored + newly_missing > 0 {
+        println!(
+            "ROM library updated: {} added, {} changed, {} moved, {} restored, {} missing",
+            result.newly_added, result.changed, result.moved, result.restored, newly_missing
+        );
+    }
+    *known_missing = result.missing;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_mode_parse() {
+        assert_eq!(WatchMode::parse("", None), WatchMode::Notify);
+        assert_eq!(WatchMode::parse("inotify", Some("60")), WatchMode::Notify);
+        assert_eq!(WatchMode::parse("Poll", Some("60")), WatchMode::Poll(Duration::from_secs(60)));
+        assert_eq!(
+            WatchMode::parse("poll", Some("0")),
+            WatchMode::Poll(Duration::from_secs(DEFAULT_POLL_INTERVAL_SECS))
+        );
+        assert_eq!(WatchMode::parse("off", None), WatchMode::Off);
+    }
+}
@@ -2,11 +2,23 @@
 CREATE TABLE IF NOT EXISTS users (
     id SERIAL PRIMARY KEY,
     username VARCHAR(50) UNIQUE NOT NULL,
-    email VARCHAR(100) UNIQUE NOT NULL,
+    email VARCHAR(100) UNIQUE,
     password_hash VARCHAR(255) NOT NULL,
+    role VARCHAR(20) NOT NULL DEFAULT 'player' CHECK (role IN ('admin', 'uploader', 'player')),
     created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
 );
 
+-- Invite codes for invite-only registration
+CREATE TABLE IF NOT EXISTS invites (
+    id SERIAL PRIMARY KEY,
+    code VARCHAR(32) UNIQUE NOT NULL,
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    used_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
 -- Emulators registry
 CREATE TABLE IF NOT EXISTS emulators (
     id VARCHAR(50) PRIMARY KEY,
@@ -31,7 +43,42 @@ CREATE TABLE IF NOT EXISTS games (
     added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     user_id INTEGER REFERENCES users(id),
     file_size BIGINT,
-    metadata JSONB
+    -- Unix seconds; with file_size decides whether a rescan has to re-read the file
+    file_mtime BIGINT,
+    -- Set when a scan no longer finds the file
+    missing_since TIMESTAMP,
+    metadata JSONB,
+    crc32 CHAR(8),
+    md5 CHAR(32),
+    sha1 CHAR(40)
+);
+
+-- Imported Logiqx DATs (No-Intro, Redump, ...)
+CREATE TABLE IF NOT EXISTS dat_files (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(200) NOT NULL UNIQUE,
+    description TEXT,
+    version VARCHAR(100),
+    file_name VARCHAR(500) NOT NULL,
+    entry_count INTEGER NOT NULL DEFAULT 0,
+    imported_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- One row per ROM listed in a DAT
+CREATE TABLE IF NOT EXISTS dat_entries (
+    id SERIAL PRIMARY KEY,
+    dat_id INTEGER NOT NULL REFERENCES dat_files(id) ON DELETE CASCADE,
+    game_name TEXT NOT NULL,
+    title TEXT NOT NULL,
+    region TEXT,
+    languages TEXT,
+    revision TEXT,
+    rom_name TEXT NOT NULL,
+    size BIGINT,
+    crc32 CHAR(8),
+    md5 CHAR(32),
+    sha1 CHAR(40),
+    status VARCHAR(20)
 );
 
 -- Save states
@@ -51,7 +98,8 @@ CREATE TABLE IF NOT EXISTS play_history (
     game_id INTEGER REFERENCES games(id) ON DELETE CASCADE,
     user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
     play_time_seconds INTEGER DEFAULT 0,
-    last_played TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    last_played TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    ended_at TIMESTAMP
 );
 
 -- Active sessions table for tracking connected users
@@ -64,12 +112,36 @@ CREATE TABLE IF NOT EXISTS active_sessions (
     UNIQUE(username, ip_address)
 );
 
+-- Upgrades for databases created before these columns existed. Everything in this
+-- file can be run again on an existing database to bring it up to date.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role VARCHAR(20) NOT NULL DEFAULT 'player' CHECK (role IN ('admin', 'uploader', 'player'));
+ALTER TABLE users ALTER COLUMN email DROP NOT NULL;
+ALTER TABLE games ADD COLUMN IF NOT EXISTS file_mtime BIGINT;
+ALTER TABLE games ADD COLUMN IF NOT EXISTS missing_since TIMESTAMP;
+ALTER TABLE games ADD COLUMN IF NOT EXISTS crc32 CHAR(8);
+ALTER TABLE games ADD COLUMN IF NOT EXISTS md5 CHAR(32);
+ALTER TABLE games ADD COLUMN IF NOT EXISTS sha1 CHAR(40);
+ALTER TABLE play_history ADD COLUMN IF NOT EXISTS ended_at TIMESTAMP;
+
+-- Accounts from before roles all start as players, so someone has to be able to manage them
+UPDATE users SET role = 'admin'
+WHERE id = (SELECT MIN(id) FROM users)
+  AND NOT EXISTS (SELECT 1 FROM users WHERE role = 'admin');
+
 -- Indexes
 CREATE INDEX IF NOT EXISTS idx_games_system ON games(system);
 CREATE INDEX IF NOT EXISTS idx_games_user ON games(user_id);
 CREATE INDEX IF NOT EXISTS idx_games_emulator ON games(emulator_id);
+CREATE INDEX IF NOT EXISTS idx_games_crc32 ON games(crc32);
+CREATE INDEX IF NOT EXISTS idx_games_md5 ON games(md5);
+CREATE INDEX IF NOT EXISTS idx_games_sha1 ON games(sha1);
+CREATE INDEX IF NOT EXISTS idx_dat_entries_dat ON dat_entries(dat_id);
+CREATE INDEX IF NOT EXISTS idx_dat_entries_crc32 ON dat_entries(crc32);
+CREATE INDEX IF NOT EXISTS idx_dat_entries_md5 ON dat_entries(md5);
+CREATE INDEX IF NOT EXISTS idx_dat_entries_sha1 ON dat_entries(sha1);
 CREATE INDEX IF NOT EXISTS idx_save_states_game_user ON save_states(game_id, user_id);
 CREATE INDEX IF NOT EXISTS idx_play_history_user ON play_history(user_id);
+CREATE INDEX IF NOT EXISTS idx_play_history_user_game ON play_history(user_id, game_id);
 CREATE INDEX IF NOT EXISTS idx_active_sessions_last_seen ON active_sessions(last_seen);
 
 -- Insert emulator registry data
@@ -87,11 +159,19 @@ INSERT INTO emulators (id, name, system, core, emulator_type, service_port, gith
 ('yuzu', 'yuzu', 'Nintendo Switch', 'yuzu', 'NativeService', 8088, 'https://github.com/yuzu-emu/yuzu', 'GPLv2', ARRAY['nsp', 'xci']),
 ('ryujinx', 'Ryujinx', 'Nintendo Switch', 'ryujinx', 'NativeService', 8089, 'https://github.com/Ryujinx/Ryujinx', 'MIT', ARRAY['nsp', 'xci']),
 ('duckstation', 'DuckStation', 'PlayStation 1', 'duckstation_libretro', 'RetroArchCore', 8081, 'https://github.com/stenzek/duckstation', 'GPLv3', ARRAY['cue', 'bin', 'iso', 'chd']),
-('pcsx2', 'PCSX2', 'PlayStation 2', 'pcsx2', 'NativeService', 8084, 'https://github.com/PCSX2/pcsx2', 'GPLv3', ARRAY['iso', 'bin']),
+('pcsx2', 'PCSX2', 'PlayStation 2', 'pcsx2', 'NativeService', 8084, 'https://github.com/PCSX2/pcsx2', 'GPLv3', ARRAY['iso', 'bin', 'chd']),
 ('rpcs3', 'RPCS3', 'PlayStation 3', 'rpcs3', 'NativeService', 8085, 'https://github.com/RPCS3/rpcs3', 'GPLv2', ARRAY['pkg', 'iso']),
 ('ppsspp', 'PPSSPP', 'PlayStation Portable', 'ppsspp_libretro', 'NativeService', 8086, 'https://github.com/hrydgard/ppsspp', 'GPLv2+', ARRAY['iso', 'cso']),
 ('vita3k', 'Vita3K', 'PlayStation Vita', 'vita3k', 'NativeService', 8091, 'https://github.com/Vita3K/Vita3K', 'GPLv2', ARRAY['vpk', 'pkg']),
 ('flycast', 'Flycast', 'Sega Dreamcast / Naomi / Atomiswave', 'flycast_libretro', 'RetroArchCore', 8081, 'https://github.com/flyinghead/flycast', 'GPLv2', ARRAY['cdi', 'gdi', 'chd']),
 ('bizhawk', 'BizHawk', 'Multi-System (TAS Tool)', 'bizhawk', 'NativeService', 8092, 'https://github.com/TASEmulators/BizHawk', 'GPLv2', ARRAY['nes', 'snes', 'n64', 'gb', 'gba'])
-ON CONFLICT (id) DO NOTHING;
+ON CONFLICT (id) DO UPDATE SET
+    name = EXCLUDED.name,
+    system = EXCLUDED.system,
+    core = EXCLUDED.core,
+    emulator_type = EXCLUDED.emulator_type,
+    service_port = EXCLUDED.service_port,
+    github_url = EXCLUDED.github_url,
+    license = EXCLUDED.license,
+    supported_formats = EXCLUDED.supported_formats;
 
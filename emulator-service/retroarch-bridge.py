@@ -8,11 +8,15 @@ import socketserver
 import urllib.parse
 import socket
 import json
+import os
 from typing import Optional
 
 RETROARCH_HOST = "localhost"
 RETROARCH_PORT = 8081
 
+# Backend as the browser reaches it; ROMs inside archives are fetched from it unpacked
+BACKEND_URL = os.environ.get("BACKEND_URL", "http://localhost:37291").rstrip('/')
+
 class RetroArchBridge(http.server.SimpleHTTPRequestHandler):
     def do_GET(self):
         parsed_path = urllib.parse.urlparse(self.path)
@@ -21,6 +25,8 @@ class RetroArchBridge(http.server.SimpleHTTPRequestHandler):
         if parsed_path.path == "/play":
             core = query_params.get('core', [None])[0]
             rom = query_params.get('rom', [None])[0]
+            # Set for a ROM inside a .zip/.7z, which `rom` then names
+            entry = query_params.get('entry', [None])[0]
             
             if not core or not rom:
                 self.send_error(400, "Missing core or rom parameter")
@@ -41,6 +47,13 @@ class RetroArchBridge(http.server.SimpleHTTPRequestHandler):
             else:
                 emulatorjs_rom_path = rom_path.lstrip('/')
             
+            # EmulatorJS can't pick one file out of an archive, so let the backend unpack it
+            if entry:
+                emulatorjs_rom_path = (
+                    f"{BACKEND_URL}/api/roms/archive-entry"
+                    f"?rom={urllib.parse.quote(emulatorjs_rom_path)}&entry={urllib.parse.quote(entry)}"
+                )
+            
             # Map RetroArch cores to EmulatorJS cores
             core_mapping = {
                 'fceumm_libretro': 'nes',
@@ -55,7 +68,7 @@ class RetroArchBridge(http.server.SimpleHTTPRequestHandler):
             
             # EmulatorJS URL format: /?rom=path/to/rom&core=corename
             # The ROM path should be relative to the /data/roms mount point
-            emulatorjs_url = f"http://localhost:8082/?rom={urllib.parse.quote(emulatorjs_rom_path)}&core={emulatorjs_core}"
+            emulatorjs_url = f"http://localhost:8082/?rom={urllib.parse.quote(emulatorjs_rom_path, safe='')}&core={emulatorjs_core}"
             
             # Create HTML page that can launch via EmulatorJS
             html = f"""<!DOCTYPE html>
@@ -2,11 +2,11 @@ import React, { useState, useEffect } from 'react';
 import GameLibrary from './components/GameLibrary';
 import LoginModal from './components/LoginModal';
 import ConnectedUsers from './components/ConnectedUsers';
-import { registerSession } from './services/api';
+import { registerSession, refreshTokens, UnauthorizedError } from './services/api';
 import './App.css';
 
 function App() {
-  const [token] = useState<string>('demo-token'); // In production, get from auth
+  const [token, setToken] = useState<string>('');
   const [selectedGameId, setSelectedGameId] = useState<number | null>(null);
   const [theme, setTheme] = useState<'dark' | 'light'>('dark');
   const [totalGames, setTotalGames] = useState<number>(0);
@@ -21,12 +21,23 @@ function App() {
     }
     
     const savedUsername = localStorage.getItem('username');
-    if (savedUsername) {
+    const savedToken = localStorage.getItem('accessToken');
+    if (savedUsername && savedToken) {
       setUsername(savedUsername);
+      setToken(savedToken);
       setShowLogin(false);
     }
   }, []);
 
+  const handleLogout = () => {
+    localStorage.removeItem('username');
+    localStorage.removeItem('accessToken');
+    localStorage.removeItem('refreshToken');
+    setUsername(null);
+    setToken('');
+    setShowLogin(true);
+  };
+
   // Apply theme to document
   useEffect(() => {
     document.documentElement.setAttribute('data-theme', theme);
@@ -35,31 +46,44 @@ function App() {
 
   // Register session periodically when logged in
   useEffect(() => {
-    if (!username) return;
+    if (!username || !token) return;
+
+    const keepAlive = () => {
+      registerSession(token).catch(async err => {
+        if (!(err instanceof UnauthorizedError)) {
+          console.error('Failed to register session:', err);
+          return;
+        }
+        // Access token expired, try to renew it with the refresh token
+        const refreshToken = localStorage.getItem('refreshToken');
+        try {
+          if (!refreshToken) throw err;
+          const tokens = await refreshTokens(refreshToken);
+          localStorage.setItem('accessToken', tokens.access_token);
+          localStorage.setItem('refreshToken', tokens.refresh_token);
+          setToken(tokens.access_token);
+        } catch {
+          handleLogout();
+        }
+      });
+    };
 
     // Register immediately
-    registerSession(username).catch(err => {
-      console.error('Failed to register session:', err);
-    });
+    keepAlive();
 
     // Then register every 30 seconds to keep session alive
-    const interval = setInterval(() => {
-      registerSession(username).catch(err => {
-        console.error('Failed to register session:', err);
-      });
-    }, 30000);
+    const interval = setInterval(keepAlive, 30000);
 
     return () => clearInterval(interval);
-  }, [username]);
+  }, [username, token]);
 
-  const handleLogin = (loggedInUsername: string) => {
+  const handleLogin = (loggedInUsername: string, accessToken: string, refreshToken: string) => {
     setUsername(loggedInUsername);
+    setToken(accessToken);
     setShowLogin(false);
     localStorage.setItem('username', loggedInUsername);
-    // Register session immediately after login
-    registerSession(loggedInUsername).catch(err => {
-      console.error('Failed to register session:', err);
-    });
+    localStorage.setItem('accessToken', accessToken);
+    localStorage.setItem('refreshToken', refreshToken);
   };
 
   const toggleTheme = () => {
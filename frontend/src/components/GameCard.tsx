@@ -1,10 +1,12 @@
 import React, { useState } from 'react';
+import { getEmulatorRequirements } from '../services/api';
 import './GameCard.css';
 
 interface Game {
   id: number;
   title: string;
   system: string;
+  emulator_id?: string;
   emulator: {
     name: string;
     github_url: string;
@@ -14,6 +16,7 @@ interface Game {
 
 interface GameCardProps {
   game: Game;
+  token?: string;
   onClick: () => void;
   viewMode?: 'grid' | 'list';
   animationDelay?: number;
@@ -34,6 +37,7 @@ const systemIcons: Record<string, string> = {
 
 const GameCard: React.FC<GameCardProps> = ({ 
   game, 
+  token,
   onClick, 
   viewMode = 'grid',
   animationDelay = 0 
@@ -51,6 +55,31 @@ const GameCard: React.FC<GameCardProps> = ({
       return;
     }
     
+    // Warn when the emulator's BIOS is missing, since the game would just show a black screen
+    if (token && game.emulator_id) {
+      try {
+        const requirements = await getEmulatorRequirements(token, game.emulator_id);
+        if (!requirements.ready) {
+          const problems = requirements.requirements
+            .filter(r => r.required && r.status !== 'present')
+            .map(r =>
+              `• ${r.description}: ${r.status === 'wrong_hash' ? 'found, but not a known good dump' : 'missing'}\n` +
+              `  (${r.files.map(f => f.file_name).join(', ')})`
+            );
+          const userConfirmed = window.confirm(
+            `⚠️ ${game.emulator.name} needs firmware that isn't in the bios/ directory:\n\n` +
+            `${problems.join('\n')}\n\n` +
+            `The game will probably not boot. Would you like to launch it anyway?`
+          );
+          if (!userConfirmed) {
+            return;
+          }
+        }
+      } catch (error) {
+        console.warn('Could not check BIOS requirements:', error);
+      }
+    }
+    
     // Extract port from URL to provide better error messages
     const urlMatch = game.launch_url.match(/localhost:(\d+)/);
     const port = urlMatch ? urlMatch[1] : 'unknown';
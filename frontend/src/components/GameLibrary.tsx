@@ -264,6 +264,7 @@ const GameLibrary: React.FC<GameLibraryProps> = ({
             <GameCard 
               key={game.id} 
               game={game} 
+              token={token}
               onClick={() => onSelectGame(game.id)}
               viewMode={viewMode}
               animationDelay={index * 0.05}
@@ -1,9 +1,9 @@
 import React, { useState, useEffect } from 'react';
-import { login, getPreviousUsernames, deleteAccount } from '../services/api';
+import { login, register, getPreviousUsernames, getRegistrationInfo, deleteAccount, RegistrationInfo } from '../services/api';
 import './LoginModal.css';
 
 interface LoginModalProps {
-  onLogin: (username: string) => void;
+  onLogin: (username: string, accessToken: string, refreshToken: string) => void;
 }
 
 const LoginModal: React.FC<LoginModalProps> = ({ onLogin }) => {
@@ -14,6 +14,10 @@ const LoginModal: React.FC<LoginModalProps> = ({ onLogin }) => {
   const [previousUsernames, setPreviousUsernames] = useState<string[]>([]);
   const [showDeleteConfirm, setShowDeleteConfirm] = useState(false);
   const [deleteLoading, setDeleteLoading] = useState(false);
+  const [isRegistering, setIsRegistering] = useState(false);
+  const [email, setEmail] = useState('');
+  const [inviteCode, setInviteCode] = useState('');
+  const [registrationInfo, setRegistrationInfo] = useState<RegistrationInfo | null>(null);
 
   // Load previous usernames on mount
   useEffect(() => {
@@ -26,6 +30,9 @@ const LoginModal: React.FC<LoginModalProps> = ({ onLogin }) => {
       }
     };
     loadPreviousUsernames();
+    getRegistrationInfo()
+      .then(setRegistrationInfo)
+      .catch(err => console.error('Failed to load registration info:', err));
   }, []);
 
   const handleUsernameClick = (selectedUsername: string) => {
@@ -57,9 +64,16 @@ const LoginModal: React.FC<LoginModalProps> = ({ onLogin }) => {
     }
 
     try {
-      const response = await login({ username: username.trim(), password });
-      if (response.success && response.username) {
-        onLogin(response.username);
+      const response = isRegistering
+        ? await register({
+            username: username.trim(),
+            password,
+            email: email.trim() || undefined,
+            invite_code: inviteCode.trim() || undefined,
+          })
+        : await login({ username: username.trim(), password });
+      if (response.success && response.username && response.access_token && response.refresh_token) {
+        onLogin(response.username, response.access_token, response.refresh_token);
       } else {
         setError(response.message || 'Login failed');
       }
@@ -80,10 +94,14 @@ const LoginModal: React.FC<LoginModalProps> = ({ onLogin }) => {
     setError('');
 
     try {
-      const response = await deleteAccount({ 
-        username: username.trim(), 
-        password 
-      });
+      // Deleting requires an authenticated session, so log in first
+      const auth = await login({ username: username.trim(), password });
+      if (!auth.success || !auth.access_token) {
+        setError(auth.message || 'Failed to delete account');
+        return;
+      }
+
+      const response = await deleteAccount(auth.access_token, { password });
       
       if (response.success) {
         // Remove username from previous usernames list
@@ -159,12 +177,51 @@ const LoginModal: React.FC<LoginModalProps> = ({ onLogin }) => {
               disabled={loading || deleteLoading}
             />
           </div>
+          {isRegistering && (
+            <div className="form-group">
+              <label htmlFor="email">Email (optional)</label>
+              <input
+                id="email"
+                type="email"
+                value={email}
+                onChange={(e) => setEmail(e.target.value)}
+                placeholder="you@example.com"
+                maxLength={100}
+                disabled={loading}
+              />
+            </div>
+          )}
+          {isRegistering && registrationInfo?.mode === 'invite' && (
+            <div className="form-group">
+              <label htmlFor="invite-code">Invite Code</label>
+              <input
+                id="invite-code"
+                type="text"
+                value={inviteCode}
+                onChange={(e) => setInviteCode(e.target.value)}
+                placeholder="Enter your invite code"
+                disabled={loading}
+              />
+            </div>
+          )}
           {error && <div className="error-message">{error}</div>}
           <div className="login-actions">
             <button type="submit" className="login-button" disabled={loading || deleteLoading}>
-            {loading ? 'Logging in...' : 'Login'}
+            {loading
+              ? (isRegistering ? 'Creating account...' : 'Logging in...')
+              : (isRegistering ? 'Create Account' : 'Login')}
           </button>
-            {username.trim() && (
+            {registrationInfo?.mode !== 'closed' && (
+              <button
+                type="button"
+                className="register-toggle-button"
+                onClick={() => { setIsRegistering(!isRegistering); setError(''); }}
+                disabled={loading || deleteLoading}
+              >
+                {isRegistering ? 'I already have an account' : 'Create an account'}
+              </button>
+            )}
+            {!isRegistering && username.trim() && (
               <button
                 type="button"
                 className="delete-account-button"
@@ -1,5 +1,5 @@
 import React, { useState, useEffect, useCallback } from 'react';
-import { uploadRom, scanRoms, getConsoles, Console, UploadResult, ScanResult } from '../services/api';
+import { uploadRom, UploadError, DuplicateChoice, scanRoms, waitForScan, getConsoles, Console, UploadResult, ScanResult, ScanStatus } from '../services/api';
 import './RomUpload.css';
 
 interface RomUploadProps {
@@ -15,6 +15,11 @@ interface UploadFile {
   message?: string;
   console?: string;
   title?: string;
+  // A file of the same name, or the same ROM, is already in the library
+  conflict?: boolean;
+  duplicate?: boolean;
+  replace?: boolean;
+  onDuplicate?: DuplicateChoice;
 }
 
 const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
@@ -24,6 +29,7 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
   const [isDragging, setIsDragging] = useState(false);
   const [isScanning, setIsScanning] = useState(false);
   const [scanResult, setScanResult] = useState<ScanResult | null>(null);
+  const [scanStatus, setScanStatus] = useState<ScanStatus | null>(null);
   const [showScanner, setShowScanner] = useState(false);
 
   useEffect(() => {
@@ -86,11 +92,11 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
 
   const handleUploadFile = async (fileToUpload: UploadFile) => {
     setFiles(prev => prev.map(f => 
-      f.id === fileToUpload.id ? { ...f, status: 'uploading', progress: 0 } : f
+      f.id === fileToUpload.id ? { ...f, status: 'uploading', progress: 0, conflict: false, duplicate: false } : f
     ));
 
     try {
-      const result = await uploadRom(
+      const results = await uploadRom(
         token,
         fileToUpload.file,
         fileToUpload.console,
@@ -99,12 +105,21 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
           setFiles(prev => prev.map(f =>
             f.id === fileToUpload.id ? { ...f, progress } : f
           ));
-        }
+        },
+        fileToUpload.replace,
+        fileToUpload.onDuplicate
       );
 
+      // An archive reports each ROM in it; list the ones that didn't make it
+      const added = results.filter(r => r.success);
+      const failed = results.filter(r => !r.success);
+      const message = results.length === 1
+        ? results[0].message
+        : [`Added ${added.length} of ${results.length} files`,
+           ...failed.map(r => `${r.file_name}: ${r.message}`)].join('\n');
       setFiles(prev => prev.map(f =>
         f.id === fileToUpload.id
-          ? { ...f, status: 'success', progress: 100, message: result.message }
+          ? { ...f, status: added.length > 0 ? 'success' : 'error', progress: 100, message }
           : f
       ));
 
@@ -116,6 +131,8 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
               ...f,
               status: 'error',
               message: error instanceof Error ? error.message : 'Upload failed',
+              conflict: error instanceof UploadError && error.status === 409,
+              duplicate: error instanceof UploadError && !!error.result?.duplicate_of,
             }
           : f
       ));
@@ -141,9 +158,11 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
   const handleScan = async () => {
     setIsScanning(true);
     setScanResult(null);
+    setScanStatus(null);
     
     try {
-      const result = await scanRoms(token);
+      const job = await scanRoms(token);
+      const result = await waitForScan(token, job.job_id, setScanStatus);
       setScanResult(result);
       onUploadComplete?.();
     } catch (error) {
@@ -152,13 +171,33 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
         total_found: 0,
         newly_added: 0,
         already_exists: 0,
+        hashed: 0,
+        identified: 0,
+        unmatched: [],
+        bad_dumps: [],
+        mislabelled: [],
+        changed: 0,
+        moved: 0,
+        restored: 0,
+        missing: [],
+        removed: 0,
+        unknown_directories: [],
         errors: [error instanceof Error ? error.message : 'Scan failed'],
       });
     } finally {
       setIsScanning(false);
+      setScanStatus(null);
     }
   };
 
+  const formatScanProgress = (status: ScanStatus) => {
+    const counts = status.phase === 'walking'
+      ? `${status.files_seen} files found`
+      : `${status.files_read} of ${status.files_to_read} files read, ${status.skipped} unchanged`;
+    const eta = status.eta_secs != null && !status.finished ? ` · about ${status.eta_secs}s left` : '';
+    return `${counts}${eta}`;
+  };
+
   const getStatusIcon = (status: UploadFile['status']) => {
     switch (status) {
       case 'success': return '✓';
@@ -232,11 +271,11 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
                   type="file"
                   multiple
                   onChange={handleFileSelect}
-                  accept=".nes,.smc,.sfc,.n64,.z64,.iso,.bin,.cue,.gba,.nds,.3ds,.nsp,.xci,.gcm,.wbfs,.cdi,.gdi,.chd,.pbp,.vpk,.pkg"
+                  accept=".nes,.smc,.sfc,.n64,.z64,.iso,.bin,.cue,.gba,.nds,.3ds,.nsp,.xci,.gcm,.wbfs,.cdi,.gdi,.chd,.pbp,.vpk,.pkg,.zip,.7z,.tar"
                 />
               </label>
               <p className="supported-formats">
-                Supports: NES, SNES, N64, GameCube, Wii, PS1, PS2, PS3, PSP, GBA, DS, 3DS, Switch, and more
+                Supports: NES, SNES, N64, GameCube, Wii, PS1, PS2, PS3, PSP, GBA, DS, 3DS, Switch, and more, or a zip, 7z or tar of them
               </p>
             </div>
           </div>
@@ -303,6 +342,26 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
                           Upload
                         </button>
                       )}
+                      {uploadFile.status === 'error' && uploadFile.conflict && (
+                        <>
+                          <button
+                            onClick={() => handleUploadFile(
+                              uploadFile.duplicate
+                                ? { ...uploadFile, onDuplicate: 'replace' }
+                                : { ...uploadFile, replace: true }
+                            )}
+                            className="upload-btn"
+                          >
+                            Replace
+                          </button>
+                          <button
+                            onClick={() => handleUploadFile({ ...uploadFile, onDuplicate: 'keep_both' })}
+                            className="upload-btn"
+                          >
+                            Keep Both
+                          </button>
+                        </>
+                      )}
                       {uploadFile.status !== 'uploading' && (
                         <button
                           onClick={() => removeFile(uploadFile.id)}
@@ -344,6 +403,10 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
             )}
           </button>
 
+          {isScanning && scanStatus && (
+            <p className="scan-progress">{formatScanProgress(scanStatus)}</p>
+          )}
+
           {scanResult && (
             <div className="scan-results">
               <h4>Scan Results</h4>
@@ -378,6 +441,17 @@ const RomUpload: React.FC<RomUploadProps> = ({ token, onUploadComplete }) => {
                   </ul>
                 </div>
               )}
+
+              {scanResult.unknown_directories.length > 0 && (
+                <div className="scan-errors">
+                  <h5>Skipped directories (no matching console, add an alias in scan.toml):</h5>
+                  <ul>
+                    {scanResult.unknown_directories.map(dir => (
+                      <li key={dir}>{dir}/</li>
+                    ))}
+                  </ul>
+                </div>
+              )}
             </div>
           )}
         </div>
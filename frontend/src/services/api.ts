@@ -31,6 +31,48 @@ export interface Game {
     github_url: string;
   };
   launch_url: string;
+  missing_since?: string | null;
+  metadata?: {
+    tags?: RomTags;
+    header?: RomHeader | null;
+    disc_platform?: string | null;
+    tracks?: string[];
+    discs?: string[];
+    archive?: { path: string; entry: string };
+  };
+}
+
+export interface RomHeader {
+  format: string;
+  system: string;
+  title?: string;
+  game_code?: string;
+  mapper?: string;
+  region?: string;
+  checksum_valid?: boolean;
+}
+
+export interface RomTags {
+  regions: string[];
+  languages: string[];
+  revision?: string;
+  verified: boolean;
+  bad_dump: boolean;
+  hack: boolean;
+  translation: boolean;
+}
+
+export interface GameFilters {
+  system?: string;
+  emulator?: string;
+  region?: string;
+  language?: string;
+  revision?: string;
+  verified?: boolean;
+  bad_dump?: boolean;
+  hack?: boolean;
+  translation?: boolean;
+  include_missing?: boolean;
 }
 
 export interface Emulator {
@@ -45,8 +87,15 @@ export interface Emulator {
   license: string;
 }
 
-export async function getGames(token: string): Promise<Game[]> {
-  const response = await fetch(`${API_URL}/api/games`, {
+export async function getGames(token: string, filters: GameFilters = {}): Promise<Game[]> {
+  const params = new URLSearchParams();
+  Object.entries(filters).forEach(([key, value]) => {
+    if (value !== undefined) {
+      params.append(key, String(value));
+    }
+  });
+  const query = params.toString();
+  const response = await fetch(`${API_URL}/api/games${query ? `?${query}` : ''}`, {
     headers: {
       'Authorization': `Bearer ${token}`,
     },
@@ -69,6 +118,36 @@ export async function getEmulators(token: string): Promise<Emulator[]> {
   return response.json();
 }
 
+export type BiosStatus = 'present' | 'missing' | 'wrong_hash';
+
+export interface EmulatorRequirements {
+  emulator_id: string;
+  ready: boolean;
+  requirements: {
+    description: string;
+    required: boolean;
+    status: BiosStatus;
+    files: {
+      file_name: string;
+      status: BiosStatus;
+      path?: string | null;
+      md5?: string | null;
+    }[];
+  }[];
+}
+
+export async function getEmulatorRequirements(token: string, emulatorId: string): Promise<EmulatorRequirements> {
+  const response = await fetch(`${API_URL}/api/emulators/${encodeURIComponent(emulatorId)}/requirements`, {
+    headers: {
+      'Authorization': `Bearer ${token}`,
+    },
+  });
+  if (!response.ok) {
+    throw new Error('Failed to fetch emulator requirements');
+  }
+  return response.json();
+}
+
 export async function getGame(token: string, id: number): Promise<Game> {
   const response = await fetch(`${API_URL}/api/games/${id}`, {
     headers: {
@@ -111,16 +190,41 @@ export interface ScanResult {
   total_found: number;
   newly_added: number;
   already_exists: number;
+  hashed: number;
+  identified: number;
+  unmatched: string[];
+  bad_dumps: string[];
+  mislabelled: string[];
+  changed: number;
+  moved: number;
+  restored: number;
+  missing: string[];
+  removed: number;
+  unknown_directories: string[];
   errors: string[];
 }
 
+export interface DuplicateGame {
+  game_id: number;
+  title: string;
+  file_path: string;
+  missing: boolean;
+}
+
 export interface UploadResult {
   success: boolean;
   message: string;
   game_id?: number;
   file_path?: string;
+  duplicate_of?: DuplicateGame | null;
+  // The file as uploaded, or "archive#entry" for a file unpacked from an archive
+  file_name?: string | null;
+  // HTTP status of a failed file: 409 for a clash, 413 when too large
+  status?: number | null;
 }
 
+export type DuplicateChoice = 'replace' | 'keep_both';
+
 export async function getConsoles(token: string): Promise<Console[]> {
   const response = await fetch(`${API_URL}/api/roms/consoles`, {
     headers: {
@@ -133,26 +237,128 @@ export async function getConsoles(token: string): Promise<Console[]> {
   return response.json();
 }
 
-export async function scanRoms(token: string): Promise<ScanResult> {
-  const response = await fetch(`${API_URL}/api/roms/scan`, {
+export interface ScanStatus {
+  job_id: string;
+  phase: 'walking' | 'reading' | 'saving' | 'identifying' | 'finished';
+  finished: boolean;
+  started_at: string;
+  elapsed_secs: number;
+  files_seen: number;
+  files_to_read: number;
+  files_read: number;
+  added: number;
+  skipped: number;
+  errors: number;
+  eta_secs?: number | null;
+  result?: ScanResult | null;
+}
+
+// Starts a background scan. If one is already running, its status is returned instead.
+export async function scanRoms(
+  token: string,
+  options: { rescan?: boolean; remove_missing?: boolean } = {}
+): Promise<ScanStatus> {
+  const params = new URLSearchParams();
+  if (options.rescan) params.set('rescan', 'true');
+  if (options.remove_missing) params.set('remove_missing', 'true');
+  const query = params.toString();
+  const response = await fetch(`${API_URL}/api/roms/scan${query ? `?${query}` : ''}`, {
     method: 'POST',
     headers: {
       'Authorization': `Bearer ${token}`,
     },
   });
-  if (!response.ok) {
+  if (!response.ok && response.status !== 409) {
     throw new Error('Failed to scan ROMs');
   }
   return response.json();
 }
 
+export async function getScanStatus(token: string, jobId: string): Promise<ScanStatus> {
+  const response = await fetch(`${API_URL}/api/roms/scan/${encodeURIComponent(jobId)}`, {
+    headers: {
+      'Authorization': `Bearer ${token}`,
+    },
+  });
+  if (!response.ok) {
+    throw new Error('Failed to fetch scan status');
+  }
+  return response.json();
+}
+
+// Polls a scan job until it finishes, reporting progress along the way
+export async function waitForScan(
+  token: string,
+  jobId: string,
+  onProgress?: (status: ScanStatus) => void,
+  intervalMs = 1000
+): Promise<ScanResult> {
+  for (;;) {
+    const status = await getScanStatus(token, jobId);
+    onProgress?.(status);
+    if (status.finished && status.result) {
+      return status.result;
+    }
+    await new Promise(resolve => setTimeout(resolve, intervalMs));
+  }
+}
+
+// Files above this size go through the resumable upload endpoint in chunks, which
+// survive dropped connections and stay under proxy body limits (100 MB on Cloudflare)
+const RESUMABLE_THRESHOLD = 64 * 1024 * 1024;
+const RESUMABLE_CHUNK_SIZE = 50 * 1024 * 1024;
+const RESUMABLE_RETRIES = 5;
+
+// Archives are unpacked by the server, which only the form upload does
+const isArchive = (file: File) => /\.(zip|7z|tar)$/i.test(file.name);
+
+// Carries the status, so callers can tell a name clash or duplicate (409) from other
+// failures; for a duplicate, the game it duplicates is in result.duplicate_of
+export class UploadError extends Error {
+  constructor(message: string, public status: number, public result?: UploadResult) {
+    super(message);
+  }
+}
+
+const parseResult = (body: string): UploadResult | undefined => {
+  try {
+    return body ? JSON.parse(body) : undefined;
+  } catch (e) {
+    return undefined;
+  }
+};
+
+const uploadError = (status: number, result?: UploadResult) => {
+  if (result?.duplicate_of) {
+    const game = result.duplicate_of;
+    const where = game.missing ? 'missing from' : 'already in';
+    return new UploadError(`Same ROM as "${game.title}", ${where} the library (${game.file_path})`, status, result);
+  }
+  if (status === 409) {
+    return new UploadError('A file with this name already exists', status);
+  }
+  if (status === 413) {
+    return new UploadError('File is larger than the upload limit for this system', status);
+  }
+  return new UploadError(`Upload failed with status ${status}`, status);
+};
+
+// Upload one file, resolving with a result for each ROM it held: one for a ROM, any
+// number for an archive. A single ROM that is refused rejects with an UploadError.
 export async function uploadRom(
   token: string,
   file: File,
   console?: string,
   title?: string,
-  onProgress?: (progress: number) => void
-): Promise<UploadResult> {
+  onProgress?: (progress: number) => void,
+  replace = false,
+  onDuplicate?: DuplicateChoice
+): Promise<UploadResult[]> {
+  if (file.size > RESUMABLE_THRESHOLD && !isArchive(file)) {
+    const result = await uploadRomResumable(token, file, console, title, onProgress, replace, onDuplicate);
+    return [result];
+  }
+
   const formData = new FormData();
   formData.append('file', file);
   if (console) {
@@ -161,6 +367,12 @@ export async function uploadRom(
   if (title) {
     formData.append('title', title);
   }
+  if (replace) {
+    formData.append('replace', 'true');
+  }
+  if (onDuplicate) {
+    formData.append('on_duplicate', onDuplicate);
+  }
 
   return new Promise((resolve, reject) => {
     const xhr = new XMLHttpRequest();
@@ -174,14 +386,21 @@ export async function uploadRom(
 
     xhr.addEventListener('load', () => {
       if (xhr.status >= 200 && xhr.status < 300) {
+        let results: UploadResult[];
         try {
-          const result = JSON.parse(xhr.responseText);
-          resolve(result);
+          results = JSON.parse(xhr.responseText);
         } catch (e) {
           reject(new Error('Invalid response from server'));
+          return;
+        }
+        const [first] = results;
+        if (results.length === 1 && !first.success) {
+          reject(uploadError(first.status ?? 400, first));
+        } else {
+          resolve(results);
         }
       } else {
-        reject(new Error(`Upload failed with status ${xhr.status}`));
+        reject(uploadError(xhr.status));
       }
     });
 
@@ -195,6 +414,144 @@ export async function uploadRom(
   });
 }
 
+// Upload-Metadata values are base64 encoded UTF-8
+const encodeMetadata = (value: string) => btoa(unescape(encodeURIComponent(value)));
+
+interface ChunkResponse {
+  status: number;
+  offset: number;
+  body: string;
+}
+
+function sendChunk(
+  url: string,
+  token: string,
+  chunk: Blob,
+  offset: number,
+  onLoaded: (loaded: number) => void
+): Promise<ChunkResponse> {
+  return new Promise((resolve, reject) => {
+    const xhr = new XMLHttpRequest();
+    xhr.upload.addEventListener('progress', (e) => onLoaded(e.loaded));
+    xhr.addEventListener('load', () => {
+      resolve({
+        status: xhr.status,
+        offset: Number(xhr.getResponseHeader('Upload-Offset') ?? offset),
+        body: xhr.responseText,
+      });
+    });
+    xhr.addEventListener('error', () => reject(new Error('Upload failed')));
+    xhr.open('PATCH', `${API_URL}${url}`);
+    xhr.setRequestHeader('Authorization', `Bearer ${token}`);
+    xhr.setRequestHeader('Tus-Resumable', '1.0.0');
+    xhr.setRequestHeader('Upload-Offset', String(offset));
+    xhr.setRequestHeader('Content-Type', 'application/offset+octet-stream');
+    xhr.send(chunk);
+  });
+}
+
+// Upload through the tus endpoint, picking up where an earlier attempt at the same
+// file left off. Interrupted chunks are retried from the offset the server reports.
+async function uploadRomResumable(
+  token: string,
+  file: File,
+  console?: string,
+  title?: string,
+  onProgress?: (progress: number) => void,
+  replace = false,
+  onDuplicate?: DuplicateChoice
+): Promise<UploadResult> {
+  const storageKey = `rom-upload:${file.name}:${file.size}:${file.lastModified}`;
+  const headers = { Authorization: `Bearer ${token}`, 'Tus-Resumable': '1.0.0' };
+
+  let url = localStorage.getItem(storageKey);
+  let offset = 0;
+  if (url) {
+    const response = await fetch(`${API_URL}${url}`, { method: 'HEAD', headers });
+    if (response.ok) {
+      offset = Number(response.headers.get('Upload-Offset') ?? 0);
+    } else {
+      url = null;
+    }
+  }
+
+  if (!url) {
+    const metadata = [`filename ${encodeMetadata(file.name)}`];
+    if (console) {
+      metadata.push(`console ${encodeMetadata(console)}`);
+    }
+    if (title) {
+      metadata.push(`title ${encodeMetadata(title)}`);
+    }
+    if (replace) {
+      metadata.push(`replace ${encodeMetadata('true')}`);
+    }
+    if (onDuplicate) {
+      metadata.push(`on_duplicate ${encodeMetadata(onDuplicate)}`);
+    }
+    const response = await fetch(`${API_URL}/api/roms/uploads`, {
+      method: 'POST',
+      headers: {
+        ...headers,
+        'Upload-Length': String(file.size),
+        'Upload-Metadata': metadata.join(','),
+      },
+    });
+    url = response.headers.get('Location');
+    if (!response.ok || !url) {
+      throw uploadError(response.status);
+    }
+    localStorage.setItem(storageKey, url);
+  }
+
+  let failures = 0;
+  while (true) {
+    const chunk = file.slice(offset, offset + RESUMABLE_CHUNK_SIZE);
+    const start = offset;
+    try {
+      const response = await sendChunk(url, token, chunk, offset, (loaded) => {
+        onProgress?.(((start + loaded) / file.size) * 100);
+      });
+      if (response.status === 200) {
+        localStorage.removeItem(storageKey);
+        return JSON.parse(response.body);
+      }
+      // Every byte is in, so this is how the import went
+      if (start >= file.size) {
+        localStorage.removeItem(storageKey);
+        throw uploadError(response.status, parseResult(response.body));
+      }
+      if (response.status === 204) {
+        offset = response.offset;
+        failures = 0;
+        continue;
+      }
+      if (response.status !== 409 && response.status !== 423 && response.status < 500) {
+        localStorage.removeItem(storageKey);
+        throw uploadError(response.status);
+      }
+    } catch (e) {
+      if (!(e instanceof Error) || e.message !== 'Upload failed') {
+        throw e;
+      }
+    }
+
+    // Connection dropped or the server is busy: wait, then ask where to carry on from
+    failures += 1;
+    if (failures > RESUMABLE_RETRIES) {
+      throw new Error('Upload interrupted, try again to resume');
+    }
+    await new Promise((resolve) => setTimeout(resolve, 1000 * 2 ** failures));
+    const response = await fetch(`${API_URL}${url}`, { method: 'HEAD', headers }).catch(() => null);
+    if (response?.ok) {
+      offset = Number(response.headers.get('Upload-Offset') ?? offset);
+    } else if (response?.status === 404) {
+      localStorage.removeItem(storageKey);
+      throw new Error('Upload expired, please start again');
+    }
+  }
+}
+
 export interface LoginRequest {
   username: string;
   password: string;
@@ -205,6 +562,17 @@ export interface LoginResponse {
   message: string;
   username: string | null;
   user_id?: number | null;
+  role?: 'admin' | 'uploader' | 'player' | null;
+  access_token?: string | null;
+  refresh_token?: string | null;
+  expires_in?: number | null;
+}
+
+export interface TokenPair {
+  access_token: string;
+  refresh_token: string;
+  token_type: string;
+  expires_in: number;
 }
 
 export interface ConnectedUser {
@@ -240,14 +608,68 @@ export async function login(credentials: LoginRequest): Promise<LoginResponse> {
   return data as LoginResponse;
 }
 
-export async function registerSession(username: string): Promise<void> {
-  const response = await fetch(`${API_URL}/api/sessions/register`, {
+export interface RegisterRequest {
+  username: string;
+  password: string;
+  email?: string;
+  invite_code?: string;
+}
+
+export interface RegistrationInfo {
+  mode: 'open' | 'invite' | 'closed';
+  username_min_length: number;
+  username_max_length: number;
+  password_min_length: number;
+}
+
+export async function register(details: RegisterRequest): Promise<LoginResponse> {
+  const response = await fetch(`${API_URL}/api/auth/register`, {
+    method: 'POST',
+    headers: {
+      'Content-Type': 'application/json',
+    },
+    body: JSON.stringify(details),
+  });
+  if (!response.ok) {
+    throw new Error(`Failed to register: ${response.status} ${response.statusText}`);
+  }
+  return response.json();
+}
+
+export async function getRegistrationInfo(): Promise<RegistrationInfo> {
+  const response = await fetch(`${API_URL}/api/auth/registration`);
+  if (!response.ok) {
+    throw new Error('Failed to fetch registration info');
+  }
+  return response.json();
+}
+
+export async function refreshTokens(refreshToken: string): Promise<TokenPair> {
+  const response = await fetch(`${API_URL}/api/auth/refresh`, {
     method: 'POST',
     headers: {
       'Content-Type': 'application/json',
     },
-    body: JSON.stringify({ username }),
+    body: JSON.stringify({ refresh_token: refreshToken }),
+  });
+  if (!response.ok) {
+    throw new Error('Failed to refresh session');
+  }
+  return response.json();
+}
+
+export class UnauthorizedError extends Error {}
+
+export async function registerSession(token: string): Promise<void> {
+  const response = await fetch(`${API_URL}/api/sessions/register`, {
+    method: 'POST',
+    headers: {
+      'Authorization': `Bearer ${token}`,
+    },
   });
+  if (response.status === 401) {
+    throw new UnauthorizedError('Session expired');
+  }
   if (!response.ok) {
     throw new Error('Failed to register session');
   }
@@ -274,7 +696,6 @@ export async function getPreviousUsernames(): Promise<PreviousUsernamesResponse>
 }
 
 export interface DeleteAccountRequest {
-  username: string;
   password: string;
 }
 
@@ -283,11 +704,12 @@ export interface DeleteAccountResponse {
   message: string;
 }
 
-export async function deleteAccount(credentials: DeleteAccountRequest): Promise<DeleteAccountResponse> {
+export async function deleteAccount(token: string, credentials: DeleteAccountRequest): Promise<DeleteAccountResponse> {
   const response = await fetch(`${API_URL}/api/auth/delete-account`, {
     method: 'POST',
     headers: {
       'Content-Type': 'application/json',
+      'Authorization': `Bearer ${token}`,
     },
     body: JSON.stringify(credentials),
   });